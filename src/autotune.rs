@@ -0,0 +1,468 @@
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{MotorsController, Result, PID};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Relay experiment on one joint
+pub struct RelayConfig {
+    /// Joint to tune
+    pub joint: usize,
+    /// Position around which the joint oscillates (in radians)
+    pub setpoint: f64,
+    /// Torque switched by the relay (in Nm)
+    pub amplitude: f64,
+    /// Constant torque added to the relay (e.g. to compensate the gravity, in Nm)
+    pub bias: f64,
+    /// Hysteresis of the relay (in radians), smaller than the oscillation amplitude
+    pub hysteresis: f64,
+    /// Number of oscillations measured, after a first one used to settle
+    pub cycles: usize,
+}
+
+impl RelayConfig {
+    /// Check the experiment for a controller of `n` joints
+    pub fn validate(&self, n: usize) -> std::result::Result<(), InvalidRelayConfigError> {
+        if self.joint >= n {
+            return Err(InvalidRelayConfigError(format!(
+                "joint {} out of {n}",
+                self.joint
+            )));
+        }
+        if !(self.amplitude.is_finite() && self.amplitude > 0.0) {
+            return Err(InvalidRelayConfigError(format!(
+                "amplitude = {}",
+                self.amplitude
+            )));
+        }
+        if !(self.hysteresis.is_finite() && self.hysteresis >= 0.0) {
+            return Err(InvalidRelayConfigError(format!(
+                "hysteresis = {}",
+                self.hysteresis
+            )));
+        }
+        if !self.setpoint.is_finite() || !self.bias.is_finite() {
+            return Err(InvalidRelayConfigError(format!(
+                "setpoint = {}, bias = {}",
+                self.setpoint, self.bias
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Tuning rule computing PID gains from the ultimate gain and period
+pub enum TuningRule {
+    /// Classic Ziegler-Nichols PID
+    ZieglerNichols,
+    /// Ziegler-Nichols PI (no derivative)
+    ZieglerNicholsPI,
+    /// Tyreus-Luyben, less aggressive and more robust
+    TyreusLuyben,
+    /// Pessen integral rule, fast disturbance rejection
+    PessenIntegral,
+    /// Ziegler-Nichols variant with some overshoot
+    SomeOvershoot,
+    /// Ziegler-Nichols variant without overshoot
+    NoOvershoot,
+}
+
+impl TuningRule {
+    /// All the tuning rules
+    pub const ALL: [TuningRule; 6] = [
+        TuningRule::ZieglerNichols,
+        TuningRule::ZieglerNicholsPI,
+        TuningRule::TyreusLuyben,
+        TuningRule::PessenIntegral,
+        TuningRule::SomeOvershoot,
+        TuningRule::NoOvershoot,
+    ];
+
+    /// PID gains for an ultimate gain and period (in s)
+    pub fn gains(&self, ultimate_gain: f64, ultimate_period: f64) -> PID {
+        let (ku, tu) = (ultimate_gain, ultimate_period);
+        // Proportional gain, integral time and derivative time
+        let (kp, ti, td) = match self {
+            TuningRule::ZieglerNichols => (0.6 * ku, tu / 2.0, tu / 8.0),
+            TuningRule::ZieglerNicholsPI => (0.45 * ku, tu / 1.2, 0.0),
+            TuningRule::TyreusLuyben => (ku / 2.2, 2.2 * tu, tu / 6.3),
+            TuningRule::PessenIntegral => (0.7 * ku, 0.4 * tu, 0.15 * tu),
+            TuningRule::SomeOvershoot => (ku / 3.0, tu / 2.0, tu / 3.0),
+            TuningRule::NoOvershoot => (0.2 * ku, tu / 2.0, tu / 3.0),
+        };
+        PID {
+            p: kp,
+            i: kp / ti,
+            d: kp * td,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Ultimate gain and period identified by a relay experiment
+pub struct UltimateParameters {
+    /// Ultimate gain (in Nm/rad)
+    pub gain: f64,
+    /// Ultimate period (in s)
+    pub period: f64,
+    /// Measured oscillation amplitude (in radians)
+    pub amplitude: f64,
+}
+
+impl UltimateParameters {
+    /// PID gains proposed by a tuning rule (from position error in radians to torque in Nm)
+    pub fn gains(&self, rule: TuningRule) -> PID {
+        rule.gains(self.gain, self.period)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// State of an auto-tuning experiment
+pub enum AutoTuneState {
+    /// Oscillating under the relay
+    Running,
+    /// Ultimate parameters identified, the relay is stopped
+    Done,
+}
+
+#[derive(Debug)]
+pub struct AutoTuneTimeoutError(pub usize);
+impl std::fmt::Display for AutoTuneTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cycles = &self.0;
+        write!(f, "(auto-tuning timed out after {cycles} oscillations)")
+    }
+}
+impl std::error::Error for AutoTuneTimeoutError {}
+
+#[derive(Debug)]
+pub struct InvalidRelayConfigError(pub String);
+impl std::fmt::Display for InvalidRelayConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid relay experiment: {reason})")
+    }
+}
+impl std::error::Error for InvalidRelayConfigError {}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Relay (Åström-Hägglund) auto-tuning of one joint in torque
+///
+/// The joint is driven by a torque switching between `bias ± amplitude`
+/// depending on the side of the setpoint it is on, which makes it oscillate
+/// around the setpoint at its ultimate period. The ultimate gain is deduced
+/// from the oscillation amplitude. The target torques must be applied by the
+/// motors (e.g. in torque control mode), only the tuned joint is written.
+///
+/// Only the relay experiment is implemented, there is no step-response tuning.
+/// The experiment fails with an [InvalidRelayConfigError] when its config is
+/// invalid, or when the joint oscillates within the relay hysteresis.
+pub struct RelayAutoTuner {
+    config: RelayConfig,
+
+    time: f64,
+    high: Option<bool>,
+    cycle_start: Option<f64>,
+    extrema: (f64, f64),
+    periods: Vec<f64>,
+    amplitudes: Vec<f64>,
+    result: Option<UltimateParameters>,
+}
+
+impl RelayAutoTuner {
+    pub fn new(config: RelayConfig) -> Self {
+        Self {
+            config,
+
+            time: 0.0,
+            high: None,
+            cycle_start: None,
+            extrema: (f64::INFINITY, f64::NEG_INFINITY),
+            periods: vec![],
+            amplitudes: vec![],
+            result: None,
+        }
+    }
+
+    /// Current state of the experiment
+    pub fn state(&self) -> AutoTuneState {
+        match self.result {
+            Some(_) => AutoTuneState::Done,
+            None => AutoTuneState::Running,
+        }
+    }
+
+    /// Identified ultimate parameters, once the experiment is done
+    pub fn result(&self) -> Option<UltimateParameters> {
+        self.result
+    }
+
+    /// Number of oscillations measured so far
+    pub fn cycles(&self) -> usize {
+        self.periods.len()
+    }
+
+    /// Read the joint position and switch the relay, `dt` seconds after the previous update
+    pub fn update<const N: usize>(
+        &mut self,
+        controller: &mut dyn MotorsController<N>,
+        dt: f64,
+    ) -> Result<AutoTuneState> {
+        if self.state() == AutoTuneState::Done {
+            return Ok(AutoTuneState::Done);
+        }
+        let config = self.config;
+        config.validate(N)?;
+        self.time += dt;
+
+        let position = controller.get_current_position()?[config.joint];
+        self.extrema = (self.extrema.0.min(position), self.extrema.1.max(position));
+
+        let error = config.setpoint - position;
+        let high = match self.high {
+            _ if error > config.hysteresis => true,
+            _ if error < -config.hysteresis => false,
+            Some(high) => high,
+            None => error >= 0.0,
+        };
+
+        if high && self.high == Some(false) {
+            // A new oscillation starts when the relay switches up
+            if let Some(start) = self.cycle_start {
+                self.periods.push(self.time - start);
+                self.amplitudes
+                    .push((self.extrema.1 - self.extrema.0) / 2.0);
+                log::debug!(target: "autotune::relay", "oscillation period: {} amplitude: {}", self.time - start, (self.extrema.1 - self.extrema.0) / 2.0);
+            }
+            self.cycle_start = Some(self.time);
+            self.extrema = (position, position);
+        }
+        self.high = Some(high);
+
+        if self.periods.len() > config.cycles.max(1) {
+            self.finish(controller)?;
+            return Ok(AutoTuneState::Done);
+        }
+
+        let relay = if high {
+            config.amplitude
+        } else {
+            -config.amplitude
+        };
+        self.send_torque(controller, config.bias + relay)?;
+        Ok(AutoTuneState::Running)
+    }
+
+    /// Stop the relay, leaving the bias torque
+    pub fn abort<const N: usize>(
+        &mut self,
+        controller: &mut dyn MotorsController<N>,
+    ) -> Result<()> {
+        log::warn!(target: "autotune::relay", "auto-tuning aborted after {} oscillations", self.cycles());
+        self.send_torque(controller, self.config.bias)
+    }
+
+    /// Run the experiment every `period` until it is done (blocking)
+    ///
+    /// The experiment is aborted after `timeout`.
+    pub fn run<const N: usize>(
+        &mut self,
+        controller: &mut dyn MotorsController<N>,
+        period: Duration,
+        timeout: Duration,
+    ) -> Result<UltimateParameters> {
+        let start = Instant::now();
+        let mut next = start;
+
+        loop {
+            if let (AutoTuneState::Done, Some(result)) =
+                (self.update(controller, period.as_secs_f64())?, self.result)
+            {
+                return Ok(result);
+            }
+            if start.elapsed() > timeout {
+                self.abort(controller)?;
+                return Err(Box::new(AutoTuneTimeoutError(self.cycles())));
+            }
+
+            next += period;
+            if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+
+    fn finish<const N: usize>(&mut self, controller: &mut dyn MotorsController<N>) -> Result<()> {
+        // The first oscillation is discarded, the joint is still settling
+        let periods = &self.periods[1..];
+        let amplitudes = &self.amplitudes[1..];
+        let period = periods.iter().sum::<f64>() / periods.len() as f64;
+        let amplitude = amplitudes.iter().sum::<f64>() / amplitudes.len() as f64;
+
+        let hysteresis = self.config.hysteresis;
+        if hysteresis >= amplitude {
+            self.send_torque(controller, self.config.bias)?;
+            return Err(Box::new(InvalidRelayConfigError(format!(
+                "hysteresis {hysteresis} not smaller than the oscillation amplitude {amplitude}"
+            ))));
+        }
+        let gain =
+            4.0 * self.config.amplitude / (PI * (amplitude.powi(2) - hysteresis.powi(2)).sqrt());
+
+        let result = UltimateParameters {
+            gain,
+            period,
+            amplitude,
+        };
+        log::info!(target: "autotune::relay", "identified {:?}", result);
+        self.result = Some(result);
+
+        self.send_torque(controller, self.config.bias)
+    }
+
+    fn send_torque<const N: usize>(
+        &self,
+        controller: &mut dyn MotorsController<N>,
+        torque: f64,
+    ) -> Result<()> {
+        let mut target = [None; N];
+        target[self.config.joint] = Some(torque);
+        controller.set_target_torque_partial(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoTuneState, InvalidRelayConfigError, RelayAutoTuner, RelayConfig, TuningRule};
+    use crate::test_utils::enabled_motors;
+    use crate::{FakeDynamics, FakeMotorsController, MotorsController, PidController};
+
+    const DT: f64 = 0.001;
+
+    /// Enabled motors with dynamics, commanded in torque
+    fn tuned_motors() -> FakeMotorsController<2> {
+        let mut motors = enabled_motors([0.0; 2]).with_dynamics(
+            [Some(FakeDynamics {
+                inertia: 0.01,
+                damping: 0.05,
+                friction: 0.01,
+            }); 2],
+        );
+        motors.set_target_torque([0.0; 2]).unwrap();
+        motors
+    }
+
+    fn config() -> RelayConfig {
+        RelayConfig {
+            joint: 0,
+            setpoint: 0.2,
+            amplitude: 0.5,
+            bias: 0.0,
+            hysteresis: 0.005,
+            cycles: 4,
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let pid = TuningRule::ZieglerNichols.gains(10.0, 0.5);
+        assert!((pid.p - 6.0).abs() < 1e-9);
+        assert!((pid.i - 24.0).abs() < 1e-9);
+        assert!((pid.d - 0.375).abs() < 1e-9);
+
+        assert_eq!(TuningRule::ZieglerNicholsPI.gains(10.0, 0.5).d, 0.0);
+        for rule in TuningRule::ALL {
+            let pid = rule.gains(10.0, 0.5);
+            assert!(pid.p > 0.0 && pid.p <= 7.0 && pid.i > 0.0 && pid.d >= 0.0);
+        }
+    }
+
+    #[test]
+    fn relay_on_fake() {
+        let mut motors = tuned_motors();
+        let mut tuner = RelayAutoTuner::new(config());
+
+        for _ in 0..20000 {
+            if tuner.update(&mut motors, DT).unwrap() == AutoTuneState::Done {
+                break;
+            }
+            motors.fake_io().step(DT);
+        }
+        assert_eq!(tuner.state(), AutoTuneState::Done);
+        assert_eq!(motors.get_target_torque().unwrap(), [0.0, 0.0]);
+        assert_eq!(motors.get_current_position().unwrap()[1], 0.0);
+
+        let ultimate = tuner.result().unwrap();
+        assert!(ultimate.gain > 0.0 && ultimate.period > 0.0);
+
+        // The proposed gains control the joint in torque
+        let step_response = |rule| {
+            let pid = PidController::new(ultimate.gains(rule));
+            let mut motors = tuned_motors().with_pid_controllers([Some(pid), None]);
+            let mut max = f64::MIN;
+            for _ in 0..5000 {
+                motors.update_pid_controllers([0.5, 0.0], DT).unwrap();
+                motors.fake_io().step(DT);
+                max = max.max(motors.get_current_position().unwrap()[0]);
+            }
+            let position = motors.get_current_position().unwrap()[0];
+            assert!((position - 0.5).abs() < 1e-2, "{rule:?}: {position}");
+            max - 0.5
+        };
+        let overshoot = step_response(TuningRule::ZieglerNichols);
+        assert!(step_response(TuningRule::TyreusLuyben) < overshoot);
+        step_response(TuningRule::PessenIntegral);
+        step_response(TuningRule::SomeOvershoot);
+        step_response(TuningRule::NoOvershoot);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut motors = tuned_motors();
+        let mut tuner = RelayAutoTuner::new(RelayConfig {
+            joint: 1,
+            bias: 0.1,
+            ..config()
+        });
+
+        // The fake is not stepped, the joint never oscillates
+        let res = tuner.run(
+            &mut motors,
+            std::time::Duration::from_millis(1),
+            std::time::Duration::from_millis(5),
+        );
+        assert!(res.is_err());
+        assert_eq!(tuner.state(), AutoTuneState::Running);
+        assert_eq!(motors.get_target_torque().unwrap(), [0.0, 0.1]);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let mut motors = tuned_motors();
+        let invalid = [
+            RelayConfig {
+                joint: 2,
+                ..config()
+            },
+            RelayConfig {
+                amplitude: 0.0,
+                ..config()
+            },
+            RelayConfig {
+                hysteresis: -0.1,
+                ..config()
+            },
+        ];
+        for config in invalid {
+            let error = RelayAutoTuner::new(config)
+                .update(&mut motors, DT)
+                .unwrap_err();
+            assert!(error.is::<InvalidRelayConfigError>(), "{config:?}");
+        }
+
+        assert_eq!(motors.get_target_torque().unwrap(), [0.0, 0.0]);
+    }
+}
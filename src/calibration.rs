@@ -0,0 +1,464 @@
+use std::time::{Duration, Instant};
+
+use itertools::izip;
+use serde::{Deserialize, Serialize};
+
+use crate::motors_controller::offset_scales;
+use crate::{MotorsController, Result};
+
+#[derive(Debug)]
+pub struct UncalibratedJointsError(pub Vec<usize>);
+impl std::fmt::Display for UncalibratedJointsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let joints = &self.0;
+        write!(
+            f,
+            "(offsets ignored by joints {joints:?}, calibrate their transmission instead)"
+        )
+    }
+}
+impl std::error::Error for UncalibratedJointsError {}
+
+/// Refuse to calibrate joints whose offset is replaced by a lookup table or a coupled transmission
+fn check_offsets_used<const N: usize>(
+    controller: &dyn MotorsController<N>,
+    joints: [bool; N],
+) -> Result<()> {
+    let tables = controller.lookup_tables();
+    let ignored: Vec<usize> = (0..N)
+        .filter(|&i| joints[i])
+        .filter(|&i| {
+            controller.transmission().is_some() || tables.is_some_and(|tables| tables[i].is_some())
+        })
+        .collect();
+    if ignored.is_empty() {
+        return Ok(());
+    }
+    Err(Box::new(UncalibratedJointsError(ignored)))
+}
+
+/// Compute and set the offsets making the current pose the zero of the selected joints
+///
+/// The other joints keep their offsets. The new offsets of all joints are returned.
+/// Joints with a lookup table or a coupled transmission ignore their offset, so
+/// calibrating them is refused with an [UncalibratedJointsError].
+pub fn calibrate_zero<const N: usize>(
+    controller: &mut dyn MotorsController<N>,
+    joints: [bool; N],
+) -> Result<[Option<f64>; N]> {
+    check_offsets_used(controller, joints)?;
+    let position = controller.get_current_position()?;
+    let mut offsets = controller.offsets();
+    let scales = offset_scales(controller);
+
+    for i in 0..N {
+        if joints[i] {
+            offsets[i] = Some(offsets[i].unwrap_or(0.0) + position[i] * scales[i]);
+        }
+    }
+    log::info!(target: "calibration::calibrate_zero", "new offsets: {:?}", offsets);
+
+    controller.set_offsets(offsets)?;
+    Ok(offsets)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Homing against a hard stop for one joint
+pub struct HomingConfig {
+    /// Velocity of the search (in rad/s, as sent by `set_target_velocity`), its sign gives the direction of the stop
+    pub velocity: f64,
+    /// Torque magnitude detecting the hard stop (in Nm, as read by `get_current_torque`)
+    ///
+    /// Only a torque pushing toward the stop while the joint is stalled counts.
+    pub torque_threshold: f64,
+    /// Known joint position of the hard stop (in radians)
+    pub stop_position: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// State of a homing routine
+pub enum HomingState {
+    /// Moving toward the hard stops
+    Searching,
+    /// All hard stops found and offsets set
+    Done,
+}
+
+/// Fraction of the search velocity below which a joint is considered stalled
+const STALL_VELOCITY_RATIO: f64 = 0.1;
+
+#[derive(Debug)]
+pub struct HomingTimeoutError(pub Vec<usize>);
+impl std::fmt::Display for HomingTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let joints = &self.0;
+        write!(f, "(homing timed out for joints {joints:?})")
+    }
+}
+impl std::error::Error for HomingTimeoutError {}
+
+#[derive(Debug)]
+/// Homing routine moving joints slowly in velocity mode until they hit a hard stop
+///
+/// Once a joint pushes against its stop (torque above the threshold in the
+/// search direction, while its velocity dropped below a tenth of the search
+/// velocity), it is stopped and its offset is computed so that its position matches the known
+/// stop position. When all joints are homed, they hold their position, the
+/// previous control mode is restored and the offsets are written into the controller.
+///
+/// Like [calibrate_zero], homing joints with a lookup table or a coupled
+/// transmission is refused before any motion.
+pub struct Homing<const N: usize> {
+    configs: [Option<HomingConfig>; N],
+    velocity_mode: Option<u8>,
+
+    previous_mode: Option<[u8; N]>,
+    stops: [Option<f64>; N],
+    offsets: Option<[Option<f64>; N]>,
+    started: bool,
+}
+
+impl<const N: usize> Homing<N> {
+    pub fn new(configs: [Option<HomingConfig>; N]) -> Self {
+        Self {
+            configs,
+            velocity_mode: None,
+
+            previous_mode: None,
+            stops: [None; N],
+            offsets: None,
+            started: false,
+        }
+    }
+
+    /// Control mode to use during the search (the previous one is restored afterwards)
+    pub fn with_velocity_mode(mut self, mode: u8) -> Self {
+        self.velocity_mode = Some(mode);
+        self
+    }
+
+    /// Current state of the homing
+    pub fn state(&self) -> HomingState {
+        match self.offsets {
+            Some(_) => HomingState::Done,
+            None => HomingState::Searching,
+        }
+    }
+
+    /// New offsets of all joints, once the homing is done
+    pub fn offsets(&self) -> Option<[Option<f64>; N]> {
+        self.offsets
+    }
+
+    /// Check the torques and stop the joints that reached their hard stop (to be called every period)
+    pub fn update(&mut self, controller: &mut dyn MotorsController<N>) -> Result<HomingState> {
+        if self.state() == HomingState::Done {
+            return Ok(HomingState::Done);
+        }
+        if !self.started {
+            self.start(controller)?;
+            return Ok(HomingState::Searching);
+        }
+
+        let torque = controller.get_current_torque()?;
+        let position = controller.get_current_position()?;
+        // Directions are compared on the motor side, where the search velocity is sent
+        let raw_torque = controller.io().get_current_torque()?;
+        let raw_velocity = controller.io().get_current_velocity()?;
+        let raw_target = controller.io().get_target_velocity()?;
+
+        let mut changed = false;
+        for i in 0..N {
+            if let Some(config) = self.configs[i] {
+                let pushing = torque[i].abs() >= config.torque_threshold
+                    && raw_torque[i] * raw_target[i] > 0.0;
+                let stalled = raw_velocity[i].abs() <= STALL_VELOCITY_RATIO * raw_target[i].abs();
+                if self.stops[i].is_none() && pushing && stalled {
+                    log::info!(target: "calibration::homing", "joint {} reached its hard stop at {}", i, position[i]);
+                    self.stops[i] = Some(position[i]);
+                    changed = true;
+                }
+            }
+        }
+
+        if self.pending().is_empty() {
+            self.finish(controller)?;
+        } else if changed {
+            self.send_velocities(controller)?;
+        }
+
+        Ok(self.state())
+    }
+
+    /// Stop the search and restore the previous control mode
+    pub fn abort(&mut self, controller: &mut dyn MotorsController<N>) -> Result<()> {
+        log::warn!(target: "calibration::homing", "homing aborted, joints {:?} not homed", self.pending());
+        self.stop_all(controller)
+    }
+
+    /// Run the homing every `period` until it is done (blocking)
+    ///
+    /// The homing is aborted after `timeout`. The new offsets are returned.
+    pub fn run(
+        &mut self,
+        controller: &mut dyn MotorsController<N>,
+        period: Duration,
+        timeout: Duration,
+    ) -> Result<[Option<f64>; N]> {
+        let start = Instant::now();
+
+        while self.update(controller)? == HomingState::Searching {
+            if start.elapsed() > timeout {
+                self.abort(controller)?;
+                return Err(Box::new(HomingTimeoutError(self.pending())));
+            }
+            std::thread::sleep(period);
+        }
+        Ok(self.offsets.unwrap_or(controller.offsets()))
+    }
+
+    fn pending(&self) -> Vec<usize> {
+        (0..N)
+            .filter(|&i| self.configs[i].is_some() && self.stops[i].is_none())
+            .collect()
+    }
+
+    fn start(&mut self, controller: &mut dyn MotorsController<N>) -> Result<()> {
+        check_offsets_used(controller, self.configs.map(|config| config.is_some()))?;
+        log::info!(target: "calibration::homing", "start homing {:?}", self.configs);
+
+        if let Some(velocity_mode) = self.velocity_mode {
+            let previous_mode = controller.get_control_mode()?;
+            let mut mode = previous_mode;
+            for (m, config) in mode.iter_mut().zip(self.configs) {
+                if config.is_some() {
+                    *m = velocity_mode;
+                }
+            }
+            controller.set_control_mode(mode)?;
+            self.previous_mode = Some(previous_mode);
+        }
+
+        self.started = true;
+        self.send_velocities(controller)
+    }
+
+    fn send_velocities(&self, controller: &mut dyn MotorsController<N>) -> Result<()> {
+        let mut velocity = controller.get_target_velocity()?;
+        for (v, (config, stop)) in velocity.iter_mut().zip(self.configs.iter().zip(self.stops)) {
+            if let Some(config) = config {
+                *v = match stop {
+                    Some(_) => 0.0,
+                    None => config.velocity,
+                };
+            }
+        }
+        controller.set_target_velocity(velocity)
+    }
+
+    fn finish(&mut self, controller: &mut dyn MotorsController<N>) -> Result<()> {
+        self.stop_all(controller)?;
+
+        let mut offsets = controller.offsets();
+        let scales = offset_scales(controller);
+        for (offset, (config, stop), scale) in
+            izip!(&mut offsets, self.configs.iter().zip(self.stops), scales)
+        {
+            if let (Some(config), Some(stop)) = (config, stop) {
+                *offset = Some(offset.unwrap_or(0.0) + (stop - config.stop_position) * scale);
+            }
+        }
+        log::info!(target: "calibration::homing", "homing done, new offsets: {:?}", offsets);
+
+        controller.set_offsets(offsets)?;
+        self.offsets = Some(offsets);
+        Ok(())
+    }
+
+    /// Stop the homed joints, hold their position and restore the previous control mode
+    fn stop_all(&mut self, controller: &mut dyn MotorsController<N>) -> Result<()> {
+        let mut velocity = controller.get_target_velocity()?;
+        let current_position = controller.io().get_current_position()?;
+        let mut target_position = controller.io().get_target_position()?;
+
+        for i in 0..N {
+            if self.configs[i].is_some() {
+                velocity[i] = 0.0;
+                target_position[i] = current_position[i];
+            }
+        }
+        controller.set_target_velocity(velocity)?;
+        controller.io().set_target_position(target_position)?;
+
+        if let Some(previous_mode) = self.previous_mode.take() {
+            controller.set_control_mode(previous_mode)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{calibrate_zero, Homing, HomingConfig, HomingState, UncalibratedJointsError};
+    use crate::{
+        FakeMotorsController, LookupTable, MotorsConfig, MotorsController, OffsetSpace,
+        Transmission,
+    };
+
+    #[test]
+    fn zero_current_pose() {
+        let mut motors = FakeMotorsController::<3>::new()
+            .with_offsets([Some(0.1), Some(0.2), None])
+            .with_reduction([Some(2.0), None, None]);
+        motors.fake_io().set_current_position([1.0, 0.5, -0.5]);
+
+        let offsets = calibrate_zero(&mut motors, [true, false, true]).unwrap();
+        assert_eq!(offsets, [Some(0.5), Some(0.2), Some(-0.5)]);
+        assert_eq!(motors.offsets(), offsets);
+        assert_eq!(motors.get_current_position().unwrap(), [0.0, 0.3, 0.0]);
+    }
+
+    #[test]
+    fn zero_motor_space() {
+        let mut motors = FakeMotorsController::<2>::new()
+            .with_offsets([Some(0.25), None])
+            .with_reduction([Some(2.0), None])
+            .with_inverted([true, false])
+            .with_offset_space(OffsetSpace::Motor);
+        motors.fake_io().set_current_position([1.0, 0.5]);
+        assert_eq!(motors.get_current_position().unwrap(), [-0.375, 0.5]);
+
+        // Motor space offsets of the zero pose are the raw motor positions
+        let offsets = calibrate_zero(&mut motors, [true; 2]).unwrap();
+        assert_eq!(offsets, [Some(1.0), Some(0.5)]);
+        assert_eq!(motors.get_current_position().unwrap(), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn offsets_ignored() {
+        let table = LookupTable::new(vec![[0.0, 0.0], [1.0, 2.0]]).unwrap();
+        let mut motors = FakeMotorsController::<2>::new().with_lookup_tables([Some(table), None]);
+        motors.fake_io().set_current_position([1.0, 0.5]);
+
+        let error = calibrate_zero(&mut motors, [true; 2]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<UncalibratedJointsError>().unwrap().0,
+            vec![0]
+        );
+        assert_eq!(motors.offsets(), [None; 2]);
+        calibrate_zero(&mut motors, [false, true]).unwrap();
+
+        let mut motors = FakeMotorsController::<2>::new()
+            .with_transmission(Transmission::from_scalar([None; 2], [Some(2.0); 2]).unwrap());
+        motors.set_torque([true; 2]).unwrap();
+        let mut homing = Homing::new([
+            Some(HomingConfig {
+                velocity: 0.5,
+                torque_threshold: 0.5,
+                stop_position: 1.0,
+            }),
+            None,
+        ]);
+        assert!(homing.update(&mut motors).is_err());
+        assert!(motors.get_target_velocity().unwrap()[0].is_nan());
+    }
+
+    #[test]
+    fn hard_stop() {
+        let mut motors = FakeMotorsController::<2>::new()
+            .with_offsets([Some(3.0), None])
+            .with_reduction([Some(2.0), None])
+            .with_hard_stops([Some((-1.0, 1.0).try_into().unwrap()); 2]);
+        motors.set_torque([true; 2]).unwrap();
+
+        let mut homing = Homing::new([
+            Some(HomingConfig {
+                velocity: 0.5,
+                torque_threshold: 0.5,
+                stop_position: 1.2,
+            }),
+            None,
+        ])
+        .with_velocity_mode(1);
+
+        assert_eq!(homing.update(&mut motors).unwrap(), HomingState::Searching);
+        assert_eq!(motors.get_control_mode().unwrap(), [1, 0]);
+
+        for _ in 0..100 {
+            motors.fake_io().step(0.1);
+            if homing.update(&mut motors).unwrap() == HomingState::Done {
+                break;
+            }
+        }
+        assert_eq!(homing.state(), HomingState::Done);
+        assert_eq!(motors.get_control_mode().unwrap(), [0, 0]);
+        assert_eq!(motors.get_target_velocity().unwrap()[0], 0.0);
+
+        let offset = homing.offsets().unwrap()[0].unwrap();
+        assert!((offset + 0.7).abs() < 1e-9);
+
+        let position = motors.get_current_position().unwrap();
+        assert!((position[0] - 1.2).abs() < 1e-9);
+        assert_eq!(position[1], 0.0);
+        assert!((motors.get_target_position().unwrap()[0] - 1.2).abs() < 1e-9);
+
+        let config = MotorsConfig::from_controller(&motors);
+        assert_eq!(config.offsets, [Some(offset), None]);
+    }
+
+    #[test]
+    fn spurious_torque() {
+        let mut motors = FakeMotorsController::<1>::new()
+            .with_hard_stops([Some((-1.0, 1.0).try_into().unwrap())]);
+        motors.set_torque([true]).unwrap();
+
+        let mut homing = Homing::new([Some(HomingConfig {
+            velocity: 0.5,
+            torque_threshold: 0.5,
+            stop_position: 1.0,
+        })]);
+        homing.update(&mut motors).unwrap();
+        motors.fake_io().step(0.1);
+
+        // A load against the search direction is not the stop
+        motors.fake_io().set_current_torque([-2.0]);
+        assert_eq!(homing.update(&mut motors).unwrap(), HomingState::Searching);
+
+        // Neither is a torque spike while still moving
+        motors.fake_io().set_current_torque([2.0]);
+        assert_eq!(homing.update(&mut motors).unwrap(), HomingState::Searching);
+
+        for _ in 0..100 {
+            motors.fake_io().step(0.1);
+            if homing.update(&mut motors).unwrap() == HomingState::Done {
+                break;
+            }
+        }
+        assert_eq!(homing.state(), HomingState::Done);
+        assert!((motors.get_current_position().unwrap()[0] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut motors = FakeMotorsController::<1>::new();
+        motors.set_torque([true]).unwrap();
+
+        let mut homing = Homing::new([Some(HomingConfig {
+            velocity: -0.5,
+            torque_threshold: 0.5,
+            stop_position: 0.0,
+        })]);
+        let res = homing.run(
+            &mut motors,
+            Duration::from_millis(1),
+            Duration::from_millis(5),
+        );
+
+        assert!(res.is_err());
+        assert_eq!(homing.state(), HomingState::Searching);
+        assert_eq!(motors.get_target_velocity().unwrap(), [0.0]);
+        assert_eq!(motors.offsets(), [None]);
+    }
+}
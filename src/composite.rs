@@ -0,0 +1,1370 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::{
+    EmergencyStop, GainCapabilities, Gains, ImpedanceCommand, Limit, MotorsController, RawMotorsIO,
+    Result, PID,
+};
+
+/// Motors of a part, seen through slices so that parts of different sizes can be combined
+trait PartMotors: Send {
+    fn len(&self) -> usize;
+
+    fn is_torque_on(&mut self, on: &mut [bool]) -> Result<()>;
+    fn set_torque(&mut self, on: &[bool]) -> Result<()>;
+
+    fn get_current_position(&mut self, position: &mut [f64]) -> Result<()>;
+    fn get_current_velocity(&mut self, velocity: &mut [f64]) -> Result<()>;
+    fn get_current_torque(&mut self, torque: &mut [f64]) -> Result<()>;
+
+    fn get_target_position(&mut self, position: &mut [f64]) -> Result<()>;
+    fn set_target_position(&mut self, position: &[f64]) -> Result<()>;
+    fn get_target_torque(&mut self, torque: &mut [f64]) -> Result<()>;
+    fn set_target_torque(&mut self, torque: &[f64]) -> Result<()>;
+    fn get_target_velocity(&mut self, velocity: &mut [f64]) -> Result<()>;
+    fn set_target_velocity(&mut self, velocity: &[f64]) -> Result<()>;
+    /// Set the target positions and replace them by the feedback
+    fn set_target_position_fb(&mut self, position: &mut [f64]) -> Result<()>;
+
+    fn get_control_mode(&mut self, mode: &mut [u8]) -> Result<()>;
+    fn set_control_mode(&mut self, mode: &[u8]) -> Result<()>;
+
+    fn supports_impedance(&self) -> bool;
+    fn set_impedance_command(&mut self, command: &[ImpedanceCommand]) -> Result<()>;
+
+    fn get_velocity_limit(&mut self, velocity: &mut [f64]) -> Result<()>;
+    fn set_velocity_limit(&mut self, velocity: &[f64]) -> Result<()>;
+    fn get_torque_limit(&mut self, torque: &mut [f64]) -> Result<()>;
+    fn set_torque_limit(&mut self, torque: &[f64]) -> Result<()>;
+
+    fn get_pid_gains(&mut self, pid: &mut [PID]) -> Result<()>;
+    fn set_pid_gains(&mut self, pid: &[PID]) -> Result<()>;
+    fn gain_capabilities(&self) -> GainCapabilities;
+    fn get_gains(&mut self, gains: &mut [Gains]) -> Result<()>;
+    fn set_gains(&mut self, gains: &[Gains]) -> Result<()>;
+
+    fn get_axis_sensors(&mut self, sensors: &mut [f64]) -> Result<()>;
+    fn get_board_state(&mut self) -> Result<u8>;
+    fn set_board_state(&mut self, state: u8) -> Result<()>;
+
+    fn emergency_stop(&mut self) -> Result<()>;
+    fn reset_emergency_stop(&mut self) -> Result<()>;
+}
+
+/// Array of a part from its slice of the composite values (always of the part size)
+fn array<T: Copy, const K: usize>(values: &[T]) -> [T; K] {
+    std::array::from_fn(|i| values[i])
+}
+
+struct IOPart<IO, const K: usize>(IO);
+
+impl<IO, const K: usize> PartMotors for IOPart<IO, K>
+where
+    IO: RawMotorsIO<K> + Send,
+{
+    fn len(&self) -> usize {
+        K
+    }
+
+    fn is_torque_on(&mut self, on: &mut [bool]) -> Result<()> {
+        on.copy_from_slice(&self.0.is_torque_on()?);
+        Ok(())
+    }
+    fn set_torque(&mut self, on: &[bool]) -> Result<()> {
+        self.0.set_torque(array(on))
+    }
+
+    fn get_current_position(&mut self, position: &mut [f64]) -> Result<()> {
+        position.copy_from_slice(&self.0.get_current_position()?);
+        Ok(())
+    }
+    fn get_current_velocity(&mut self, velocity: &mut [f64]) -> Result<()> {
+        velocity.copy_from_slice(&self.0.get_current_velocity()?);
+        Ok(())
+    }
+    fn get_current_torque(&mut self, torque: &mut [f64]) -> Result<()> {
+        torque.copy_from_slice(&self.0.get_current_torque()?);
+        Ok(())
+    }
+
+    fn get_target_position(&mut self, position: &mut [f64]) -> Result<()> {
+        position.copy_from_slice(&self.0.get_target_position()?);
+        Ok(())
+    }
+    fn set_target_position(&mut self, position: &[f64]) -> Result<()> {
+        self.0.set_target_position(array(position))
+    }
+    fn get_target_torque(&mut self, torque: &mut [f64]) -> Result<()> {
+        torque.copy_from_slice(&self.0.get_target_torque()?);
+        Ok(())
+    }
+    fn set_target_torque(&mut self, torque: &[f64]) -> Result<()> {
+        self.0.set_target_torque(array(torque))
+    }
+    fn get_target_velocity(&mut self, velocity: &mut [f64]) -> Result<()> {
+        velocity.copy_from_slice(&self.0.get_target_velocity()?);
+        Ok(())
+    }
+    fn set_target_velocity(&mut self, velocity: &[f64]) -> Result<()> {
+        self.0.set_target_velocity(array(velocity))
+    }
+    fn set_target_position_fb(&mut self, position: &mut [f64]) -> Result<()> {
+        let fb = self.0.set_target_position_fb(array(position))?;
+        position.copy_from_slice(&fb);
+        Ok(())
+    }
+
+    fn get_control_mode(&mut self, mode: &mut [u8]) -> Result<()> {
+        mode.copy_from_slice(&self.0.get_control_mode()?);
+        Ok(())
+    }
+    fn set_control_mode(&mut self, mode: &[u8]) -> Result<()> {
+        self.0.set_control_mode(array(mode))
+    }
+
+    fn supports_impedance(&self) -> bool {
+        self.0.supports_impedance()
+    }
+    fn set_impedance_command(&mut self, command: &[ImpedanceCommand]) -> Result<()> {
+        self.0.set_impedance_command(array(command))
+    }
+
+    fn get_velocity_limit(&mut self, velocity: &mut [f64]) -> Result<()> {
+        velocity.copy_from_slice(&self.0.get_velocity_limit()?);
+        Ok(())
+    }
+    fn set_velocity_limit(&mut self, velocity: &[f64]) -> Result<()> {
+        self.0.set_velocity_limit(array(velocity))
+    }
+    fn get_torque_limit(&mut self, torque: &mut [f64]) -> Result<()> {
+        torque.copy_from_slice(&self.0.get_torque_limit()?);
+        Ok(())
+    }
+    fn set_torque_limit(&mut self, torque: &[f64]) -> Result<()> {
+        self.0.set_torque_limit(array(torque))
+    }
+
+    fn get_pid_gains(&mut self, pid: &mut [PID]) -> Result<()> {
+        pid.copy_from_slice(&self.0.get_pid_gains()?);
+        Ok(())
+    }
+    fn set_pid_gains(&mut self, pid: &[PID]) -> Result<()> {
+        self.0.set_pid_gains(array(pid))
+    }
+    fn gain_capabilities(&self) -> GainCapabilities {
+        self.0.gain_capabilities()
+    }
+    fn get_gains(&mut self, gains: &mut [Gains]) -> Result<()> {
+        gains.copy_from_slice(&self.0.get_gains()?);
+        Ok(())
+    }
+    fn set_gains(&mut self, gains: &[Gains]) -> Result<()> {
+        self.0.set_gains(array(gains))
+    }
+
+    fn get_axis_sensors(&mut self, sensors: &mut [f64]) -> Result<()> {
+        sensors.copy_from_slice(&self.0.get_axis_sensors()?);
+        Ok(())
+    }
+    fn get_board_state(&mut self) -> Result<u8> {
+        self.0.get_board_state()
+    }
+    fn set_board_state(&mut self, state: u8) -> Result<()> {
+        self.0.set_board_state(state)
+    }
+
+    fn emergency_stop(&mut self) -> Result<()> {
+        self.0.set_torque([false; K])
+    }
+    fn reset_emergency_stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct ControllerPart<C, const K: usize> {
+    controller: C,
+    gain_capabilities: GainCapabilities,
+}
+
+impl<C, const K: usize> PartMotors for ControllerPart<C, K>
+where
+    C: MotorsController<K> + Send,
+{
+    fn len(&self) -> usize {
+        K
+    }
+
+    fn is_torque_on(&mut self, on: &mut [bool]) -> Result<()> {
+        on.copy_from_slice(&self.controller.is_torque_on()?);
+        Ok(())
+    }
+    fn set_torque(&mut self, on: &[bool]) -> Result<()> {
+        self.controller.set_torque(array(on))
+    }
+
+    fn get_current_position(&mut self, position: &mut [f64]) -> Result<()> {
+        position.copy_from_slice(&self.controller.get_current_position()?);
+        Ok(())
+    }
+    fn get_current_velocity(&mut self, velocity: &mut [f64]) -> Result<()> {
+        velocity.copy_from_slice(&self.controller.get_current_velocity()?);
+        Ok(())
+    }
+    fn get_current_torque(&mut self, torque: &mut [f64]) -> Result<()> {
+        torque.copy_from_slice(&self.controller.get_current_torque()?);
+        Ok(())
+    }
+
+    fn get_target_position(&mut self, position: &mut [f64]) -> Result<()> {
+        position.copy_from_slice(&self.controller.get_target_position()?);
+        Ok(())
+    }
+    fn set_target_position(&mut self, position: &[f64]) -> Result<()> {
+        self.controller.set_target_position(array(position))
+    }
+    fn get_target_torque(&mut self, torque: &mut [f64]) -> Result<()> {
+        torque.copy_from_slice(&self.controller.get_target_torque()?);
+        Ok(())
+    }
+    fn set_target_torque(&mut self, torque: &[f64]) -> Result<()> {
+        self.controller.set_target_torque(array(torque))
+    }
+    fn get_target_velocity(&mut self, velocity: &mut [f64]) -> Result<()> {
+        velocity.copy_from_slice(&self.controller.get_target_velocity()?);
+        Ok(())
+    }
+    fn set_target_velocity(&mut self, velocity: &[f64]) -> Result<()> {
+        self.controller.set_target_velocity(array(velocity))
+    }
+    fn set_target_position_fb(&mut self, position: &mut [f64]) -> Result<()> {
+        let fb = self.controller.set_target_position_fb(array(position))?;
+        position.copy_from_slice(&fb);
+        Ok(())
+    }
+
+    fn get_control_mode(&mut self, mode: &mut [u8]) -> Result<()> {
+        mode.copy_from_slice(&self.controller.get_control_mode()?);
+        Ok(())
+    }
+    fn set_control_mode(&mut self, mode: &[u8]) -> Result<()> {
+        self.controller.set_control_mode(array(mode))
+    }
+
+    fn supports_impedance(&self) -> bool {
+        // Controllers fall back to software impedance
+        true
+    }
+    fn set_impedance_command(&mut self, command: &[ImpedanceCommand]) -> Result<()> {
+        self.controller.set_impedance_command(array(command))
+    }
+
+    fn get_velocity_limit(&mut self, velocity: &mut [f64]) -> Result<()> {
+        velocity.copy_from_slice(&self.controller.get_velocity_limit()?);
+        Ok(())
+    }
+    fn set_velocity_limit(&mut self, velocity: &[f64]) -> Result<()> {
+        self.controller.set_velocity_limit(array(velocity))
+    }
+    fn get_torque_limit(&mut self, torque: &mut [f64]) -> Result<()> {
+        torque.copy_from_slice(&self.controller.get_torque_limit()?);
+        Ok(())
+    }
+    fn set_torque_limit(&mut self, torque: &[f64]) -> Result<()> {
+        self.controller.set_torque_limit(array(torque))
+    }
+
+    fn get_pid_gains(&mut self, pid: &mut [PID]) -> Result<()> {
+        pid.copy_from_slice(&self.controller.get_pid_gains()?);
+        Ok(())
+    }
+    fn set_pid_gains(&mut self, pid: &[PID]) -> Result<()> {
+        self.controller.set_pid_gains(array(pid))
+    }
+    fn gain_capabilities(&self) -> GainCapabilities {
+        self.gain_capabilities
+    }
+    fn get_gains(&mut self, gains: &mut [Gains]) -> Result<()> {
+        gains.copy_from_slice(&self.controller.get_gains()?);
+        Ok(())
+    }
+    fn set_gains(&mut self, gains: &[Gains]) -> Result<()> {
+        self.controller.set_gains(array(gains))
+    }
+
+    fn get_axis_sensors(&mut self, sensors: &mut [f64]) -> Result<()> {
+        sensors.copy_from_slice(&self.controller.get_axis_sensors()?);
+        Ok(())
+    }
+    fn get_board_state(&mut self) -> Result<u8> {
+        self.controller.get_board_state()
+    }
+    fn set_board_state(&mut self, state: u8) -> Result<()> {
+        self.controller.set_board_state(state)
+    }
+
+    fn emergency_stop(&mut self) -> Result<()> {
+        self.controller.emergency_stop()
+    }
+    fn reset_emergency_stop(&mut self) -> Result<()> {
+        self.controller.reset_emergency_stop()
+    }
+}
+
+/// Timestamp and error message of a part for a transaction
+///
+/// Errors are not Send, their messages are sent back from the threads.
+type Outcome = (Option<Instant>, Option<String>);
+
+/// Sends the outcome of a job back to the transaction
+type Reply = Box<dyn FnOnce() + Send>;
+type Transaction = Box<dyn FnOnce(&mut dyn PartMotors) -> Reply + Send>;
+
+/// Transaction of a part, run on its worker
+struct Job {
+    run: Transaction,
+    /// Timeout of the transaction, after which the job is dropped (None for stops, always run)
+    deadline: Option<Instant>,
+}
+
+/// Thread owning the motors of a part and running its transactions
+struct Worker {
+    jobs: Option<mpsc::Sender<Job>>,
+    in_flight: Arc<AtomicUsize>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn(name: &str, mut motors: Box<dyn PartMotors>) -> std::io::Result<Self> {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let done = in_flight.clone();
+        let handle = std::thread::Builder::new()
+            .name(format!("composite-{name}"))
+            .spawn(move || {
+                for job in receiver {
+                    // Commands of a transaction that timed out are not sent late
+                    if job.deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                        log::debug!(target: "composite_io::worker", "dropping a job that timed out");
+                        done.fetch_sub(1, Ordering::SeqCst);
+                        continue;
+                    }
+                    let reply = (job.run)(motors.as_mut());
+                    // Idle before replying, so that the next transaction is never refused
+                    done.fetch_sub(1, Ordering::SeqCst);
+                    reply();
+                }
+            })?;
+        Ok(Self {
+            jobs: Some(jobs),
+            in_flight,
+            handle: Some(handle),
+        })
+    }
+
+    /// Queue a job, unless the worker is still busy with a transaction that timed out
+    ///
+    /// Stops (without deadline) are queued behind the late transaction.
+    fn submit(&self, job: Job) -> std::result::Result<(), String> {
+        if job.deadline.is_some() && self.in_flight.load(Ordering::SeqCst) > 0 {
+            return Err("still busy with a transaction that timed out".to_string());
+        }
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let sent = self
+            .jobs
+            .as_ref()
+            .is_some_and(|jobs| jobs.send(job).is_ok());
+        if !sent {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            return Err("worker thread stopped".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.jobs.take();
+        // A worker stuck on its bus is left behind rather than blocking the drop
+        if self.in_flight.load(Ordering::SeqCst) == 0 {
+            if let Some(handle) = self.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// Where the transactions of a part run
+enum Runner {
+    /// On the calling thread (or a scoped thread per transaction)
+    Local(Box<dyn PartMotors>),
+    /// On the part's own worker thread
+    Worker(Worker),
+}
+
+/// Named group of joints of a composite (a raw io or a whole controller)
+pub struct CompositePart {
+    name: String,
+    len: usize,
+    limits: Vec<Option<Limit>>,
+    supports_impedance: bool,
+    gain_capabilities: GainCapabilities,
+    runner: Runner,
+}
+
+impl CompositePart {
+    /// Part driving the raw motors of an io
+    pub fn from_io<IO, const K: usize>(name: &str, io: IO) -> Self
+    where
+        IO: RawMotorsIO<K> + Send + 'static,
+    {
+        Self::new(name, vec![None; K], Box::new(IOPart(io)))
+    }
+
+    /// Part driving the joints of a controller, through its conversions, limits and safety checks
+    pub fn from_controller<C, const K: usize>(name: &str, mut controller: C) -> Self
+    where
+        C: MotorsController<K> + Send + 'static,
+    {
+        let limits = controller.limits().to_vec();
+        let motors = ControllerPart {
+            gain_capabilities: controller.gain_capabilities(),
+            controller,
+        };
+        Self::new(name, limits, Box::new(motors))
+    }
+
+    fn new(name: &str, limits: Vec<Option<Limit>>, motors: Box<dyn PartMotors>) -> Self {
+        Self {
+            name: name.to_string(),
+            len: motors.len(),
+            limits,
+            supports_impedance: motors.supports_impedance(),
+            gain_capabilities: motors.gain_capabilities(),
+            runner: Runner::Local(motors),
+        }
+    }
+
+    /// Move the motors of the part to their own worker thread
+    fn into_worker(self) -> std::io::Result<Self> {
+        let runner = match self.runner {
+            Runner::Local(motors) => Runner::Worker(Worker::spawn(&self.name, motors)?),
+            worker => worker,
+        };
+        Ok(Self { runner, ..self })
+    }
+
+    /// Name of the part, attached to its errors
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of joints of the part
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the part has no joint
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl std::fmt::Debug for CompositePart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompositePart")
+            .field("name", &self.name)
+            .field("len", &self.len)
+            .field("worker", &matches!(self.runner, Runner::Worker(_)))
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Outcome of the last transaction of a part
+pub struct PartReport {
+    /// Name of the part
+    pub name: String,
+    /// Joints of the part in the composite
+    pub joints: Range<usize>,
+    /// When the part completed the transaction (None if it did not answer)
+    pub timestamp: Option<Instant>,
+    /// Error of the part, if the transaction failed
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Feedback of a synchronized cycle of all the parts (see [CompositeIO::cycle])
+///
+/// The joints of the parts that failed are set to NaN.
+pub struct CompositeCycle<const N: usize> {
+    pub position: [f64; N],
+    pub velocity: [f64; N],
+    pub torque: [f64; N],
+    pub reports: Vec<PartReport>,
+}
+
+#[derive(Clone, Copy)]
+/// Command and feedback of a joint during a cycle
+struct JointCycle {
+    target: Option<f64>,
+    position: f64,
+    velocity: f64,
+    torque: f64,
+}
+
+#[derive(Debug)]
+/// IO combining several parts into N joints, in the order of the parts
+///
+/// Each transaction is sent to every part, on one thread per part when the
+/// composite is parallel (the default), so that parts on different buses are
+/// read and written at the same time. A failing part does not prevent the
+/// others from receiving the command, and its error is reported in a
+/// [CompositeError] with the name of the part.
+///
+/// With [workers](Self::with_workers), each part runs on its own long-lived
+/// thread and a transaction waits for the parts up to a timeout: a stalled bus
+/// is reported as failed without stalling the others, and is skipped until its
+/// late transaction completes (except for emergency stops and torque off, which
+/// are queued behind it). The outcome of the last transaction of each part
+/// is available in its [report](Self::reports).
+pub struct CompositeIO<const N: usize> {
+    parts: Vec<CompositePart>,
+    parallel: bool,
+    timeout: Duration,
+    reports: Vec<PartReport>,
+}
+
+impl<const N: usize> CompositeIO<N> {
+    /// Combine parts, checking that they add up to N joints and that their names are unique
+    pub fn new(parts: Vec<CompositePart>) -> std::result::Result<Self, InvalidCompositeError> {
+        let len: usize = parts.iter().map(CompositePart::len).sum();
+        if len != N {
+            return Err(InvalidCompositeError(format!(
+                "parts have {len} joints instead of {N}"
+            )));
+        }
+        for (i, part) in parts.iter().enumerate() {
+            if parts[..i].iter().any(|p| p.name == part.name) {
+                return Err(InvalidCompositeError(format!(
+                    "duplicate part name {:?}",
+                    part.name
+                )));
+            }
+        }
+        Ok(Self {
+            parts,
+            parallel: true,
+            timeout: Duration::from_millis(100),
+            reports: Vec::new(),
+        })
+    }
+
+    /// Run the transactions of the parts on parallel threads or one after the other
+    pub fn with_parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Run each part on its own worker thread, waiting for them up to a timeout per transaction
+    pub fn with_workers(mut self, timeout: Duration) -> std::io::Result<Self> {
+        self.parts = self
+            .parts
+            .into_iter()
+            .map(CompositePart::into_worker)
+            .collect::<std::io::Result<_>>()?;
+        self.timeout = timeout;
+        Ok(self)
+    }
+
+    /// Parts of the composite
+    pub fn parts(&self) -> &[CompositePart] {
+        &self.parts
+    }
+
+    /// Outcome of the last transaction of each part
+    pub fn reports(&self) -> &[PartReport] {
+        &self.reports
+    }
+
+    /// Write the target positions (if any) and read the feedback of all the parts in one transaction
+    ///
+    /// Parts are isolated: the feedback of the parts that answered is returned
+    /// even if others failed, see the reports for their errors and timestamps.
+    pub fn cycle(&mut self, target_position: Option<[f64; N]>) -> CompositeCycle<N> {
+        let mut joints = [JointCycle {
+            target: None,
+            position: f64::NAN,
+            velocity: f64::NAN,
+            torque: f64::NAN,
+        }; N];
+        if let Some(target_position) = target_position {
+            for (joint, target) in joints.iter_mut().zip(target_position) {
+                joint.target = Some(target);
+            }
+        }
+
+        let result = self.run(&mut joints, |part, joints| {
+            if joints.iter().all(|joint| joint.target.is_some()) {
+                let target: Vec<f64> = joints.iter().flat_map(|joint| joint.target).collect();
+                part.set_target_position(&target)?;
+            }
+            let mut values = vec![0.0; joints.len()];
+            part.get_current_position(&mut values)?;
+            for (joint, position) in joints.iter_mut().zip(&values) {
+                joint.position = *position;
+            }
+            part.get_current_velocity(&mut values)?;
+            for (joint, velocity) in joints.iter_mut().zip(&values) {
+                joint.velocity = *velocity;
+            }
+            part.get_current_torque(&mut values)?;
+            for (joint, torque) in joints.iter_mut().zip(&values) {
+                joint.torque = *torque;
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            for report in self.reports.iter().filter(|report| report.error.is_some()) {
+                for joint in &mut joints[report.joints.clone()] {
+                    joint.position = f64::NAN;
+                    joint.velocity = f64::NAN;
+                    joint.torque = f64::NAN;
+                }
+            }
+        }
+
+        CompositeCycle {
+            position: joints.map(|joint| joint.position),
+            velocity: joints.map(|joint| joint.velocity),
+            torque: joints.map(|joint| joint.torque),
+            reports: self.reports.clone(),
+        }
+    }
+
+    /// Joints of a part in the composite
+    pub fn joints(&self, name: &str) -> Option<Range<usize>> {
+        let mut start = 0;
+        for part in &self.parts {
+            if part.name == name {
+                return Some(start..start + part.len());
+            }
+            start += part.len();
+        }
+        None
+    }
+
+    /// Limits of the joints, as enforced by the controller parts
+    pub fn limits(&self) -> [Option<Limit>; N] {
+        let mut limits = [None; N];
+        for (limit, part_limit) in limits
+            .iter_mut()
+            .zip(self.parts.iter().flat_map(|part| &part.limits))
+        {
+            *limit = *part_limit;
+        }
+        limits
+    }
+
+    /// Apply the emergency stop of every part
+    ///
+    /// Controller parts latch their own emergency stop, raw io parts are turned off.
+    pub fn emergency_stop(&mut self) -> Result<()> {
+        let chunks = self.part_chunks(&mut [(); 0]);
+        self.run_chunks(chunks, |_| true, |part, _| part.emergency_stop())
+    }
+
+    /// Release the emergency stop of the controller parts
+    pub fn reset_emergency_stop(&mut self) -> Result<()> {
+        self.run_parts(&mut [(); 0], |part, _| part.reset_emergency_stop())
+    }
+
+    /// Run a transaction on the parts, each with its slice of the joint values
+    fn run<T: Copy + Send + 'static>(
+        &mut self,
+        values: &mut [T],
+        transaction: impl Fn(&mut dyn PartMotors, &mut [T]) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<()> {
+        let chunks = self.joint_chunks(values);
+        self.run_chunks(chunks, |_| false, transaction)
+    }
+
+    /// Run a transaction on the parts, each with its own value (or none if values is empty)
+    fn run_parts<T: Copy + Send + 'static>(
+        &mut self,
+        values: &mut [T],
+        transaction: impl Fn(&mut dyn PartMotors, &mut [T]) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<()> {
+        let chunks = self.part_chunks(values);
+        self.run_chunks(chunks, |_| false, transaction)
+    }
+
+    /// Slices of the joint values of each part
+    fn joint_chunks<'a, T>(&self, values: &'a mut [T]) -> Vec<&'a mut [T]> {
+        let mut chunks = Vec::with_capacity(self.parts.len());
+        let mut rest = values;
+        for part in &self.parts {
+            let (chunk, tail) = rest.split_at_mut(part.len());
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+
+    /// Value of each part (or none if values is empty)
+    fn part_chunks<'a, T>(&self, values: &'a mut [T]) -> Vec<&'a mut [T]> {
+        let mut chunks: Vec<&mut [T]> = values.chunks_mut(1).collect();
+        chunks.resize_with(self.parts.len(), Default::default);
+        chunks
+    }
+
+    /// Run a transaction on the chunks of the parts
+    ///
+    /// The chunks for which `stop` is true stop their part (emergency stop or
+    /// torque off): they are still sent to a part busy with a late transaction.
+    fn run_chunks<T: Copy + Send + 'static>(
+        &mut self,
+        chunks: Vec<&mut [T]>,
+        stop: fn(&[T]) -> bool,
+        transaction: impl Fn(&mut dyn PartMotors, &mut [T]) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<()> {
+        let workers = self
+            .parts
+            .iter()
+            .any(|part| matches!(part.runner, Runner::Worker(_)));
+        let outcomes = match workers {
+            true => self.run_workers(chunks, stop, transaction),
+            false => self.run_local(chunks, transaction),
+        };
+
+        let mut start = 0;
+        self.reports = self
+            .parts
+            .iter()
+            .zip(&outcomes)
+            .map(|(part, (timestamp, error))| {
+                let joints = start..start + part.len();
+                start = joints.end;
+                PartReport {
+                    name: part.name.clone(),
+                    joints,
+                    timestamp: *timestamp,
+                    error: error.clone(),
+                }
+            })
+            .collect();
+
+        let errors: Vec<PartError> = self
+            .parts
+            .iter()
+            .zip(outcomes)
+            .filter_map(|(part, (_, error))| {
+                Some(PartError {
+                    name: part.name.clone(),
+                    message: error?,
+                })
+            })
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        log::warn!(target: "composite_io::run", "failed parts: {:?}", errors);
+        Err(Box::new(CompositeError(errors)))
+    }
+
+    /// Run a transaction on scoped threads (or sequentially), returning the timestamp and error of each part
+    fn run_local<T: Send>(
+        &mut self,
+        chunks: Vec<&mut [T]>,
+        transaction: impl Fn(&mut dyn PartMotors, &mut [T]) -> Result<()> + Sync,
+    ) -> Vec<Outcome> {
+        let transaction = &transaction;
+        let run = move |runner: &mut Runner, chunk: &mut [T]| -> Outcome {
+            match runner {
+                Runner::Local(motors) => {
+                    let error = transaction(motors.as_mut(), chunk)
+                        .err()
+                        .map(|e| e.to_string());
+                    (Some(Instant::now()), error)
+                }
+                Runner::Worker(_) => (None, Some("part runs on a worker".to_string())),
+            }
+        };
+        match self.parallel && self.parts.len() > 1 {
+            true => std::thread::scope(|scope| {
+                let handles: Vec<_> = self
+                    .parts
+                    .iter_mut()
+                    .zip(chunks)
+                    .map(|(part, chunk)| scope.spawn(move || run(&mut part.runner, chunk)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|_| (None, Some("transaction panicked".to_string())))
+                    })
+                    .collect()
+            }),
+            false => self
+                .parts
+                .iter_mut()
+                .zip(chunks)
+                .map(|(part, chunk)| run(&mut part.runner, chunk))
+                .collect(),
+        }
+    }
+
+    /// Run a transaction on the workers and wait for them up to the timeout
+    fn run_workers<T: Copy + Send + 'static>(
+        &mut self,
+        mut chunks: Vec<&mut [T]>,
+        stop: fn(&[T]) -> bool,
+        transaction: impl Fn(&mut dyn PartMotors, &mut [T]) -> Result<()> + Send + Sync + 'static,
+    ) -> Vec<Outcome> {
+        let deadline = Instant::now() + self.timeout;
+        let transaction = Arc::new(transaction);
+        let (sender, receiver) = mpsc::channel();
+        let mut outcomes: Vec<Outcome> = (0..self.parts.len()).map(|_| (None, None)).collect();
+        let mut submitted = 0;
+
+        for (index, (part, chunk)) in self.parts.iter_mut().zip(&chunks).enumerate() {
+            let transaction = transaction.clone();
+            let sender = sender.clone();
+            let mut values = chunk.to_vec();
+            let stop = stop(chunk);
+            let run: Transaction = Box::new(move |motors| {
+                let error = transaction(motors, &mut values)
+                    .err()
+                    .map(|e| e.to_string());
+                let timestamp = Instant::now();
+                // The receiver is gone if the transaction timed out
+                Box::new(move || {
+                    let _ = sender.send((index, values, error, timestamp));
+                })
+            });
+            let job = Job {
+                run,
+                deadline: (!stop).then_some(deadline),
+            };
+            match &mut part.runner {
+                Runner::Worker(worker) => match worker.submit(job) {
+                    Ok(()) => submitted += 1,
+                    Err(error) => outcomes[index].1 = Some(error),
+                },
+                Runner::Local(motors) => {
+                    (job.run)(motors.as_mut())();
+                    submitted += 1;
+                }
+            }
+        }
+        drop(sender);
+
+        while submitted > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let Ok((index, values, error, timestamp)) = receiver.recv_timeout(timeout) else {
+                break;
+            };
+            chunks[index].copy_from_slice(&values);
+            outcomes[index] = (Some(timestamp), error);
+            submitted -= 1;
+        }
+        for outcome in &mut outcomes {
+            if outcome.0.is_none() && outcome.1.is_none() {
+                outcome.1 = Some(format!("no answer within {:?}", self.timeout));
+            }
+        }
+        outcomes
+    }
+}
+
+impl<const N: usize> RawMotorsIO<N> for CompositeIO<N> {
+    fn is_torque_on(&mut self) -> Result<[bool; N]> {
+        let mut on = [false; N];
+        self.run(&mut on, |part, on| part.is_torque_on(on))?;
+        Ok(on)
+    }
+
+    fn set_torque(&mut self, on: [bool; N]) -> Result<()> {
+        let mut on = on;
+        let chunks = self.joint_chunks(&mut on);
+        self.run_chunks(
+            chunks,
+            |on| !on.contains(&true),
+            |part, on| part.set_torque(on),
+        )
+    }
+
+    fn get_current_position(&mut self) -> Result<[f64; N]> {
+        let mut position = [0.0; N];
+        self.run(&mut position, |part, position| {
+            part.get_current_position(position)
+        })?;
+        Ok(position)
+    }
+
+    fn get_current_velocity(&mut self) -> Result<[f64; N]> {
+        let mut velocity = [0.0; N];
+        self.run(&mut velocity, |part, velocity| {
+            part.get_current_velocity(velocity)
+        })?;
+        Ok(velocity)
+    }
+
+    fn get_current_torque(&mut self) -> Result<[f64; N]> {
+        let mut torque = [0.0; N];
+        self.run(&mut torque, |part, torque| part.get_current_torque(torque))?;
+        Ok(torque)
+    }
+
+    fn get_target_position(&mut self) -> Result<[f64; N]> {
+        let mut position = [0.0; N];
+        self.run(&mut position, |part, position| {
+            part.get_target_position(position)
+        })?;
+        Ok(position)
+    }
+
+    fn set_target_position(&mut self, position: [f64; N]) -> Result<()> {
+        let mut position = position;
+        self.run(&mut position, |part, position| {
+            part.set_target_position(position)
+        })
+    }
+
+    fn get_target_torque(&mut self) -> Result<[f64; N]> {
+        let mut torque = [0.0; N];
+        self.run(&mut torque, |part, torque| part.get_target_torque(torque))?;
+        Ok(torque)
+    }
+
+    fn set_target_torque(&mut self, torque: [f64; N]) -> Result<()> {
+        let mut torque = torque;
+        self.run(&mut torque, |part, torque| part.set_target_torque(torque))
+    }
+
+    fn set_target_velocity(&mut self, velocity: [f64; N]) -> Result<()> {
+        let mut velocity = velocity;
+        self.run(&mut velocity, |part, velocity| {
+            part.set_target_velocity(velocity)
+        })
+    }
+
+    fn get_target_velocity(&mut self) -> Result<[f64; N]> {
+        let mut velocity = [0.0; N];
+        self.run(&mut velocity, |part, velocity| {
+            part.get_target_velocity(velocity)
+        })?;
+        Ok(velocity)
+    }
+
+    fn set_control_mode(&mut self, mode: [u8; N]) -> Result<()> {
+        let mut mode = mode;
+        self.run(&mut mode, |part, mode| part.set_control_mode(mode))
+    }
+
+    fn get_control_mode(&mut self) -> Result<[u8; N]> {
+        let mut mode = [0; N];
+        self.run(&mut mode, |part, mode| part.get_control_mode(mode))?;
+        Ok(mode)
+    }
+
+    fn supports_impedance(&self) -> bool {
+        self.parts.iter().all(|part| part.supports_impedance)
+    }
+
+    fn set_impedance_command(&mut self, command: [ImpedanceCommand; N]) -> Result<()> {
+        let mut command = command;
+        self.run(&mut command, |part, command| {
+            part.set_impedance_command(command)
+        })
+    }
+
+    fn set_target_position_fb(&mut self, position: [f64; N]) -> Result<[f64; N]> {
+        let mut fb = position;
+        self.run(&mut fb, |part, fb| part.set_target_position_fb(fb))?;
+        Ok(fb)
+    }
+
+    fn get_velocity_limit(&mut self) -> Result<[f64; N]> {
+        let mut velocity = [0.0; N];
+        self.run(&mut velocity, |part, velocity| {
+            part.get_velocity_limit(velocity)
+        })?;
+        Ok(velocity)
+    }
+
+    fn set_velocity_limit(&mut self, velocity: [f64; N]) -> Result<()> {
+        let mut velocity = velocity;
+        self.run(&mut velocity, |part, velocity| {
+            part.set_velocity_limit(velocity)
+        })
+    }
+
+    fn get_torque_limit(&mut self) -> Result<[f64; N]> {
+        let mut torque = [0.0; N];
+        self.run(&mut torque, |part, torque| part.get_torque_limit(torque))?;
+        Ok(torque)
+    }
+
+    fn set_torque_limit(&mut self, torque: [f64; N]) -> Result<()> {
+        let mut torque = torque;
+        self.run(&mut torque, |part, torque| part.set_torque_limit(torque))
+    }
+
+    fn get_pid_gains(&mut self) -> Result<[PID; N]> {
+        let mut pid = [PID {
+            p: 0.0,
+            i: 0.0,
+            d: 0.0,
+        }; N];
+        self.run(&mut pid, |part, pid| part.get_pid_gains(pid))?;
+        Ok(pid)
+    }
+
+    fn set_pid_gains(&mut self, pid: [PID; N]) -> Result<()> {
+        let mut pid = pid;
+        self.run(&mut pid, |part, pid| part.set_pid_gains(pid))
+    }
+
+    /// Gain fields honored by all the parts
+    fn gain_capabilities(&self) -> GainCapabilities {
+        let mut capabilities = GainCapabilities::ALL;
+        for part in &self.parts {
+            let part = part.gain_capabilities;
+            capabilities.p &= part.p;
+            capabilities.i &= part.i;
+            capabilities.d &= part.d;
+            capabilities.velocity_feedforward &= part.velocity_feedforward;
+            capabilities.acceleration_feedforward &= part.acceleration_feedforward;
+            capabilities.derivative_filter &= part.derivative_filter;
+            capabilities.integral_limit &= part.integral_limit;
+        }
+        capabilities
+    }
+
+    fn get_gains(&mut self) -> Result<[Gains; N]> {
+        let mut gains = [Gains::default(); N];
+        self.run(&mut gains, |part, gains| part.get_gains(gains))?;
+        Ok(gains)
+    }
+
+    fn set_gains(&mut self, gains: [Gains; N]) -> Result<()> {
+        let mut gains = gains;
+        self.run(&mut gains, |part, gains| part.set_gains(gains))
+    }
+
+    fn get_axis_sensors(&mut self) -> Result<[f64; N]> {
+        let mut sensors = [0.0; N];
+        self.run(&mut sensors, |part, sensors| part.get_axis_sensors(sensors))?;
+        Ok(sensors)
+    }
+
+    /// Board states of all the parts, combined with a bitwise or
+    fn get_board_state(&mut self) -> Result<u8> {
+        let mut states = vec![0; self.parts.len()];
+        self.run_parts(&mut states, |part, state| {
+            state[0] = part.get_board_state()?;
+            Ok(())
+        })?;
+        Ok(states
+            .iter()
+            .fold(0, |state, part_state| state | part_state))
+    }
+
+    /// Set the board state of all the parts
+    fn set_board_state(&mut self, state: u8) -> Result<()> {
+        self.run_parts(&mut [(); 0], move |part, _| part.set_board_state(state))
+    }
+}
+
+#[derive(Debug)]
+/// Controller combining several parts into N joints
+///
+/// The offsets, reductions and limits of the controller parts are applied by
+/// the parts themselves, so the composite reports no offset or reduction, and
+/// the limits of its parts. Its emergency stop also stops every part.
+pub struct CompositeController<const N: usize> {
+    io: CompositeIO<N>,
+    limits: [Option<Limit>; N],
+    emergency_stop: EmergencyStop,
+}
+
+impl<const N: usize> CompositeController<N> {
+    /// Combine parts, checking that they add up to N joints and that their names are unique
+    pub fn new(parts: Vec<CompositePart>) -> std::result::Result<Self, InvalidCompositeError> {
+        Ok(Self::from_io(CompositeIO::new(parts)?))
+    }
+
+    /// Controller driving a composite io
+    pub fn from_io(io: CompositeIO<N>) -> Self {
+        Self {
+            limits: io.limits(),
+            io,
+            emergency_stop: EmergencyStop::new(),
+        }
+    }
+
+    /// Underlying composite io
+    pub fn composite_io(&mut self) -> &mut CompositeIO<N> {
+        &mut self.io
+    }
+}
+
+impl<const N: usize> MotorsController<N> for CompositeController<N> {
+    fn io(&mut self) -> &mut dyn RawMotorsIO<N> {
+        &mut self.io
+    }
+
+    fn offsets(&self) -> [Option<f64>; N] {
+        [None; N]
+    }
+
+    fn reduction(&self) -> [Option<f64>; N] {
+        [None; N]
+    }
+
+    fn limits(&self) -> [Option<Limit>; N] {
+        self.limits
+    }
+
+    fn emergency_stop_latch(&self) -> Option<&EmergencyStop> {
+        Some(&self.emergency_stop)
+    }
+
+    fn emergency_stop(&mut self) -> Result<()> {
+        self.emergency_stop.trigger();
+        self.emergency_stop.take_pending_stop();
+        self.io.emergency_stop()
+    }
+
+    fn reset_emergency_stop(&mut self) -> Result<()> {
+        if self.emergency_stop.take_pending_stop() {
+            self.io.emergency_stop()?;
+        }
+        log::info!(target: "composite_controller::reset_emergency_stop", "emergency stop reset");
+        self.emergency_stop.reset()?;
+        self.io.reset_emergency_stop()
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCompositeError(pub String);
+impl std::fmt::Display for InvalidCompositeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid composite: {reason})")
+    }
+}
+impl std::error::Error for InvalidCompositeError {}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Error of a part of a composite, with the name of the part
+///
+/// Part errors are not Send, so only their message is sent back from the
+/// threads running the transactions.
+pub struct PartError {
+    pub name: String,
+    pub message: String,
+}
+impl std::fmt::Display for PartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+impl std::error::Error for PartError {}
+
+#[derive(Debug)]
+/// Errors of the failing parts of a composite
+pub struct CompositeError(pub Vec<PartError>);
+impl std::fmt::Display for CompositeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors: Vec<_> = self.0.iter().map(|error| error.to_string()).collect();
+        write!(f, "(composite errors: {})", errors.join(", "))
+    }
+}
+impl std::error::Error for CompositeError {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{CompositeController, CompositeError, CompositeIO, CompositePart};
+    use crate::{
+        EmergencyStopError, FakeMotorsController, FakeMotorsIO, Limit, MotorsController,
+        RawMotorsIO,
+    };
+
+    /// Controller whose bus blocks while the test holds its lock
+    struct Stalling {
+        io: FakeMotorsIO<1>,
+        bus: Arc<Mutex<()>>,
+    }
+
+    impl MotorsController<1> for Stalling {
+        fn io(&mut self) -> &mut dyn RawMotorsIO<1> {
+            drop(self.bus.lock().unwrap());
+            &mut self.io
+        }
+        fn offsets(&self) -> [Option<f64>; 1] {
+            [None]
+        }
+        fn reduction(&self) -> [Option<f64>; 1] {
+            [None]
+        }
+        fn limits(&self) -> [Option<Limit>; 1] {
+            [None]
+        }
+    }
+
+    fn arm() -> FakeMotorsController<2> {
+        FakeMotorsController::new()
+            .with_reduction([Some(2.0), None])
+            .with_limits([Some(Limit::new(-1.0, 1.0)), None])
+    }
+
+    fn parts() -> Vec<CompositePart> {
+        vec![
+            CompositePart::from_controller("arm", arm()),
+            CompositePart::from_io("head", FakeMotorsIO::<1>::default()),
+        ]
+    }
+
+    #[test]
+    fn joint_order() {
+        for parallel in [true, false] {
+            let mut io = CompositeIO::<3>::new(parts())
+                .unwrap()
+                .with_parallel(parallel);
+            assert_eq!(io.joints("head"), Some(2..3));
+            assert_eq!(io.limits(), [Some(Limit::new(-1.0, 1.0)), None, None]);
+
+            io.set_torque([true; 3]).unwrap();
+            io.set_target_position([0.5, 0.2, 0.3]).unwrap();
+            assert_eq!(io.get_current_position().unwrap(), [0.5, 0.2, 0.3]);
+            assert_eq!(io.get_target_position().unwrap(), [0.5, 0.2, 0.3]);
+            assert_eq!(
+                io.set_target_position_fb([0.4, 0.1, 0.0]).unwrap(),
+                [0.4, 0.1, 0.0]
+            );
+        }
+    }
+
+    #[test]
+    fn workers() {
+        let mut io = CompositeIO::<3>::new(parts())
+            .unwrap()
+            .with_workers(Duration::from_secs(1))
+            .unwrap();
+        io.set_torque([true; 3]).unwrap();
+        io.set_target_position([0.5, 0.2, 0.3]).unwrap();
+        assert_eq!(io.get_current_position().unwrap(), [0.5, 0.2, 0.3]);
+        assert!(io.reports().iter().all(|report| report.error.is_none()));
+        assert!(io.reports().iter().all(|report| report.timestamp.is_some()));
+
+        let cycle = io.cycle(Some([0.4, 0.1, 0.0]));
+        assert_eq!(cycle.position, [0.4, 0.1, 0.0]);
+        assert_eq!(cycle.reports[1].joints, 2..3);
+    }
+
+    #[test]
+    fn stalled_part() {
+        let bus = Arc::new(Mutex::new(()));
+        let head = Stalling {
+            io: FakeMotorsIO::default(),
+            bus: bus.clone(),
+        };
+        let mut io = CompositeIO::<3>::new(vec![
+            CompositePart::from_controller("arm", arm()),
+            CompositePart::from_controller("head", head),
+        ])
+        .unwrap()
+        .with_workers(Duration::from_millis(50))
+        .unwrap();
+        io.set_torque([true; 3]).unwrap();
+
+        // The stalled head times out without holding back the arm
+        let stall = bus.lock().unwrap();
+        let cycle = io.cycle(Some([0.5, 0.2, 0.3]));
+        assert_eq!(cycle.position[..2], [0.5, 0.2]);
+        assert!(cycle.position[2].is_nan());
+        assert!(cycle.reports[0].timestamp.is_some());
+        assert!(cycle.reports[1].timestamp.is_none());
+        assert!(cycle.reports[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("no answer"));
+
+        // Until its late transaction completes, the head is skipped
+        let cycle = io.cycle(None);
+        assert_eq!(cycle.position[..2], [0.5, 0.2]);
+        assert!(cycle.reports[1].error.as_ref().unwrap().contains("busy"));
+        assert!(io.set_torque([true; 3]).is_err());
+
+        // but a torque off is queued behind the late transaction
+        assert!(io.set_torque([false; 3]).is_err());
+        assert!(io.reports()[0].error.is_none());
+        assert!(io.reports()[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("no answer"));
+
+        drop(stall);
+        std::thread::sleep(Duration::from_millis(20));
+        let cycle = io.cycle(None);
+        assert!(cycle.reports.iter().all(|report| report.error.is_none()));
+        assert_eq!(cycle.position, [0.5, 0.2, 0.3]);
+        assert_eq!(io.is_torque_on().unwrap(), [false; 3]);
+    }
+
+    #[test]
+    fn part_errors() {
+        let mut motors = CompositeController::<3>::new(parts()).unwrap();
+        motors.set_torque([true; 3]).unwrap();
+        assert_eq!(motors.limits(), [Some(Limit::new(-1.0, 1.0)), None, None]);
+
+        motors.emergency_stop().unwrap();
+        assert_eq!(motors.is_torque_on().unwrap(), [false; 3]);
+        assert!(motors.set_target_position([0.0; 3]).is_err());
+
+        motors.reset_emergency_stop().unwrap();
+        motors.set_torque([true; 3]).unwrap();
+        motors.set_target_position([2.0, 0.0, 2.0]).unwrap();
+        assert_eq!(motors.get_current_position().unwrap(), [1.0, 0.0, 2.0]);
+
+        // A failing part is reported by name and the others still get the command
+        let arm = arm();
+        let latch = arm.emergency_stop_latch().unwrap().clone();
+        let mut io = CompositeIO::<3>::new(vec![
+            CompositePart::from_controller("arm", arm),
+            CompositePart::from_io("head", FakeMotorsIO::<1>::default()),
+        ])
+        .unwrap();
+        io.set_torque([true; 3]).unwrap();
+        latch.trigger();
+
+        let error = io.set_target_position([0.5, 0.5, 0.5]).unwrap_err();
+        let error = error.downcast_ref::<CompositeError>().unwrap();
+        assert_eq!(error.0.len(), 1);
+        assert_eq!(error.0[0].name, "arm");
+        assert_eq!(error.0[0].message, EmergencyStopError.to_string());
+        assert!(error.to_string().contains("arm"));
+        assert_eq!(io.get_current_position().unwrap()[2], 0.5);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(CompositeIO::<2>::new(parts()).is_err());
+        assert!(CompositeIO::<4>::new(vec![
+            CompositePart::from_controller("arm", arm()),
+            CompositePart::from_controller("arm", arm()),
+        ])
+        .is_err());
+    }
+}
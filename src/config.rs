@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Limit, LookupTable, MotorsController, OffsetSpace, StopMode};
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// Serializable joint space configuration of a controller
+pub struct MotorsConfig<const N: usize> {
+    /// Offsets of the motors (in radians)
+    #[serde(with = "crate::serde_array")]
+    pub offsets: [Option<f64>; N],
+    /// Reduction of the motors
+    #[serde(with = "crate::serde_array")]
+    pub reduction: [Option<f64>; N],
+    /// Joints turning in the opposite direction of their motor
+    #[serde(default = "not_inverted", with = "crate::serde_array")]
+    pub inverted: [bool; N],
+    /// Space in which the offsets are expressed
+    #[serde(default)]
+    pub offset_space: OffsetSpace,
+    /// Limits of the motors
+    #[serde(with = "crate::serde_array")]
+    pub limits: [Option<Limit>; N],
+    /// Nonlinear transmissions of the joints (replacing their offset and reduction)
+    #[serde(default = "no_lookup_tables", with = "crate::serde_array")]
+    pub lookup_tables: [Option<LookupTable>; N],
+    /// How each motor is stopped on emergency stop
+    #[serde(default = "torque_off", with = "crate::serde_array")]
+    pub stop_modes: [StopMode; N],
+}
+
+fn not_inverted<const N: usize>() -> [bool; N] {
+    [false; N]
+}
+
+fn no_lookup_tables<const N: usize>() -> [Option<LookupTable>; N] {
+    std::array::from_fn(|_| None)
+}
+
+fn torque_off<const N: usize>() -> [StopMode; N] {
+    [StopMode::TorqueOff; N]
+}
+
+impl<const N: usize> Default for MotorsConfig<N> {
+    fn default() -> Self {
+        Self {
+            offsets: [None; N],
+            reduction: [None; N],
+            inverted: [false; N],
+            offset_space: OffsetSpace::Joint,
+            limits: [None; N],
+            lookup_tables: no_lookup_tables(),
+            stop_modes: torque_off(),
+        }
+    }
+}
+
+impl<const N: usize> MotorsConfig<N> {
+    /// Read the configuration currently used by a controller
+    pub fn from_controller(controller: &dyn MotorsController<N>) -> Self {
+        Self {
+            offsets: controller.offsets(),
+            reduction: controller.reduction(),
+            inverted: controller.inverted(),
+            offset_space: controller.offset_space(),
+            limits: controller.limits(),
+            lookup_tables: controller
+                .lookup_tables()
+                .cloned()
+                .unwrap_or_else(no_lookup_tables),
+            stop_modes: controller.stop_modes(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MotorsConfig;
+    use crate::{FakeMotorsController, Limit, MotorsController, OffsetSpace, StopMode};
+
+    #[test]
+    fn round_trip() {
+        let config = MotorsConfig {
+            offsets: [Some(0.5), None],
+            reduction: [None, Some(-2.0)],
+            inverted: [true, false],
+            offset_space: OffsetSpace::Motor,
+            limits: [Some(Limit::new(-1.0, 1.0)), None],
+            lookup_tables: [None, None],
+            stop_modes: [StopMode::Hold, StopMode::TorqueOff],
+        };
+
+        let motors = FakeMotorsController::from_config(&config);
+        assert_eq!(MotorsConfig::from_controller(&motors), config);
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MotorsConfig<2>>(&json).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn lookup_tables() {
+        let json = r#"{
+            "offsets": [null, null],
+            "reduction": [null, 2.0],
+            "limits": [null, null],
+            "lookup_tables": [{"points": [[0.0, 0.0], [1.0, 2.0], [2.0, 3.0]]}, null]
+        }"#;
+        let config: MotorsConfig<2> = serde_json::from_str(json).unwrap();
+        assert!(config.lookup_tables[0].is_some());
+
+        let mut motors = FakeMotorsController::from_config(&config);
+        motors.set_torque([true; 2]).unwrap();
+        motors.set_target_position([1.0, 1.0]).unwrap();
+        assert_eq!(motors.io().get_target_position().unwrap(), [2.0, 2.0]);
+        assert_eq!(MotorsConfig::from_controller(&motors), config);
+
+        // The tables and stop modes are optional
+        let json = r#"{"offsets": [null], "reduction": [null], "limits": [null]}"#;
+        let config: MotorsConfig<1> = serde_json::from_str(json).unwrap();
+        assert_eq!(config, MotorsConfig::default());
+    }
+}
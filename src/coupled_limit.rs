@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Limit;
+
+/// Maximum number of projection passes over all constraints
+const MAX_ITERATIONS: usize = 100;
+/// Tolerance used to check the constraints and the convergence of the projection (in radians)
+const TOLERANCE: f64 = 1e-9;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Constraint involving several joints (positions in radians)
+pub enum CoupledLimit {
+    /// Linear inequality `sum(coefficients[i] * position[i]) <= max`
+    ///
+    /// Missing coefficients are zero.
+    Linear { coefficients: Vec<f64>, max: f64 },
+    /// The positions of two joints must stay inside a polygon
+    ///
+    /// The vertices give the (first joint, second joint) positions of the
+    /// polygon corners in order. The polygon does not need to be convex.
+    Polygon {
+        joints: [usize; 2],
+        vertices: Vec<[f64; 2]>,
+    },
+}
+
+impl CoupledLimit {
+    /// Check if a position satisfies the constraint
+    pub fn contains(&self, position: &[f64]) -> bool {
+        match self {
+            Self::Linear { coefficients, max } => dot(coefficients, position) <= max + TOLERANCE,
+            Self::Polygon { joints, vertices } => {
+                let point = [position[joints[0]], position[joints[1]]];
+                inside_polygon(point, vertices)
+                    || distance_to_polygon(point, vertices).0 <= TOLERANCE
+            }
+        }
+    }
+
+    /// Move a position to the closest one satisfying the constraint
+    pub fn project(&self, position: &mut [f64]) {
+        match self {
+            Self::Linear { coefficients, max } => {
+                let excess = dot(coefficients, position) - max;
+                let norm = dot(coefficients, coefficients);
+                if excess > 0.0 && norm > 0.0 {
+                    for (p, c) in position.iter_mut().zip(coefficients) {
+                        *p -= excess * c / norm;
+                    }
+                }
+            }
+            Self::Polygon { joints, vertices } => {
+                let point = [position[joints[0]], position[joints[1]]];
+                if !inside_polygon(point, vertices) {
+                    let (_, closest) = distance_to_polygon(point, vertices);
+                    position[joints[0]] = closest[0];
+                    position[joints[1]] = closest[1];
+                }
+            }
+        }
+    }
+
+    fn check<const N: usize>(&self) -> Result<(), InvalidCoupledLimitError> {
+        let invalid = |reason: &str| Err(InvalidCoupledLimitError(format!("{reason} in {self:?}")));
+
+        match self {
+            Self::Linear { coefficients, max } => {
+                if coefficients.len() > N {
+                    return invalid(&format!("more coefficients than the {N} joints"));
+                }
+                if coefficients.iter().any(|c| !c.is_finite()) || max.is_nan() {
+                    return invalid("non finite coefficient");
+                }
+                if coefficients.iter().all(|&c| c == 0.0) {
+                    return invalid("all coefficients are zero");
+                }
+            }
+            Self::Polygon { joints, vertices } => {
+                if joints.iter().any(|&j| j >= N) || joints[0] == joints[1] {
+                    return invalid(&format!("joints must be two different joints below {N}"));
+                }
+                if vertices.len() < 3 {
+                    return invalid("a polygon needs at least 3 vertices");
+                }
+                if vertices.iter().flatten().any(|v| !v.is_finite()) {
+                    return invalid("non finite vertex");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "Vec<CoupledLimit>", into = "Vec<CoupledLimit>")]
+/// Set of constraints coupling the joint positions
+///
+/// Target positions violating the constraints are projected onto the feasible
+/// set (with Dykstra's alternating projections, also enforcing the per-joint
+/// limits). The result is the closest feasible position when all the
+/// constraints are convex, and a nearby feasible position otherwise.
+pub struct CoupledLimits<const N: usize> {
+    constraints: Vec<CoupledLimit>,
+}
+
+impl<const N: usize> CoupledLimits<N> {
+    /// Create a set of constraints, checking they only involve existing joints
+    pub fn new(constraints: Vec<CoupledLimit>) -> Result<Self, InvalidCoupledLimitError> {
+        for constraint in &constraints {
+            constraint.check::<N>()?;
+        }
+        Ok(Self { constraints })
+    }
+
+    /// Constraints of the set
+    pub fn constraints(&self) -> &[CoupledLimit] {
+        &self.constraints
+    }
+
+    /// Check if a position satisfies all the constraints
+    pub fn contains(&self, position: [f64; N]) -> bool {
+        self.constraints.iter().all(|c| c.contains(&position))
+    }
+
+    /// Indices of the constraints violated by a position
+    pub fn violated(&self, position: [f64; N]) -> Vec<usize> {
+        (0..self.constraints.len())
+            .filter(|&k| !self.constraints[k].contains(&position))
+            .collect()
+    }
+
+    /// Project a position onto the feasible set, also enforcing the per-joint limits
+    pub fn project(&self, position: [f64; N], limits: &[Option<Limit>; N]) -> [f64; N] {
+        let clamp = |position: &mut [f64; N]| {
+            for (p, limit) in position.iter_mut().zip(limits) {
+                if let Some(limit) = limit {
+                    *p = limit.clamp(*p);
+                }
+            }
+        };
+
+        let mut projected = position;
+        clamp(&mut projected);
+        if self.contains(projected) {
+            return projected;
+        }
+        projected = position;
+
+        // Dykstra's algorithm, the per-joint limits being the last set
+        let mut increments = vec![[0.0; N]; self.constraints.len() + 1];
+        for _ in 0..MAX_ITERATIONS {
+            let previous = projected;
+
+            for (k, increment) in increments.iter_mut().enumerate() {
+                let mut shifted = projected;
+                for (s, inc) in shifted.iter_mut().zip(increment.iter()) {
+                    *s += inc;
+                }
+                let mut next = shifted;
+                match self.constraints.get(k) {
+                    Some(constraint) => constraint.project(&mut next),
+                    None => clamp(&mut next),
+                }
+                for ((inc, s), n) in increment.iter_mut().zip(shifted).zip(next) {
+                    *inc = s - n;
+                }
+                projected = next;
+            }
+
+            let change = previous
+                .iter()
+                .zip(projected)
+                .map(|(p, n)| (p - n).abs())
+                .fold(0.0, f64::max);
+            if change < TOLERANCE {
+                break;
+            }
+        }
+
+        if !self.contains(projected) {
+            log::warn!(target: "coupled_limits::project", "could not satisfy constraints {:?} for {:?}", self.violated(projected), position);
+        }
+        projected
+    }
+}
+
+impl<const N: usize> TryFrom<Vec<CoupledLimit>> for CoupledLimits<N> {
+    type Error = InvalidCoupledLimitError;
+
+    fn try_from(constraints: Vec<CoupledLimit>) -> Result<Self, Self::Error> {
+        Self::new(constraints)
+    }
+}
+
+impl<const N: usize> From<CoupledLimits<N>> for Vec<CoupledLimit> {
+    fn from(limits: CoupledLimits<N>) -> Self {
+        limits.constraints
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCoupledLimitError(pub String);
+impl std::fmt::Display for InvalidCoupledLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid coupled limit: {reason})")
+    }
+}
+impl std::error::Error for InvalidCoupledLimitError {}
+
+fn dot(coefficients: &[f64], position: &[f64]) -> f64 {
+    coefficients.iter().zip(position).map(|(c, p)| c * p).sum()
+}
+
+/// Even-odd rule point in polygon test
+fn inside_polygon(point: [f64; 2], vertices: &[[f64; 2]]) -> bool {
+    let mut inside = false;
+    let mut j = vertices.len() - 1;
+    for i in 0..vertices.len() {
+        let (a, b) = (vertices[i], vertices[j]);
+        if (a[1] > point[1]) != (b[1] > point[1])
+            && point[0] < (b[0] - a[0]) * (point[1] - a[1]) / (b[1] - a[1]) + a[0]
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Distance from a point to the polygon boundary, and the closest boundary point
+fn distance_to_polygon(point: [f64; 2], vertices: &[[f64; 2]]) -> (f64, [f64; 2]) {
+    let mut best = (f64::INFINITY, point);
+    for i in 0..vertices.len() {
+        let (a, b) = (vertices[i], vertices[(i + 1) % vertices.len()]);
+        let edge = [b[0] - a[0], b[1] - a[1]];
+        let length = edge[0] * edge[0] + edge[1] * edge[1];
+        let t = match length {
+            l if l > 0.0 => {
+                (((point[0] - a[0]) * edge[0] + (point[1] - a[1]) * edge[1]) / l).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        };
+        let closest = [a[0] + t * edge[0], a[1] + t * edge[1]];
+        let distance = (point[0] - closest[0]).hypot(point[1] - closest[1]);
+        if distance < best.0 {
+            best = (distance, closest);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CoupledLimit, CoupledLimits};
+    use crate::test_utils::assert_close;
+    use crate::Limit;
+
+    /// Elbow range shrinking from [-1, 1] to [-0.5, 0.5] as the shoulder goes from 0 to 1
+    fn elbow() -> CoupledLimit {
+        CoupledLimit::Polygon {
+            joints: [0, 1],
+            vertices: vec![[0.0, -1.0], [1.0, -0.5], [1.0, 0.5], [0.0, 1.0]],
+        }
+    }
+
+    #[test]
+    fn linear() {
+        let limits = CoupledLimits::<3>::new(vec![CoupledLimit::Linear {
+            coefficients: vec![1.0, 1.0],
+            max: 1.0,
+        }])
+        .unwrap();
+
+        assert!(limits.contains([0.5, 0.5, 10.0]));
+        assert!(!limits.contains([1.0, 0.5, 0.0]));
+        assert_eq!(limits.violated([1.0, 0.5, 0.0]), vec![0]);
+
+        let projected = limits.project([1.0, 1.0, 2.0], &[None; 3]);
+        assert_close(projected, [0.5, 0.5, 2.0], 1e-6);
+    }
+
+    #[test]
+    fn polygon() {
+        let limits = CoupledLimits::<2>::new(vec![elbow()]).unwrap();
+
+        assert!(limits.contains([0.0, 1.0]));
+        assert!(limits.contains([1.0, 0.5]));
+        assert!(!limits.contains([1.0, 0.8]));
+        assert_eq!(limits.project([0.5, 0.5], &[None; 2]), [0.5, 0.5]);
+        assert_close(limits.project([1.0, 0.8], &[None; 2]), [0.88, 0.56], 1e-6);
+        assert_close(limits.project([2.0, 0.0], &[None; 2]), [1.0, 0.0], 1e-6);
+    }
+
+    #[test]
+    fn with_joint_limits() {
+        let limits = CoupledLimits::<2>::new(vec![CoupledLimit::Linear {
+            coefficients: vec![1.0, -1.0],
+            max: 0.0,
+        }])
+        .unwrap();
+        let joint_limits = [Some(Limit::new(-1.0, 0.5)), None];
+
+        // Projecting (1, 0) onto x <= y gives (0.5, 0.5), within the joint limits
+        assert_close(limits.project([1.0, 0.0], &joint_limits), [0.5, 0.5], 1e-6);
+        // The closest point of x <= y is (1, 1), but x is limited to 0.5
+        assert_close(limits.project([2.0, 0.0], &joint_limits), [0.5, 0.5], 1e-6);
+        assert_close(
+            limits.project([0.8, -2.0], &joint_limits),
+            [-0.6, -0.6],
+            1e-6,
+        );
+        // Both sets are active
+        assert_close(
+            limits.project([-3.0, -4.0], &joint_limits),
+            [-1.0, -1.0],
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(CoupledLimits::<2>::new(vec![CoupledLimit::Linear {
+            coefficients: vec![1.0, 1.0, 1.0],
+            max: 1.0,
+        }])
+        .is_err());
+        assert!(CoupledLimits::<2>::new(vec![CoupledLimit::Linear {
+            coefficients: vec![0.0],
+            max: 1.0,
+        }])
+        .is_err());
+        assert!(CoupledLimits::<2>::new(vec![CoupledLimit::Polygon {
+            joints: [0, 2],
+            vertices: vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]],
+        }])
+        .is_err());
+        assert!(CoupledLimits::<2>::new(vec![CoupledLimit::Polygon {
+            joints: [0, 1],
+            vertices: vec![[0.0, 0.0], [1.0, 0.0]],
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn serde() {
+        let limits = CoupledLimits::<2>::new(vec![
+            elbow(),
+            CoupledLimit::Linear {
+                coefficients: vec![1.0, 1.0],
+                max: 1.5,
+            },
+        ])
+        .unwrap();
+
+        let json = serde_json::to_string(&limits).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CoupledLimits<2>>(&json).unwrap(),
+            limits
+        );
+
+        let json = r#"[{"polygon": {"joints": [0, 3], "vertices": [[0, 0], [1, 0], [0, 1]]}}]"#;
+        assert!(serde_json::from_str::<CoupledLimits<2>>(json).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// How a motor is stopped on emergency stop
+pub enum StopMode {
+    /// Turn the torque off
+    TorqueOff,
+    /// Keep the torque on and hold the current position
+    Hold,
+    /// Keep the torque on and set the target velocity to zero
+    ZeroVelocity,
+}
+
+const CLEAR: u8 = 0;
+const TRIGGERED: u8 = 1;
+const STOPPED: u8 = 2;
+
+type StopAction = Box<dyn Fn() -> Result<()> + Send>;
+
+#[derive(Default)]
+struct Latch {
+    state: AtomicU8,
+    stop_action: Mutex<Option<StopAction>>,
+}
+
+#[derive(Clone, Default)]
+/// Latched emergency stop flag, shared between threads
+///
+/// Triggering it from another thread makes the controller apply its stop
+/// reaction on its next command or feedback read (e.g. the next cycle of a
+/// move in progress) and refuse motion commands until it is reset. To stop
+/// the motors even if the thread driving the controller is stalled, register
+/// a [stop action](Self::set_stop_action): it runs right away on the
+/// triggering thread.
+pub struct EmergencyStop(Arc<Latch>);
+
+impl EmergencyStop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the action run by the thread triggering the emergency stop
+    ///
+    /// It must not need the controller (whose thread may be stalled, or hold
+    /// its lock), e.g. it can use a dedicated handle on the motors bus to turn
+    /// the torque off. The controller still applies its own stop reaction
+    /// afterwards.
+    pub fn set_stop_action(&self, action: impl Fn() -> Result<()> + Send + 'static) {
+        *self.stop_action() = Some(Box::new(action));
+    }
+
+    fn stop_action(&self) -> std::sync::MutexGuard<'_, Option<StopAction>> {
+        self.0
+            .stop_action
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Latch the emergency stop (can be called from any thread)
+    pub fn trigger(&self) {
+        let latched = self
+            .0
+            .state
+            .compare_exchange(CLEAR, TRIGGERED, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok();
+        if !latched {
+            return;
+        }
+        if let Some(action) = self.stop_action().as_ref() {
+            if let Err(e) = action() {
+                log::error!(target: "emergency_stop::trigger", "stop action failed: {}", e);
+            }
+        }
+    }
+
+    /// Check if the emergency stop is latched
+    pub fn is_triggered(&self) -> bool {
+        self.0.state.load(Ordering::SeqCst) != CLEAR
+    }
+
+    /// Release the latch
+    ///
+    /// Refused while the stop reaction of the controller has not been applied
+    /// yet, use [MotorsController::reset_emergency_stop](crate::MotorsController::reset_emergency_stop)
+    /// to apply it first.
+    pub fn reset(&self) -> Result<()> {
+        match self
+            .0
+            .state
+            .compare_exchange(STOPPED, CLEAR, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Err(TRIGGERED) => Err(Box::new(PendingStopError)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check if the stop reaction still needs to be applied, and mark it as applied
+    pub(crate) fn take_pending_stop(&self) -> bool {
+        self.0
+            .state
+            .compare_exchange(TRIGGERED, STOPPED, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+}
+
+impl std::fmt::Debug for EmergencyStop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmergencyStop")
+            .field("triggered", &self.is_triggered())
+            .field("stop_action", &self.stop_action().is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct EmergencyStopError;
+impl std::fmt::Display for EmergencyStopError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(emergency stop latched, reset required)")
+    }
+}
+impl std::error::Error for EmergencyStopError {}
+
+#[derive(Debug)]
+pub struct PendingStopError;
+impl std::fmt::Display for PendingStopError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(emergency stop not applied yet, cannot reset)")
+    }
+}
+impl std::error::Error for PendingStopError {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::{EmergencyStop, PendingStopError};
+
+    #[test]
+    fn latch() {
+        let estop = EmergencyStop::new();
+        let other = estop.clone();
+        assert!(!estop.is_triggered());
+        assert!(!estop.take_pending_stop());
+
+        std::thread::spawn(move || other.trigger()).join().unwrap();
+        assert!(estop.is_triggered());
+        assert!(estop.take_pending_stop());
+        assert!(!estop.take_pending_stop());
+
+        // Triggering again does not require a new stop
+        estop.trigger();
+        assert!(estop.is_triggered());
+        assert!(!estop.take_pending_stop());
+
+        estop.reset().unwrap();
+        assert!(!estop.is_triggered());
+
+        // Not released before the stop is applied
+        estop.trigger();
+        assert!(estop.reset().unwrap_err().is::<PendingStopError>());
+        assert!(estop.is_triggered());
+        assert!(estop.take_pending_stop());
+        estop.reset().unwrap();
+        assert!(!estop.is_triggered());
+    }
+
+    #[test]
+    fn stop_action() {
+        let estop = EmergencyStop::new();
+        let stops = Arc::new(AtomicUsize::new(0));
+        let counter = stops.clone();
+        estop.set_stop_action(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        // Run once, on the triggering thread, without the controller
+        let other = estop.clone();
+        std::thread::spawn(move || other.trigger()).join().unwrap();
+        estop.trigger();
+        assert_eq!(stops.load(Ordering::SeqCst), 1);
+        assert!(estop.take_pending_stop());
+
+        estop.reset().unwrap();
+        estop.trigger();
+        assert_eq!(stops.load(Ordering::SeqCst), 2);
+    }
+}
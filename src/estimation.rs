@@ -0,0 +1,285 @@
+use std::f64::consts::TAU;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Velocity estimation from timestamped positions
+pub enum VelocityEstimator {
+    /// Difference between the last two positions
+    FiniteDifference,
+    /// Finite difference smoothed by a first order low-pass filter
+    LowPass {
+        /// Cutoff frequency (in Hz)
+        cutoff: f64,
+    },
+    /// Kalman filter with a constant velocity model
+    Kalman {
+        /// Acceleration noise density (in rad²/s³)
+        process_noise: f64,
+        /// Position measurement variance (in rad²)
+        measurement_noise: f64,
+    },
+}
+
+/// Initial variance of the velocity estimated by the Kalman filter (in rad²/s²)
+const INITIAL_VELOCITY_VARIANCE: f64 = 100.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct JointState {
+    time: Option<Instant>,
+    position: f64,
+    difference: f64,
+    velocity: f64,
+    covariance: [[f64; 2]; 2],
+}
+
+impl JointState {
+    fn update(&mut self, estimator: Option<VelocityEstimator>, position: f64, dt: f64) {
+        self.difference = (position - self.position) / dt;
+
+        match estimator {
+            None | Some(VelocityEstimator::FiniteDifference) => {
+                self.position = position;
+                self.velocity = self.difference;
+            }
+            Some(VelocityEstimator::LowPass { cutoff }) => {
+                let alpha = low_pass_alpha(cutoff, dt);
+                self.position = position;
+                self.velocity += alpha * (self.difference - self.velocity);
+            }
+            Some(VelocityEstimator::Kalman {
+                process_noise: q,
+                measurement_noise: r,
+            }) => {
+                // Prediction
+                let p = self.covariance;
+                let predicted = self.position + self.velocity * dt;
+                let p00 =
+                    p[0][0] + dt * (p[0][1] + p[1][0]) + dt * dt * p[1][1] + q * dt.powi(3) / 3.0;
+                let p01 = p[0][1] + dt * p[1][1] + q * dt * dt / 2.0;
+                let p11 = p[1][1] + q * dt;
+
+                // Correction with the measured position
+                let innovation = position - predicted;
+                let s = p00 + r;
+                let (k0, k1) = (p00 / s, p01 / s);
+                self.position = predicted + k0 * innovation;
+                self.velocity += k1 * innovation;
+                self.covariance = [
+                    [(1.0 - k0) * p00, (1.0 - k0) * p01],
+                    [(1.0 - k0) * p01, p11 - k1 * p01],
+                ];
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Estimation of the joint velocities and filtering of the torque feedback
+///
+/// Positions are fed by the controller each time they are read. Joints without
+/// estimator keep the velocity reported by the motors, unless it is NaN, in
+/// which case the finite difference estimate is used.
+pub struct FeedbackFilter<const N: usize> {
+    estimators: [Option<VelocityEstimator>; N],
+    torque_cutoff: [Option<f64>; N],
+    min_period: Duration,
+
+    states: [JointState; N],
+    torque: [Option<(Instant, f64)>; N],
+}
+
+impl<const N: usize> FeedbackFilter<N> {
+    /// Create a filter with the velocity estimator of each joint
+    pub fn new(estimators: [Option<VelocityEstimator>; N]) -> Self {
+        Self {
+            estimators,
+            torque_cutoff: [None; N],
+            min_period: Duration::from_millis(1),
+
+            states: [JointState::default(); N],
+            torque: [None; N],
+        }
+    }
+
+    /// Low-pass filter the torque readings (cutoff frequencies in Hz)
+    pub fn with_torque_filter(mut self, cutoff: [Option<f64>; N]) -> Self {
+        self.torque_cutoff = cutoff;
+        self
+    }
+
+    /// Ignore positions read less than `min_period` after the previous one (1ms by default)
+    ///
+    /// This avoids amplifying the noise when positions are read several times
+    /// in the same cycle.
+    pub fn with_min_period(mut self, min_period: Duration) -> Self {
+        self.min_period = min_period;
+        self
+    }
+
+    /// Velocity estimator of each joint
+    pub fn estimators(&self) -> [Option<VelocityEstimator>; N] {
+        self.estimators
+    }
+
+    /// Forget the previous samples
+    pub fn reset(&mut self) {
+        self.states = [JointState::default(); N];
+        self.torque = [None; N];
+    }
+
+    /// Feed positions read at `time` (in radians)
+    pub fn update_position(&mut self, position: [f64; N], time: Instant) {
+        for ((state, estimator), position) in
+            self.states.iter_mut().zip(self.estimators).zip(position)
+        {
+            if !position.is_finite() {
+                continue;
+            }
+            let last = match state.time {
+                Some(last) => last,
+                None => {
+                    *state = JointState {
+                        time: Some(time),
+                        position,
+                        difference: f64::NAN,
+                        covariance: [[0.0, 0.0], [0.0, INITIAL_VELOCITY_VARIANCE]],
+                        ..Default::default()
+                    };
+                    continue;
+                }
+            };
+            let dt = time.saturating_duration_since(last);
+            if dt < self.min_period || dt.is_zero() {
+                continue;
+            }
+
+            state.update(estimator, position, dt.as_secs_f64());
+            state.time = Some(time);
+        }
+    }
+
+    /// Estimated velocities (in rad/s), using the reported ones for the joints without estimator
+    pub fn velocity(&self, reported: [f64; N]) -> [f64; N] {
+        let mut velocity = reported;
+        for i in 0..N {
+            let state = &self.states[i];
+            if self.estimators[i].is_some() {
+                velocity[i] = state.velocity;
+            } else if reported[i].is_nan() && state.time.is_some() {
+                velocity[i] = state.difference;
+            }
+        }
+        velocity
+    }
+
+    /// Filter torques read at `time` (in Nm)
+    pub fn filter_torque(&mut self, torque: [f64; N], time: Instant) -> [f64; N] {
+        let mut filtered = torque;
+        for i in 0..N {
+            let cutoff = match self.torque_cutoff[i] {
+                Some(cutoff) if torque[i].is_finite() => cutoff,
+                _ => continue,
+            };
+            if let Some((last, value)) = self.torque[i] {
+                let dt = time.saturating_duration_since(last).as_secs_f64();
+                filtered[i] = value + low_pass_alpha(cutoff, dt) * (torque[i] - value);
+            }
+            self.torque[i] = Some((time, filtered[i]));
+        }
+        filtered
+    }
+}
+
+/// Smoothing factor of a first order low-pass filter
+fn low_pass_alpha(cutoff: f64, dt: f64) -> f64 {
+    let rc = 1.0 / (TAU * cutoff);
+    dt / (dt + rc)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FeedbackFilter, VelocityEstimator};
+
+    /// Feed 2s of positions of joints moving at 1 rad/s, with alternating noise
+    fn feed<const N: usize>(filter: &mut FeedbackFilter<N>, noise: f64) {
+        let start = Instant::now();
+        for k in 0..200 {
+            let time = start + Duration::from_millis(10 * k);
+            let t = k as f64 * 0.01;
+            let noise = if k % 2 == 0 { noise } else { -noise };
+            filter.update_position([t + noise; N], time);
+        }
+    }
+
+    #[test]
+    fn finite_difference() {
+        let mut filter = FeedbackFilter::new([Some(VelocityEstimator::FiniteDifference), None]);
+        assert_eq!(filter.velocity([0.5, f64::NAN])[0], 0.0);
+
+        feed(&mut filter, 0.0);
+        let velocity = filter.velocity([0.5, f64::NAN]);
+        assert!((velocity[0] - 1.0).abs() < 1e-6);
+        // NaN reported velocities fall back to the finite difference
+        assert!((velocity[1] - 1.0).abs() < 1e-6);
+        // Valid reported velocities are kept
+        assert_eq!(filter.velocity([0.5, 0.5])[1], 0.5);
+
+        filter.reset();
+        assert!(filter.velocity([0.5, f64::NAN])[1].is_nan());
+    }
+
+    #[test]
+    fn noise() {
+        let mut finite_difference =
+            FeedbackFilter::new([Some(VelocityEstimator::FiniteDifference)]);
+        let mut low_pass = FeedbackFilter::new([Some(VelocityEstimator::LowPass { cutoff: 2.0 })]);
+        let mut kalman = FeedbackFilter::new([Some(VelocityEstimator::Kalman {
+            process_noise: 1.0,
+            measurement_noise: 1e-4,
+        })]);
+
+        feed(&mut finite_difference, 0.001);
+        feed(&mut low_pass, 0.001);
+        feed(&mut kalman, 0.001);
+
+        let error = |filter: &FeedbackFilter<1>| (filter.velocity([f64::NAN])[0] - 1.0).abs();
+        assert!(error(&finite_difference) > 0.15);
+        assert!(error(&low_pass) < 0.05);
+        assert!(error(&kalman) < 0.05);
+    }
+
+    #[test]
+    fn min_period() {
+        let mut filter = FeedbackFilter::new([Some(VelocityEstimator::FiniteDifference)])
+            .with_min_period(Duration::from_millis(5));
+        let start = Instant::now();
+
+        filter.update_position([0.0], start);
+        filter.update_position([0.01], start + Duration::from_millis(10));
+        // Read again in the same cycle
+        filter.update_position([0.0101], start + Duration::from_micros(10100));
+        assert!((filter.velocity([f64::NAN])[0] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn torque() {
+        let mut filter = FeedbackFilter::<2>::new([None; 2]).with_torque_filter([Some(1.0), None]);
+        let start = Instant::now();
+
+        assert_eq!(filter.filter_torque([0.0, 0.0], start), [0.0, 0.0]);
+        let torque = filter.filter_torque([1.0, 1.0], start + Duration::from_millis(10));
+        assert!(torque[0] > 0.0 && torque[0] < 0.1);
+        assert_eq!(torque[1], 1.0);
+
+        let mut torque = torque;
+        for k in 2..1000 {
+            torque = filter.filter_torque([1.0, 1.0], start + Duration::from_millis(10 * k));
+        }
+        assert!((torque[0] - 1.0).abs() < 1e-3);
+    }
+}
@@ -2,13 +2,29 @@ use itertools::izip;
 
 use crate::motors_controller::MotorsController;
 use crate::motors_io::RawMotorsIO;
-use crate::{Limit, Result, PID};
+use crate::{
+    CoupledLimits, EmergencyStop, FeedbackFilter, Feedforward, GainCapabilities, Gains,
+    ImpedanceCommand, Limit, LookupTable, MissingRegisterErrror, MotorsConfig, OffsetSpace,
+    PidController, Result, SoftLimit, SoftLimits, StopMode, Transmission, Unwrapper, PID,
+};
 
 #[derive(Debug)]
 pub struct FakeMotorsController<const N: usize> {
     offsets: [Option<f64>; N],
     reduction: [Option<f64>; N],
+    inverted: [bool; N],
+    offset_space: OffsetSpace,
     limits: [Option<Limit>; N],
+    coupled_limits: Option<CoupledLimits<N>>,
+    transmission: Option<Transmission<N>>,
+    lookup_tables: Option<[Option<LookupTable>; N]>,
+    unwrapper: Option<Unwrapper<N>>,
+    soft_limits: Option<SoftLimits<N>>,
+    feedback_filter: Option<FeedbackFilter<N>>,
+    pid_controllers: Option<[Option<PidController>; N]>,
+    feedforward: Option<Feedforward<N>>,
+    stop_modes: [StopMode; N],
+    emergency_stop: EmergencyStop,
 
     io: FakeMotorsIO<N>,
 }
@@ -18,6 +34,21 @@ impl<const N: usize> FakeMotorsController<N> {
         Self::default()
     }
 
+    /// Create a fake controller from a configuration
+    pub fn from_config(config: &MotorsConfig<N>) -> Self {
+        let motors = Self::new()
+            .with_offsets(config.offsets)
+            .with_reduction(config.reduction)
+            .with_inverted(config.inverted)
+            .with_offset_space(config.offset_space)
+            .with_limits(config.limits)
+            .with_stop_modes(config.stop_modes);
+        match config.lookup_tables.iter().any(Option::is_some) {
+            true => motors.with_lookup_tables(config.lookup_tables.clone()),
+            false => motors,
+        }
+    }
+
     pub fn with_offsets(mut self, offsets: [Option<f64>; N]) -> Self {
         self.offsets = offsets;
         self
@@ -28,10 +59,90 @@ impl<const N: usize> FakeMotorsController<N> {
         self
     }
 
+    /// Reverse the direction of the selected joints relative to their motor
+    pub fn with_inverted(mut self, inverted: [bool; N]) -> Self {
+        self.inverted = inverted;
+        self
+    }
+
+    /// Express the offsets in joint or in motor space
+    pub fn with_offset_space(mut self, offset_space: OffsetSpace) -> Self {
+        self.offset_space = offset_space;
+        self
+    }
+
     pub fn with_limits(mut self, limits: [Option<Limit>; N]) -> Self {
         self.limits = limits;
         self
     }
+
+    pub fn with_coupled_limits(mut self, coupled_limits: CoupledLimits<N>) -> Self {
+        self.coupled_limits = Some(coupled_limits);
+        self
+    }
+
+    /// Couple the motors to the joints, instead of the offsets and reductions
+    pub fn with_transmission(mut self, transmission: Transmission<N>) -> Self {
+        self.transmission = Some(transmission);
+        self
+    }
+
+    /// Convert the positions of the joints with a table through their nonlinear transmission
+    pub fn with_lookup_tables(mut self, lookup_tables: [Option<LookupTable>; N]) -> Self {
+        self.lookup_tables = Some(lookup_tables);
+        self
+    }
+
+    /// Unwrap the positions of the motors reporting positions wrapped to [-π, π)
+    pub fn with_unwrapping(mut self, wrapped: [bool; N]) -> Self {
+        self.unwrapper = Some(Unwrapper::new(wrapped));
+        self
+    }
+
+    /// Slow down the joints approaching their hard limits
+    pub fn with_soft_limits(mut self, soft_limits: [Option<SoftLimit>; N]) -> Self {
+        self.soft_limits = Some(SoftLimits::new(soft_limits));
+        self
+    }
+
+    /// Estimate the velocities and filter the torques read from the fake io
+    pub fn with_feedback_filter(mut self, feedback_filter: FeedbackFilter<N>) -> Self {
+        self.feedback_filter = Some(feedback_filter);
+        self
+    }
+
+    /// Track positions with software PID controllers (see [update_pid_controllers](MotorsController::update_pid_controllers))
+    pub fn with_pid_controllers(mut self, pid_controllers: [Option<PidController>; N]) -> Self {
+        self.pid_controllers = Some(pid_controllers);
+        self
+    }
+
+    /// Compensate the friction and the gravity in the torque and impedance commands
+    pub fn with_feedforward(mut self, feedforward: Feedforward<N>) -> Self {
+        self.feedforward = Some(feedforward);
+        self
+    }
+
+    pub fn with_stop_modes(mut self, stop_modes: [StopMode; N]) -> Self {
+        self.stop_modes = stop_modes;
+        self
+    }
+
+    pub fn with_hard_stops(mut self, hard_stops: [Option<Limit>; N]) -> Self {
+        self.io.hard_stops = hard_stops;
+        self
+    }
+
+    /// Simulate the dynamics of the motors (see [FakeMotorsIO::step])
+    pub fn with_dynamics(mut self, dynamics: [Option<FakeDynamics>; N]) -> Self {
+        self.io.set_dynamics(dynamics);
+        self
+    }
+
+    /// Access the underlying fake io (e.g. to simulate external events)
+    pub fn fake_io(&mut self) -> &mut FakeMotorsIO<N> {
+        &mut self.io
+    }
 }
 
 impl<const N: usize> Default for FakeMotorsController<N> {
@@ -39,7 +150,19 @@ impl<const N: usize> Default for FakeMotorsController<N> {
         Self {
             offsets: [None; N],
             reduction: [None; N],
+            inverted: [false; N],
+            offset_space: OffsetSpace::Joint,
             limits: [None; N],
+            coupled_limits: None,
+            transmission: None,
+            lookup_tables: None,
+            unwrapper: None,
+            soft_limits: None,
+            feedback_filter: None,
+            pid_controllers: None,
+            feedforward: None,
+            stop_modes: [StopMode::TorqueOff; N],
+            emergency_stop: EmergencyStop::new(),
 
             io: FakeMotorsIO::<N>::default(),
         }
@@ -55,10 +178,63 @@ impl<const N: usize> MotorsController<N> for FakeMotorsController<N> {
         self.reduction
     }
 
+    fn inverted(&self) -> [bool; N] {
+        self.inverted
+    }
+
+    fn offset_space(&self) -> OffsetSpace {
+        self.offset_space
+    }
+
     fn limits(&self) -> [Option<Limit>; N] {
         self.limits
     }
 
+    fn coupled_limits(&self) -> Option<&CoupledLimits<N>> {
+        self.coupled_limits.as_ref()
+    }
+
+    fn transmission(&self) -> Option<&Transmission<N>> {
+        self.transmission.as_ref()
+    }
+
+    fn lookup_tables(&self) -> Option<&[Option<LookupTable>; N]> {
+        self.lookup_tables.as_ref()
+    }
+
+    fn set_offsets(&mut self, offsets: [Option<f64>; N]) -> Result<()> {
+        self.offsets = offsets;
+        Ok(())
+    }
+
+    fn unwrapper(&mut self) -> Option<&mut Unwrapper<N>> {
+        self.unwrapper.as_mut()
+    }
+
+    fn soft_limits(&mut self) -> Option<&mut SoftLimits<N>> {
+        self.soft_limits.as_mut()
+    }
+
+    fn feedback_filter(&mut self) -> Option<&mut FeedbackFilter<N>> {
+        self.feedback_filter.as_mut()
+    }
+
+    fn pid_controllers(&mut self) -> Option<&mut [Option<PidController>; N]> {
+        self.pid_controllers.as_mut()
+    }
+
+    fn feedforward(&self) -> Option<&Feedforward<N>> {
+        self.feedforward.as_ref()
+    }
+
+    fn emergency_stop_latch(&self) -> Option<&EmergencyStop> {
+        Some(&self.emergency_stop)
+    }
+
+    fn stop_modes(&self) -> [StopMode; N] {
+        self.stop_modes
+    }
+
     fn io(&mut self) -> &mut dyn RawMotorsIO<N> {
         &mut self.io
     }
@@ -77,12 +253,40 @@ pub struct FakeMotorsIO<const N: usize> {
     target_torque: [f64; N],
     target_velocity: [f64; N],
     control_mode: [u8; N],
+    impedance: [Option<ImpedanceCommand>; N],
+    impedance_support: bool,
+    gain_capabilities: GainCapabilities,
 
     velocity_limit: [f64; N],
     torque_limit: [f64; N],
     pid: [PID; N],
+    gains: [Gains; N],
+
+    max_torque_jump: [f64; N],
+    torque_limit_history: Vec<[f64; N]>,
+    lagging: [bool; N],
+    hard_stops: [Option<Limit>; N],
+    blocked: [bool; N],
+    dynamics: [Option<FakeDynamics>; N],
+    servos: [PidController; N],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Rigid body model of a motor, simulated by [FakeMotorsIO::step]
+pub struct FakeDynamics {
+    /// Inertia seen by the motor (in kg·m²)
+    pub inertia: f64,
+    /// Viscous friction (in Nm·s/rad)
+    pub damping: f64,
+    /// Coulomb friction (in Nm)
+    pub friction: f64,
 }
 
+/// Torque applied against a hard stop when the torque limit is infinite (in Nm)
+const STALL_TORQUE: f64 = 1.0;
+/// Torque needed to hold a motor one radian away from its target (in Nm/rad)
+const HOLDING_STIFFNESS: f64 = 1.0;
+
 impl<const N: usize> Default for FakeMotorsIO<N> {
     fn default() -> Self {
         Self {
@@ -97,6 +301,9 @@ impl<const N: usize> Default for FakeMotorsIO<N> {
             target_velocity: [f64::NAN; N],
 
             control_mode: [0; N],
+            impedance: [None; N],
+            impedance_support: true,
+            gain_capabilities: GainCapabilities::ALL,
 
             velocity_limit: [f64::INFINITY; N],
             torque_limit: [f64::INFINITY; N],
@@ -105,10 +312,192 @@ impl<const N: usize> Default for FakeMotorsIO<N> {
                 i: f64::NAN,
                 d: f64::NAN,
             }; N],
+            gains: [Gains::default(); N],
+
+            max_torque_jump: [0.0; N],
+            torque_limit_history: Vec::new(),
+            lagging: [false; N],
+            hard_stops: [None; N],
+            blocked: [false; N],
+            dynamics: [None; N],
+            servos: [PidController::new(PID {
+                p: 0.0,
+                i: 0.0,
+                d: 0.0,
+            }); N],
         }
     }
 }
 
+impl<const N: usize> FakeMotorsIO<N> {
+    /// Move the motors from the outside (e.g. by hand), whatever the torque state
+    pub fn set_current_position(&mut self, position: [f64; N]) {
+        log::debug!(target: "fake_io::set_current_position", "Moving current position to {:?}", position);
+        self.current_position = position;
+        self.blocked = [false; N];
+    }
+
+    /// Apply an external load on the motors, read as their current torque until the next step
+    pub fn set_current_torque(&mut self, torque: [f64; N]) {
+        log::debug!(target: "fake_io::set_current_torque", "Setting current torque to {:?}", torque);
+        self.current_torque = torque;
+    }
+
+    /// Simulate motors without native impedance commands (to exercise the software fallback)
+    pub fn set_impedance_support(&mut self, supported: bool) {
+        self.impedance_support = supported;
+    }
+
+    /// Simulate motors honoring only some of the extended gains
+    pub fn set_gain_capabilities(&mut self, capabilities: GainCapabilities) {
+        self.gain_capabilities = capabilities;
+    }
+
+    /// Simulate the dynamics of the motors instead of moving them instantly to their targets
+    ///
+    /// Motors with dynamics follow their impedance command when one is set.
+    /// Otherwise they track their target position with a software PID
+    /// controller using their PID gains when these are finite, or else apply
+    /// their target torque. They only move when [step](Self::step) is called.
+    pub fn set_dynamics(&mut self, dynamics: [Option<FakeDynamics>; N]) {
+        self.dynamics = dynamics;
+        for (velocity, torque, dynamics) in izip!(
+            &mut self.current_velocity,
+            &mut self.current_torque,
+            dynamics
+        ) {
+            if dynamics.is_some() && velocity.is_nan() {
+                *velocity = 0.0;
+                *torque = 0.0;
+            }
+        }
+    }
+
+    /// Advance the simulation by `dt` seconds
+    ///
+    /// Motors with torque on move at their current velocity. When they push
+    /// against a hard stop, they are blocked: their current velocity reads zero
+    /// and their current torque saturates at the torque limit. Motors with dynamics are accelerated by their motor
+    /// torque and slowed down by friction, even with torque off.
+    pub fn step(&mut self, dt: f64) {
+        for i in 0..N {
+            if let Some(dynamics) = self.dynamics[i] {
+                self.step_dynamics(i, dynamics, dt);
+                continue;
+            }
+
+            let velocity = self.current_velocity[i];
+            if !self.torque_on[i] || !velocity.is_finite() {
+                continue;
+            }
+
+            let position = self.current_position[i] + velocity * dt;
+            self.current_position[i] = position;
+            self.current_torque[i] = 0.0;
+            self.blocked[i] = false;
+
+            if let Some(stop) = self.hard_stops[i] {
+                if stop.clamp(position) != position {
+                    let stall = match self.torque_limit[i] {
+                        limit if limit.is_finite() => limit,
+                        _ => STALL_TORQUE,
+                    };
+                    log::debug!(target: "fake_io::step", "Motor {} blocked by hard stop {:?}", i, stop);
+                    self.current_position[i] = stop.clamp(position);
+                    self.current_torque[i] = stall.copysign(velocity);
+                    self.blocked[i] = true;
+                }
+            }
+        }
+    }
+
+    fn step_dynamics(&mut self, i: usize, dynamics: FakeDynamics, dt: f64) {
+        let torque_limit = Limit::new(-self.torque_limit[i].abs(), self.torque_limit[i].abs());
+        let pid = self.pid[i];
+        let torque = match self.torque_on[i] {
+            false => 0.0,
+            true if self.impedance[i].is_some() => {
+                let command = self.impedance[i].unwrap_or_default();
+                torque_limit
+                    .clamp(command.torque(self.current_position[i], self.current_velocity[i]))
+            }
+            true if pid.p.is_finite() && pid.i.is_finite() && pid.d.is_finite() => {
+                let servo = &mut self.servos[i];
+                servo.set_gains(pid);
+                servo.set_output_limit(torque_limit);
+                servo.update(self.target_position[i], self.current_position[i], dt)
+            }
+            true if self.target_torque[i].is_finite() => torque_limit.clamp(self.target_torque[i]),
+            true => 0.0,
+        };
+
+        let velocity = self.current_velocity[i];
+        let mut accel_torque = torque - dynamics.damping * velocity;
+        if velocity != 0.0 {
+            accel_torque -= dynamics.friction.copysign(velocity);
+        } else if accel_torque.abs() <= dynamics.friction {
+            accel_torque = 0.0;
+        } else {
+            accel_torque -= dynamics.friction.copysign(accel_torque);
+        }
+
+        let mut new_velocity = velocity + accel_torque / dynamics.inertia * dt;
+        if velocity != 0.0 && new_velocity.signum() != velocity.signum() {
+            // Friction stops the motor, it does not reverse it
+            new_velocity = 0.0;
+        }
+        let position = self.current_position[i] + new_velocity * dt;
+
+        self.current_torque[i] = torque;
+        self.current_velocity[i] = new_velocity;
+        self.current_position[i] = position;
+        if let Some(stop) = self.hard_stops[i] {
+            if stop.clamp(position) != position {
+                log::debug!(target: "fake_io::step", "Motor {} blocked by hard stop {:?}", i, stop);
+                self.current_position[i] = stop.clamp(position);
+                self.current_velocity[i] = 0.0;
+            }
+        }
+    }
+
+    /// Pull a motor toward its stale target, as far as its torque limit allows
+    fn pull_to_target(&mut self, i: usize) {
+        let error = self.target_position[i] - self.current_position[i];
+        let reach = self.torque_limit[i].abs() / HOLDING_STIFFNESS;
+        let jump = error.clamp(-reach, reach);
+        if jump != 0.0 {
+            log::warn!(target: "fake_io::set_torque", "Motor {} pulled by a stale target, jumping by {:?}", i, jump);
+        }
+        self.max_torque_jump[i] = self.max_torque_jump[i].max(jump.abs());
+        self.current_position[i] += jump;
+        self.lagging[i] = jump != error;
+    }
+
+    fn apply_hard_stops(&mut self) {
+        for (cur, stop) in self.current_position.iter_mut().zip(self.hard_stops) {
+            if let Some(stop) = stop {
+                *cur = stop.clamp(*cur);
+            }
+        }
+    }
+
+    /// Largest position jump caused by enabling the torque on a stale target (in radians)
+    ///
+    /// Like real motors, enabling the torque makes the motor jump to its target
+    /// position. With a finite torque limit, it stops short of the target (a
+    /// torque of 1 Nm holds it 1 radian away) and raising the limit pulls it
+    /// further, so ramping the limit splits the jump. Tests can check this stays
+    /// at zero to catch unsafe enable orders.
+    pub fn max_torque_jump(&self) -> [f64; N] {
+        self.max_torque_jump
+    }
+
+    /// Every torque limit written to the motors, in order
+    pub fn torque_limit_history(&self) -> &[[f64; N]] {
+        &self.torque_limit_history
+    }
+}
+
 impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
     fn is_torque_on(&mut self) -> Result<[bool; N]> {
         Ok(self.torque_on)
@@ -117,19 +506,16 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
     fn set_torque(&mut self, on: [bool; N]) -> Result<()> {
         log::debug!(target: "fake_io::set_torque", "Setting torque to {:?}", on);
 
-        for (cur, target, on, torque_on) in izip!(
-            &mut self.current_position,
-            self.target_position,
-            on,
-            self.torque_on
-        ) {
-            if on && !torque_on {
-                log::debug!(target: "fake_io::set_torque", "Setting current position to target position {:?}", target);
-                *cur = target;
+        for (i, on) in on.iter().enumerate() {
+            if *on && !self.torque_on[i] && self.dynamics[i].is_some() {
+                self.servos[i].reset();
+            } else if *on && !self.torque_on[i] {
+                self.pull_to_target(i);
             }
         }
 
         self.torque_on = on;
+        self.apply_hard_stops();
 
         Ok(())
     }
@@ -139,7 +525,14 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
     }
 
     fn get_current_velocity(&mut self) -> Result<[f64; N]> {
-        Ok(self.current_velocity)
+        // A motor blocked by a hard stop keeps pushing but does not move
+        let mut velocity = self.current_velocity;
+        for (v, blocked) in velocity.iter_mut().zip(self.blocked) {
+            if blocked {
+                *v = 0.0;
+            }
+        }
+        Ok(velocity)
     }
 
     fn get_current_torque(&mut self) -> Result<[f64; N]> {
@@ -155,13 +548,23 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
     }
 
     fn set_target_torque(&mut self, target_torque: [f64; N]) -> Result<()> {
+        self.set_target_torque_partial(target_torque.map(Some))
+    }
+
+    fn set_target_torque_partial(&mut self, target_torque: [Option<f64>; N]) -> Result<()> {
         log::debug!(target: "fake_io::set_target_torque", "Setting target_torque to {:?}", target_torque);
-        self.target_torque = target_torque;
 
-        for (cur, on, target) in izip!(&mut self.current_torque, self.torque_on, target_torque) {
-            if on {
+        for (i, target) in target_torque.into_iter().enumerate() {
+            let Some(target) = target else {
+                continue;
+            };
+            self.target_torque[i] = target;
+            self.impedance[i] = None;
+            self.lagging[i] = false;
+
+            if self.torque_on[i] && self.dynamics[i].is_none() {
                 log::debug!(target: "fake_io::set_target_torque", "Setting current torque to target torque {:?} (torque on)", target);
-                *cur = target;
+                self.current_torque[i] = target;
             } else {
                 log::debug!(target: "fake_io::set_target_torque", "Current torque unchanged (torque off)");
             }
@@ -175,14 +578,23 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
     }
 
     fn set_target_velocity(&mut self, target_velocity: [f64; N]) -> Result<()> {
+        self.set_target_velocity_partial(target_velocity.map(Some))
+    }
+
+    fn set_target_velocity_partial(&mut self, target_velocity: [Option<f64>; N]) -> Result<()> {
         log::debug!(target: "fake_io::set_target_velocity", "Setting target_velocity to {:?}", target_velocity);
-        self.target_velocity = target_velocity;
 
-        for (cur, on, target) in izip!(&mut self.current_velocity, self.torque_on, target_velocity)
-        {
-            if on {
+        for (i, target) in target_velocity.into_iter().enumerate() {
+            let Some(target) = target else {
+                continue;
+            };
+            self.target_velocity[i] = target;
+            self.impedance[i] = None;
+            self.lagging[i] = false;
+
+            if self.torque_on[i] && self.dynamics[i].is_none() {
                 log::debug!(target: "fake_io::set_target_velocity", "Setting current velocity to target velocity {:?} (velocity on)", target);
-                *cur = target;
+                self.current_velocity[i] = target;
             } else {
                 log::debug!(target: "fake_io::set_target_velocity", "Current velocity unchanged (velocity off)");
             }
@@ -204,19 +616,59 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
         Ok(())
     }
 
+    fn supports_impedance(&self) -> bool {
+        self.impedance_support
+    }
+
+    fn set_impedance_command(&mut self, command: [ImpedanceCommand; N]) -> Result<()> {
+        if !self.impedance_support {
+            return Err(Box::new(MissingRegisterErrror(
+                "impedance_command".to_string(),
+            )));
+        }
+        log::debug!(target: "fake_io::set_impedance_command", "Setting impedance_command to {:?}", command);
+        self.impedance = command.map(Some);
+        self.lagging = [false; N];
+
+        for (i, command) in command.iter().enumerate() {
+            self.target_position[i] = command.position;
+            self.target_velocity[i] = command.velocity;
+            self.target_torque[i] = command.torque;
+            if self.torque_on[i] && self.dynamics[i].is_none() {
+                log::debug!(target: "fake_io::set_impedance_command", "Motor {} tracking impedance command perfectly (torque on)", i);
+                self.current_position[i] = command.position;
+                self.current_velocity[i] = command.velocity;
+                self.current_torque[i] = command.torque;
+            }
+        }
+        self.apply_hard_stops();
+
+        Ok(())
+    }
+
     fn set_target_position(&mut self, target_position: [f64; N]) -> Result<()> {
+        self.set_target_position_partial(target_position.map(Some))
+    }
+
+    fn set_target_position_partial(&mut self, target_position: [Option<f64>; N]) -> Result<()> {
         log::debug!(target: "fake_io::set_target_position", "Setting target_position to {:?}", target_position);
-        self.target_position = target_position;
 
-        for (cur, on, target) in izip!(&mut self.current_position, self.torque_on, target_position)
-        {
-            if on {
+        for (i, target) in target_position.into_iter().enumerate() {
+            let Some(target) = target else {
+                continue;
+            };
+            self.target_position[i] = target;
+            self.impedance[i] = None;
+            self.lagging[i] = false;
+
+            if self.torque_on[i] && self.dynamics[i].is_none() {
                 log::debug!(target: "fake_io::set_target_position", "Setting current position to target position {:?} (torque on)", target);
-                *cur = target;
+                self.current_position[i] = target;
             } else {
                 log::debug!(target: "fake_io::set_target_position", "Current position unchanged (torque off)");
             }
         }
+        self.apply_hard_stops();
 
         Ok(())
     }
@@ -224,17 +676,24 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
     fn set_target_position_fb(&mut self, target_position: [f64; N]) -> Result<[f64; N]> {
         log::debug!(target: "fake_io::set_target_position", "Setting target_position to {:?}", target_position);
         self.target_position = target_position;
+        self.impedance = [None; N];
+        self.lagging = [false; N];
         let mut fb: [f64; N] = [0.0; { N }];
 
-        for (cur, on, target) in izip!(&mut self.current_position, self.torque_on, target_position)
-        {
-            if on {
+        for (cur, on, target, dynamics) in izip!(
+            &mut self.current_position,
+            self.torque_on,
+            target_position,
+            self.dynamics
+        ) {
+            if on && dynamics.is_none() {
                 log::debug!(target: "fake_io::set_target_position", "Setting current position to target position {:?} (torque on)", target);
                 *cur = target;
             } else {
                 log::debug!(target: "fake_io::set_target_position", "Current position unchanged (torque off)");
             }
         }
+        self.apply_hard_stops();
         fb[0..N].copy_from_slice(&self.current_position);
         // fb[N..2*N].copy_from_slice(&self.current_velocity);
         // fb[2*N..3*N].copy_from_slice(&self.current_torque);
@@ -259,6 +718,14 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
     fn set_torque_limit(&mut self, torque: [f64; N]) -> Result<()> {
         log::debug!(target: "fake_io::set_torque_limit", "Setting torque_limit to {:?}", torque);
         self.torque_limit = torque;
+        self.torque_limit_history.push(torque);
+
+        for i in 0..N {
+            if self.torque_on[i] && self.lagging[i] {
+                self.pull_to_target(i);
+            }
+        }
+        self.apply_hard_stops();
         Ok(())
     }
 
@@ -272,6 +739,37 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
         Ok(())
     }
 
+    fn gain_capabilities(&self) -> GainCapabilities {
+        self.gain_capabilities
+    }
+
+    fn get_gains(&mut self) -> Result<[Gains; N]> {
+        let mut gains = self.gains;
+        for (gains, pid) in gains.iter_mut().zip(self.pid) {
+            let Gains { p, i, d, .. } = Gains::from(pid);
+            *gains = Gains { p, i, d, ..*gains };
+        }
+        Ok(gains)
+    }
+
+    fn set_gains(&mut self, gains: [Gains; N]) -> Result<()> {
+        log::debug!(target: "fake_io::set_gains", "Setting gains to {:?}", gains);
+        for (i, gains) in gains.iter().enumerate() {
+            self.pid[i] = gains.to_pid(self.pid[i]);
+            let current = self.gains[i];
+            self.gains[i] = Gains {
+                velocity_feedforward: gains.velocity_feedforward.or(current.velocity_feedforward),
+                acceleration_feedforward: gains
+                    .acceleration_feedforward
+                    .or(current.acceleration_feedforward),
+                derivative_filter: gains.derivative_filter.or(current.derivative_filter),
+                integral_limit: gains.integral_limit.or(current.integral_limit),
+                ..current
+            };
+        }
+        Ok(())
+    }
+
     fn get_axis_sensors(&mut self) -> Result<[f64; N]> {
         Ok(self.current_position)
     }
@@ -288,10 +786,17 @@ impl<const N: usize> RawMotorsIO<N> for FakeMotorsIO<N> {
 mod tests {
     mod controller {
         use std::f64::consts::PI;
+        use std::time::Duration;
 
         use crate::fake_motor::FakeMotorsController;
         use crate::motors_controller::MotorsController;
-        use crate::PID;
+        use crate::test_utils::assert_close;
+        use crate::{
+            CoupledLimit, CoupledLimits, FakeDynamics, FeedbackFilter, Feedforward, FrictionModel,
+            GainCapabilities, Gains, ImpedanceCommand, Limit, LookupTable, OffsetSpace,
+            PidController, SoftLimit, StopMode, TorqueRamp, TrajectoryStreamer, Transmission,
+            UnsupportedGainsError, VelocityEstimator, PID,
+        };
 
         #[test]
         fn check_default() {
@@ -353,6 +858,169 @@ mod tests {
             assert!(!torques[2]);
         }
 
+        #[test]
+        fn unsafe_torque_enable() {
+            let mut motor = FakeMotorsController::<2>::new();
+
+            motor.fake_io().set_current_position([1.0, 0.0]);
+            motor.set_torque([true; 2]).unwrap();
+
+            assert_eq!(motor.get_current_position().unwrap(), [0.0, 0.0]);
+            assert_eq!(motor.fake_io().max_torque_jump(), [1.0, 0.0]);
+        }
+
+        #[test]
+        fn safe_torque_enable() {
+            let mut motor = FakeMotorsController::<2>::new()
+                .with_offsets([Some(0.5), None])
+                .with_limits([Some((-0.1, 0.1).try_into().unwrap()), None]);
+
+            motor.set_torque([false, true]).unwrap();
+            motor.set_target_position([0.0, -0.5]).unwrap();
+            motor.fake_io().set_current_position([1.0, 2.0]);
+
+            motor.safe_enable_torque(None).unwrap();
+            assert_eq!(motor.is_torque_on().unwrap(), [true; 2]);
+            assert_eq!(motor.fake_io().max_torque_jump(), [0.0; 2]);
+
+            // Out of limit positions are kept, targets of motors already on are untouched
+            assert_eq!(motor.get_current_position().unwrap(), [0.5, -0.5]);
+            assert_eq!(motor.get_target_position().unwrap(), [0.5, -0.5]);
+        }
+
+        #[test]
+        fn limited_torque_enable() {
+            let mut motor = FakeMotorsController::<1>::new();
+
+            motor.set_torque_limit([0.25]).unwrap();
+            motor.fake_io().set_current_position([1.0]);
+            motor.set_torque([true]).unwrap();
+            assert_eq!(motor.get_current_position().unwrap(), [0.75]);
+
+            // Raising the torque limit pulls the motor the rest of the way
+            motor.set_torque_limit([0.5]).unwrap();
+            motor.set_torque_limit([f64::INFINITY]).unwrap();
+            assert_eq!(motor.get_current_position().unwrap(), [0.0]);
+            assert_eq!(motor.fake_io().max_torque_jump(), [0.5]);
+        }
+
+        #[test]
+        fn safe_torque_enable_ramp() {
+            let mut motor = FakeMotorsController::<2>::new();
+
+            motor.set_torque_limit([2.0, f64::INFINITY]).unwrap();
+            motor.fake_io().set_current_position([1.0, 0.0]);
+
+            motor
+                .safe_enable_torque(Some(TorqueRamp {
+                    duration: Duration::ZERO,
+                    steps: 4,
+                    max_torque: 1.0,
+                }))
+                .unwrap();
+            assert_eq!(motor.is_torque_on().unwrap(), [true; 2]);
+            assert_eq!(motor.fake_io().max_torque_jump(), [0.0; 2]);
+
+            // Infinite limits ramp through the maximum torque before being restored
+            assert_eq!(
+                motor.fake_io().torque_limit_history()[1..],
+                [
+                    [0.0, 0.0],
+                    [0.5, 0.25],
+                    [1.0, 0.5],
+                    [1.5, 0.75],
+                    [2.0, f64::INFINITY]
+                ]
+            );
+            assert_eq!(motor.get_torque_limit().unwrap(), [2.0, f64::INFINITY]);
+        }
+
+        #[test]
+        fn emergency_stop() {
+            let mut motor = FakeMotorsController::<3>::new().with_stop_modes([
+                StopMode::TorqueOff,
+                StopMode::Hold,
+                StopMode::ZeroVelocity,
+            ]);
+            motor.set_torque([true; 3]).unwrap();
+            motor.set_target_velocity([1.0; 3]).unwrap();
+            motor.set_target_position([1.0; 3]).unwrap();
+            motor.fake_io().set_current_position([0.5; 3]);
+
+            motor.emergency_stop().unwrap();
+            assert!(motor.is_emergency_stopped());
+            assert_eq!(motor.is_torque_on().unwrap(), [false, true, true]);
+            assert_eq!(motor.get_target_position().unwrap()[1], 0.5);
+            assert_eq!(motor.get_target_velocity().unwrap()[2], 0.0);
+
+            assert!(motor.set_target_position([0.0; 3]).is_err());
+            assert!(motor.set_target_velocity([0.0; 3]).is_err());
+            assert!(motor.set_target_torque([0.0; 3]).is_err());
+            assert!(motor.set_torque([true; 3]).is_err());
+            assert!(motor.safe_enable_torque(None).is_err());
+            motor.set_torque([false; 3]).unwrap();
+
+            motor.reset_emergency_stop().unwrap();
+            assert!(!motor.is_emergency_stopped());
+            motor.safe_enable_torque(None).unwrap();
+            motor.set_target_position([0.0; 3]).unwrap();
+            assert_eq!(motor.get_current_position().unwrap(), [0.0; 3]);
+        }
+
+        #[test]
+        fn emergency_stop_on_read() {
+            let mut motor = FakeMotorsController::<2>::new();
+            motor.set_torque([true; 2]).unwrap();
+            let latch = motor.emergency_stop_latch().unwrap().clone();
+
+            // A control loop only reading the feedback applies the stop as well
+            std::thread::spawn(move || latch.trigger()).join().unwrap();
+            motor.get_current_position().unwrap();
+            assert_eq!(motor.io().is_torque_on().unwrap(), [false; 2]);
+        }
+
+        #[test]
+        fn emergency_stop_from_other_thread() {
+            let mut motor = FakeMotorsController::<2>::new();
+            motor.set_torque([true; 2]).unwrap();
+            let latch = motor.emergency_stop_latch().unwrap().clone();
+
+            let mover = std::thread::spawn(move || {
+                let mut stream =
+                    TrajectoryStreamer::point_to_point([0.0; 2], [1.0; 2], Duration::from_secs(5))
+                        .unwrap();
+                let res = stream.run(
+                    &mut motor,
+                    Duration::from_millis(1),
+                    Duration::from_secs(10),
+                );
+                (motor, res.is_err())
+            });
+
+            std::thread::sleep(Duration::from_millis(20));
+            latch.trigger();
+
+            let (mut motor, refused) = mover.join().unwrap();
+            assert!(refused);
+            assert!(motor.is_emergency_stopped());
+            assert_eq!(motor.is_torque_on().unwrap(), [false; 2]);
+        }
+
+        #[test]
+        fn reset_applies_pending_stop() {
+            let mut motor = FakeMotorsController::<2>::new();
+            motor.set_torque([true; 2]).unwrap();
+            let latch = motor.emergency_stop_latch().unwrap().clone();
+
+            // Nothing ran on the controller since the trigger
+            std::thread::spawn(move || latch.trigger()).join().unwrap();
+            assert!(motor.emergency_stop_latch().unwrap().reset().is_err());
+
+            motor.reset_emergency_stop().unwrap();
+            assert!(!motor.is_emergency_stopped());
+            assert_eq!(motor.io().is_torque_on().unwrap(), [false; 2]);
+        }
+
         #[test]
         fn offset() {
             let mut motor =
@@ -389,6 +1057,39 @@ mod tests {
             assert_eq!(raw_target, [-2.0, 1.0, 1.0]);
         }
 
+        #[test]
+        fn inverted_offset_space() {
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_offsets([Some(0.5), Some(0.5)])
+                .with_reduction([Some(2.0), None])
+                .with_inverted([true, true])
+                .with_offset_space(OffsetSpace::Motor);
+            motors.set_torque([true; 2]).unwrap();
+
+            motors.set_target_position([1.0, 1.0]).unwrap();
+            assert_eq!(motors.io().get_target_position().unwrap(), [-1.5, -0.5]);
+            assert_eq!(motors.get_current_position().unwrap(), [1.0, 1.0]);
+
+            motors.set_target_velocity([1.0, 2.0]).unwrap();
+            assert_eq!(motors.io().get_target_velocity().unwrap(), [-1.0, -2.0]);
+            assert_eq!(motors.get_target_velocity().unwrap(), [1.0, 2.0]);
+
+            motors.set_target_torque([1.0, 2.0]).unwrap();
+            assert_eq!(motors.io().get_target_torque().unwrap(), [-1.0, -2.0]);
+            assert_eq!(motors.get_target_torque().unwrap(), [1.0, 2.0]);
+
+            // Same directions with joint space offsets
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_offsets([Some(0.5), Some(0.5)])
+                .with_reduction([Some(2.0), None])
+                .with_inverted([true, true]);
+            motors.set_torque([true; 2]).unwrap();
+
+            motors.set_target_position([1.0, 1.0]).unwrap();
+            assert_eq!(motors.io().get_target_position().unwrap(), [-3.0, -1.5]);
+            assert_eq!(motors.get_current_position().unwrap(), [1.0, 1.0]);
+        }
+
         #[test]
         fn limit() {
             let mut motor = FakeMotorsController::<3>::new().with_limits([
@@ -472,6 +1173,436 @@ mod tests {
             assert_eq!(motors.get_target_position().unwrap(), [0.0, 0.0, 1.0]);
         }
 
+        #[test]
+        fn unwrapping() {
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_unwrapping([true, false])
+                .with_reduction([Some(2.0), None])
+                .with_limits([Some((-5.0, 3.5).try_into().unwrap()), None]);
+            motors.set_torque([true; 2]).unwrap();
+
+            motors.fake_io().set_current_position([3.0, 0.0]);
+            assert_eq!(motors.get_current_position().unwrap(), [1.5, 0.0]);
+            motors.fake_io().set_current_position([-3.0, 0.0]);
+            let position = motors.get_current_position().unwrap();
+            assert!((position[0] - (PI - 1.5)).abs() < 1e-9);
+            assert_eq!(motors.unwrapper().unwrap().turns(), [1, 0]);
+
+            // Continuous target beyond the wrapped range of the motor
+            motors.set_target_position([2.0, 0.0]).unwrap();
+            let raw_target = motors.io().get_target_position().unwrap();
+            assert!((raw_target[0] - (4.0 - 2.0 * PI)).abs() < 1e-9);
+            assert!((motors.get_target_position().unwrap()[0] - 2.0).abs() < 1e-9);
+            assert!((motors.get_current_position().unwrap()[0] - 2.0).abs() < 1e-9);
+
+            // Limits apply to the continuous position
+            let fb = motors.set_target_position_fb([6.0, 0.0]).unwrap();
+            assert!((fb[0] - 3.5).abs() < 1e-9);
+            assert_eq!(motors.unwrapper().unwrap().turns(), [1, 0]);
+
+            motors.unwrapper().unwrap().reset();
+            let position = motors.get_current_position().unwrap();
+            assert!((position[0] - (3.5 - PI)).abs() < 1e-9);
+        }
+
+        #[test]
+        fn coupled_limits() {
+            let coupled_limits = CoupledLimits::new(vec![CoupledLimit::Linear {
+                coefficients: vec![1.0, 1.0],
+                max: 1.0,
+            }])
+            .unwrap();
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_offsets([Some(1.0), None])
+                .with_limits([None, Some((-1.0, 0.2).try_into().unwrap())])
+                .with_coupled_limits(coupled_limits);
+            motors.set_torque([true; 2]).unwrap();
+
+            motors.set_target_position([0.5, 0.0]).unwrap();
+            assert_eq!(motors.get_current_position().unwrap(), [0.5, 0.0]);
+
+            motors.set_target_position([1.0, 1.0]).unwrap();
+            let position = motors.get_current_position().unwrap();
+            assert!((position[0] - 0.8).abs() < 1e-6);
+            assert!((position[1] - 0.2).abs() < 1e-6);
+            assert!((motors.io().get_target_position().unwrap()[0] - 1.8).abs() < 1e-6);
+        }
+
+        #[test]
+        fn feedback_filter() {
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_reduction([Some(2.0), None])
+                .with_feedback_filter(FeedbackFilter::new([
+                    None,
+                    Some(VelocityEstimator::FiniteDifference),
+                ]));
+            motors.set_torque([true; 2]).unwrap();
+
+            assert!(motors.get_current_velocity().unwrap()[0].is_nan());
+            std::thread::sleep(Duration::from_millis(10));
+            motors.fake_io().set_current_position([0.2, 0.1]);
+            let velocity = motors.get_current_velocity().unwrap();
+            // Estimated in joint space, from the (NaN) reported velocity and the estimator
+            assert!(velocity[0] > 0.0 && velocity[0] <= 10.0);
+            assert!((velocity[0] - velocity[1]).abs() < 1e-9);
+
+            // Reported velocities are kept for joints without estimator
+            motors.set_target_velocity([0.4, 0.4]).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(motors.get_current_velocity().unwrap()[0], 0.2);
+        }
+
+        #[test]
+        fn pid_controllers() {
+            let pid = PidController::new(PID {
+                p: 10.0,
+                i: 5.0,
+                d: 0.5,
+            })
+            .with_output_limit(Limit::new(-2.0, 2.0));
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_offsets([Some(0.5), None])
+                .with_dynamics(
+                    [Some(FakeDynamics {
+                        inertia: 0.01,
+                        damping: 0.1,
+                        friction: 0.2,
+                    }); 2],
+                )
+                .with_reduction([None, Some(2.0)])
+                .with_feedforward(Feedforward::new([None, None]).with_gravity(|_| [0.0, 0.1]))
+                .with_pid_controllers([Some(pid), None]);
+            motors.set_torque([true; 2]).unwrap();
+            motors.set_target_torque([0.0, 0.0]).unwrap();
+            motors.io().set_target_torque([0.0, 0.15]).unwrap();
+
+            for _ in 0..5000 {
+                let torque = motors.update_pid_controllers([1.0, 1.0], 0.001).unwrap();
+                assert!(torque[0].unwrap().abs() <= 2.0);
+                assert_eq!(torque[1], None);
+                motors.fake_io().step(0.001);
+            }
+            let position = motors.get_current_position().unwrap();
+            assert!((position[0] - 1.0).abs() < 1e-3);
+            // The joint without a controller keeps its raw target, without compensating it again
+            assert_eq!(motors.io().get_target_torque().unwrap()[1], 0.15);
+
+            assert!(FakeMotorsController::<1>::new()
+                .update_pid_controllers([0.0], 0.001)
+                .is_err());
+        }
+
+        #[test]
+        fn transmission() {
+            let differential = Transmission::new([[1.0, 1.0], [1.0, -1.0]], [0.5, 0.0]).unwrap();
+            let mut motors = FakeMotorsController::<2>::new().with_transmission(differential);
+            motors.set_torque([true; 2]).unwrap();
+
+            motors.set_target_position([0.3, -0.2]).unwrap();
+            assert_close(
+                motors.io().get_target_position().unwrap(),
+                [0.6, 0.5],
+                1e-12,
+            );
+            assert_close(motors.get_current_position().unwrap(), [0.3, -0.2], 1e-12);
+            assert_close(motors.get_target_position().unwrap(), [0.3, -0.2], 1e-12);
+
+            motors.set_target_velocity([1.0, 0.5]).unwrap();
+            assert_close(
+                motors.io().get_target_velocity().unwrap(),
+                [1.5, 0.5],
+                1e-12,
+            );
+            assert_close(motors.get_current_velocity().unwrap(), [1.0, 0.5], 1e-12);
+
+            motors.set_target_torque([1.0, 0.0]).unwrap();
+            assert_close(motors.io().get_target_torque().unwrap(), [0.5, 0.5], 1e-12);
+            assert_close(motors.get_current_torque().unwrap(), [1.0, 0.0], 1e-12);
+            assert_close(motors.get_target_torque().unwrap(), [1.0, 0.0], 1e-12);
+
+            // The scalar offsets and reductions are the diagonal case
+            let (offsets, reduction) = ([Some(0.5), None], [Some(2.0), Some(-1.0)]);
+            let mut scalar = FakeMotorsController::<2>::new()
+                .with_offsets(offsets)
+                .with_reduction(reduction);
+            let mut diagonal = FakeMotorsController::<2>::new()
+                .with_transmission(Transmission::from_scalar(offsets, reduction).unwrap());
+            for motors in [&mut scalar, &mut diagonal] {
+                motors.set_torque([true; 2]).unwrap();
+                motors.set_target_position([0.3, -0.2]).unwrap();
+            }
+            assert_eq!(
+                scalar.io().get_target_position().unwrap(),
+                diagonal.io().get_target_position().unwrap()
+            );
+            assert_eq!(
+                scalar.get_current_position().unwrap(),
+                diagonal.get_current_position().unwrap()
+            );
+
+            // Scalar target velocities and torques are sent as is, the transmission converts them
+            for motors in [&mut scalar, &mut diagonal] {
+                motors.set_target_velocity([0.4, 0.6]).unwrap();
+                motors.set_target_torque([1.0, -0.5]).unwrap();
+                assert_close(motors.get_target_velocity().unwrap(), [0.4, 0.6], 1e-12);
+                assert_close(motors.get_target_torque().unwrap(), [1.0, -0.5], 1e-12);
+            }
+            assert_close(
+                scalar.io().get_target_velocity().unwrap(),
+                [0.4, 0.6],
+                1e-12,
+            );
+            assert_close(scalar.io().get_target_torque().unwrap(), [1.0, -0.5], 1e-12);
+            assert_close(scalar.get_current_velocity().unwrap(), [0.2, -0.6], 1e-12);
+            assert_close(scalar.get_current_torque().unwrap(), [0.5, 0.5], 1e-12);
+
+            let velocity = diagonal.io().get_target_velocity().unwrap();
+            assert_close(velocity, [0.8, -0.6], 1e-12);
+            assert_close(
+                diagonal.io().get_target_torque().unwrap(),
+                [0.5, 0.5],
+                1e-12,
+            );
+            assert_close(diagonal.get_current_velocity().unwrap(), [0.4, 0.6], 1e-12);
+            assert_close(diagonal.get_current_torque().unwrap(), [1.0, -0.5], 1e-12);
+        }
+
+        #[test]
+        fn lookup_tables() {
+            let table = LookupTable::new(vec![[0.0, 0.0], [1.0, 2.0], [2.0, 3.0]]).unwrap();
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_reduction([Some(4.0), Some(2.0)])
+                .with_lookup_tables([Some(table.clone()), None]);
+            motors.set_torque([true; 2]).unwrap();
+
+            motors.set_target_position([1.5, 1.0]).unwrap();
+            let raw = motors.io().get_target_position().unwrap();
+            assert_eq!(raw, [table.to_motor(1.5), 2.0]);
+            let position = motors.get_current_position().unwrap();
+            assert!((position[0] - 1.5).abs() < 1e-9);
+            assert_eq!(position[1], 1.0);
+
+            // Current velocities and torques use the local ratio at the current position as reduction
+            let jacobian = table.jacobian(1.5);
+            motors.set_target_velocity([1.0, 1.0]).unwrap();
+            assert_eq!(motors.io().get_target_velocity().unwrap(), [1.0, 1.0]);
+            let velocity = motors.get_current_velocity().unwrap();
+            assert!((velocity[0] - 1.0 / jacobian).abs() < 1e-9);
+
+            motors.set_target_torque([1.0, 1.0]).unwrap();
+            assert_eq!(motors.io().get_target_torque().unwrap(), [1.0, 1.0]);
+            let torque = motors.get_current_torque().unwrap();
+            assert!((torque[0] - 1.0 / jacobian).abs() < 1e-9);
+            assert_eq!(motors.get_target_torque().unwrap(), [1.0, 1.0]);
+        }
+
+        #[test]
+        fn linear_lookup_table_matches_reduction() {
+            // A table of slope 2 on the first joint, a reduction of 2 on the second one
+            let table = LookupTable::new(vec![[-10.0, -20.0], [10.0, 20.0]]).unwrap();
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_reduction([None, Some(2.0)])
+                .with_lookup_tables([Some(table), None]);
+            motors.set_torque([true; 2]).unwrap();
+
+            motors.set_target_position([0.5, 0.5]).unwrap();
+            let raw = motors.io().get_target_position().unwrap();
+            assert!((raw[0] - raw[1]).abs() < 1e-9);
+
+            motors.set_target_velocity([1.0, 1.0]).unwrap();
+            let raw = motors.io().get_target_velocity().unwrap();
+            assert!((raw[0] - raw[1]).abs() < 1e-9);
+            let velocity = motors.get_current_velocity().unwrap();
+            assert!((velocity[0] - velocity[1]).abs() < 1e-9);
+            let velocity = motors.get_target_velocity().unwrap();
+            assert!((velocity[0] - velocity[1]).abs() < 1e-9);
+
+            motors.set_target_torque([1.0, 1.0]).unwrap();
+            let raw = motors.io().get_target_torque().unwrap();
+            assert!((raw[0] - raw[1]).abs() < 1e-9);
+            let torque = motors.get_current_torque().unwrap();
+            assert!((torque[0] - torque[1]).abs() < 1e-9);
+            let torque = motors.get_target_torque().unwrap();
+            assert!((torque[0] - torque[1]).abs() < 1e-9);
+        }
+
+        #[test]
+        fn feedforward() {
+            let dynamics = FakeDynamics {
+                inertia: 0.01,
+                damping: 0.1,
+                friction: 0.2,
+            };
+            let friction = FrictionModel {
+                coulomb: 0.2,
+                viscous: 0.1,
+                velocity_threshold: 0.0,
+            };
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_dynamics([Some(dynamics); 2])
+                .with_feedforward(Feedforward::new([Some(friction), None]));
+            motors.set_torque([true; 2]).unwrap();
+
+            for _ in 0..100 {
+                motors.set_target_torque([1.0, 1.0]).unwrap();
+                motors.fake_io().step(0.001);
+            }
+            let velocity = motors.get_current_velocity().unwrap();
+            assert!(velocity[0] > 0.0 && velocity[1] > 0.0);
+
+            // The compensated joint coasts, the other one is stopped by friction
+            for _ in 0..1000 {
+                motors.set_target_torque([0.0, 0.0]).unwrap();
+                motors.fake_io().step(0.001);
+            }
+            let coasting = motors.get_current_velocity().unwrap();
+            assert!((coasting[0] - velocity[0]).abs() < 1e-9);
+            assert_eq!(coasting[1], 0.0);
+
+            // Gravity is added to the torque and impedance commands
+            let mut motors = FakeMotorsController::<1>::new().with_feedforward(
+                Feedforward::new([None]).with_gravity(|position: [f64; 1]| [position[0] + 1.0]),
+            );
+            motors.set_torque([true]).unwrap();
+            motors.set_target_torque([0.5]).unwrap();
+            assert_eq!(motors.io().get_target_torque().unwrap(), [1.5]);
+
+            let command = ImpedanceCommand {
+                position: 1.0,
+                torque: 0.5,
+                ..Default::default()
+            };
+            motors.set_impedance_command([command]).unwrap();
+            assert_eq!(motors.io().get_target_torque().unwrap(), [1.5]);
+        }
+
+        #[test]
+        fn impedance() {
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_offsets([Some(0.5), None])
+                .with_reduction([Some(2.0), None])
+                .with_limits([None, Some(Limit::new(-0.5, 0.5))]);
+            motors.set_torque([true; 2]).unwrap();
+
+            let command = ImpedanceCommand {
+                position: 1.0,
+                velocity: 0.5,
+                kp: 8.0,
+                kd: 4.0,
+                torque: 1.0,
+            };
+            motors.set_impedance_command([command; 2]).unwrap();
+            assert_eq!(motors.io().get_target_position().unwrap(), [3.0, 0.5]);
+            assert_eq!(motors.io().get_target_velocity().unwrap(), [1.0, 0.5]);
+            assert_eq!(motors.io().get_target_torque().unwrap(), [1.0, 1.0]);
+            assert_eq!(motors.get_current_position().unwrap(), [1.0, 0.5]);
+
+            let invalid = ImpedanceCommand {
+                kp: -1.0,
+                ..command
+            };
+            assert!(motors.set_impedance_command([invalid; 2]).is_err());
+
+            // Positions are limited like target positions, soft limits included
+            let soft_limited = || {
+                let mut motors = FakeMotorsController::<1>::new()
+                    .with_limits([Some(Limit::new(-1.0, 1.0))])
+                    .with_soft_limits([Some(SoftLimit::new(0.5, 2.0))]);
+                motors.set_torque([true]).unwrap();
+                motors
+            };
+            let (mut position, mut impedance) = (soft_limited(), soft_limited());
+            position.set_target_position([5.0]).unwrap();
+            impedance
+                .set_impedance_command([ImpedanceCommand {
+                    position: 5.0,
+                    ..command
+                }])
+                .unwrap();
+            let limited = position.io().get_target_position().unwrap();
+            assert!(limited[0] < 1.0);
+            assert_eq!(impedance.io().get_target_position().unwrap(), limited);
+
+            // Torques follow the target torque convention, natively or in software
+            let torque = ImpedanceCommand {
+                position: 0.0,
+                velocity: 0.0,
+                kp: 0.0,
+                kd: 0.0,
+                torque: 1.0,
+            };
+            for native in [true, false] {
+                let mut motors = FakeMotorsController::<1>::new()
+                    .with_reduction([Some(2.0)])
+                    .with_inverted([true]);
+                motors.fake_io().set_impedance_support(native);
+                motors.set_torque([true]).unwrap();
+                motors.set_target_velocity([0.0]).unwrap();
+
+                motors.set_impedance_command([torque]).unwrap();
+                let raw = motors.io().get_target_torque().unwrap();
+                motors.set_target_torque([1.0]).unwrap();
+                assert_eq!(motors.io().get_target_torque().unwrap(), raw);
+                assert_eq!(raw, [-1.0]);
+            }
+
+            // Native and software impedance converge the same way
+            for native in [true, false] {
+                let mut motors = FakeMotorsController::<1>::new()
+                    .with_reduction([Some(2.0)])
+                    .with_dynamics([Some(FakeDynamics {
+                        inertia: 0.01,
+                        damping: 0.1,
+                        friction: 0.0,
+                    })]);
+                motors.fake_io().set_impedance_support(native);
+                motors.set_torque([true]).unwrap();
+
+                let command = ImpedanceCommand {
+                    position: 1.0,
+                    velocity: 0.0,
+                    kp: 5.0,
+                    kd: 0.5,
+                    torque: 0.0,
+                };
+                for _ in 0..5000 {
+                    motors.set_impedance_command([command]).unwrap();
+                    motors.fake_io().step(0.001);
+                }
+                let position = motors.get_current_position().unwrap();
+                assert!((position[0] - 1.0).abs() < 1e-3);
+            }
+        }
+
+        #[test]
+        fn soft_limits() {
+            let mut motors = FakeMotorsController::<2>::new()
+                .with_offsets([Some(1.0), None])
+                .with_limits([Some(Limit::new(-1.0, 1.0)), None])
+                .with_soft_limits([Some(SoftLimit::new(0.5, 2.0)), None]);
+            motors.fake_io().set_current_position([1.0, 0.0]);
+            motors.safe_enable_torque(None).unwrap();
+            assert_eq!(motors.get_soft_limit_penetration().unwrap(), [0.0, 0.0]);
+
+            // Position commands jumping to the limit stop in the zone
+            motors.set_target_position([5.0, 5.0]).unwrap();
+            let position = motors.get_current_position().unwrap();
+            assert!(position[0] > 0.5 && position[0] < 1.0);
+            assert_eq!(position[1], 5.0);
+            let penetration = motors.get_soft_limit_penetration().unwrap();
+            assert!(penetration[0] > 0.0 && penetration[0] < 1.0);
+            assert_eq!(penetration[1], 0.0);
+
+            // Velocity toward the limit shrinks in the zone
+            motors.fake_io().set_current_position([1.75, 0.0]);
+            motors.set_target_velocity([10.0, 10.0]).unwrap();
+            let velocity = motors.get_target_velocity().unwrap();
+            assert!((velocity[0] - 1.0).abs() < 1e-9);
+            assert_eq!(velocity[1], 10.0);
+            motors.set_target_velocity([-10.0, 0.0]).unwrap();
+            assert_eq!(motors.get_target_velocity().unwrap(), [-10.0, 0.0]);
+        }
+
         #[test]
         fn velocity_limit() {
             let mut motors = FakeMotorsController::<3>::new()
@@ -533,10 +1664,67 @@ mod tests {
             motors.set_pid_gains(pids).unwrap();
             assert_eq!(motors.get_pid_gains().unwrap(), pids);
         }
+
+        #[test]
+        fn gains() {
+            let mut motors = FakeMotorsController::<2>::new();
+            assert_eq!(motors.gain_capabilities(), GainCapabilities::ALL);
+            assert_eq!(motors.get_gains().unwrap(), [Gains::default(); 2]);
+
+            let gains = Gains {
+                p: Some(2.0),
+                velocity_feedforward: Some(0.5),
+                ..Default::default()
+            };
+            motors.set_gains([gains, Gains::default()]).unwrap();
+            assert_eq!(motors.get_gains().unwrap()[0], gains);
+            assert_eq!(motors.get_pid_gains().unwrap()[0].p, 2.0);
+            assert!(motors.get_pid_gains().unwrap()[1].p.is_nan());
+
+            // Missing fields are not changed
+            let integral = Gains {
+                i: Some(1.0),
+                integral_limit: Some(3.0),
+                ..Default::default()
+            };
+            motors.set_gains([integral; 2]).unwrap();
+            let expected = Gains {
+                i: Some(1.0),
+                integral_limit: Some(3.0),
+                ..gains
+            };
+            assert_eq!(motors.get_gains().unwrap()[0], expected);
+
+            // Invalid gains never reach the motors
+            let negative = Gains {
+                d: Some(-1.0),
+                ..Default::default()
+            };
+            assert!(motors.set_gains([negative; 2]).is_err());
+            let nan = PID {
+                p: f64::NAN,
+                i: 0.0,
+                d: 0.0,
+            };
+            assert!(motors.set_pid_gains([nan; 2]).is_err());
+            assert_eq!(motors.get_gains().unwrap()[0], expected);
+
+            // Fields not honored by the motors are refused by the controller
+            motors
+                .fake_io()
+                .set_gain_capabilities(GainCapabilities::PID);
+            let error = motors.set_gains([integral; 2]).unwrap_err();
+            assert!(error.downcast_ref::<UnsupportedGainsError>().is_some());
+            assert_eq!(motors.get_gains().unwrap()[0], expected);
+        }
     }
 
     mod io {
-        use crate::{fake_motor::FakeMotorsIO, motors_io::RawMotorsIO};
+        use crate::{
+            fake_motor::{FakeDynamics, FakeMotorsIO},
+            motors_io::RawMotorsIO,
+            PID,
+        };
 
         #[test]
         fn check_default() {
@@ -568,6 +1756,83 @@ mod tests {
             assert_eq!(motor.get_current_position().unwrap(), [0.25]);
         }
 
+        #[test]
+        fn hard_stops() {
+            let mut motor = FakeMotorsIO::<2> {
+                hard_stops: [Some((-1.0, 1.0).try_into().unwrap()), None],
+                ..Default::default()
+            };
+            motor.set_torque([true; 2]).unwrap();
+
+            motor.set_target_position([2.0, 2.0]).unwrap();
+            assert_eq!(motor.get_current_position().unwrap(), [1.0, 2.0]);
+
+            motor.set_target_velocity([-1.0, -1.0]).unwrap();
+            motor.step(1.5);
+            assert_eq!(motor.get_current_position().unwrap(), [-0.5, 0.5]);
+            assert_eq!(motor.get_current_torque().unwrap(), [0.0, 0.0]);
+
+            motor.set_torque_limit([0.3, 0.3]).unwrap();
+            motor.step(1.0);
+            assert_eq!(motor.get_current_position().unwrap(), [-1.0, -0.5]);
+            assert_eq!(motor.get_current_torque().unwrap(), [-0.3, 0.0]);
+        }
+
+        #[test]
+        fn dynamics() {
+            let mut motors = FakeMotorsIO::<2>::default();
+            motors.set_dynamics(
+                [Some(FakeDynamics {
+                    inertia: 0.01,
+                    damping: 0.1,
+                    friction: 0.2,
+                }); 2],
+            );
+            motors
+                .set_pid_gains([
+                    PID {
+                        p: 10.0,
+                        i: 5.0,
+                        d: 0.5,
+                    },
+                    PID {
+                        p: f64::NAN,
+                        i: f64::NAN,
+                        d: f64::NAN,
+                    },
+                ])
+                .unwrap();
+            motors.set_torque([true; 2]).unwrap();
+
+            // Targets are reached through the simulation only
+            motors.set_target_position([1.0, 1.0]).unwrap();
+            motors.set_target_torque([0.1, 0.1]).unwrap();
+            assert_eq!(motors.get_current_position().unwrap(), [0.0, 0.0]);
+
+            // Torque below the static friction does not move the motor
+            motors.step(0.001);
+            assert_eq!(motors.get_current_position().unwrap()[1], 0.0);
+            assert_eq!(motors.get_current_torque().unwrap()[1], 0.1);
+
+            for _ in 0..5000 {
+                motors.step(0.001);
+            }
+            let position = motors.get_current_position().unwrap();
+            assert!((position[0] - 1.0).abs() < 2e-2);
+            assert_eq!(position[1], 0.0);
+            assert!(motors.get_current_velocity().unwrap()[0].abs() < 1e-2);
+
+            // Friction stops the motors with torque off
+            motors.set_target_torque([0.0, 1.0]).unwrap();
+            motors.step(0.1);
+            assert!(motors.get_current_velocity().unwrap()[1] > 0.0);
+            motors.set_torque([false; 2]).unwrap();
+            for _ in 0..1000 {
+                motors.step(0.001);
+            }
+            assert_eq!(motors.get_current_velocity().unwrap(), [0.0, 0.0]);
+        }
+
         #[test]
         fn multiple_fake() {
             let mut motors = FakeMotorsIO::<3>::default();
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+/// Coulomb and viscous friction of a joint
+pub struct FrictionModel {
+    /// Coulomb friction (in Nm)
+    pub coulomb: f64,
+    /// Viscous friction (in Nm·s/rad)
+    pub viscous: f64,
+    /// Velocity below which the Coulomb friction is linearly reduced, to avoid chattering around zero (in rad/s)
+    #[serde(default)]
+    pub velocity_threshold: f64,
+}
+
+impl FrictionModel {
+    /// Friction torque opposed to a velocity (in Nm), counted positive in the direction of the motion
+    pub fn torque(&self, velocity: f64) -> f64 {
+        if !velocity.is_finite() {
+            return 0.0;
+        }
+        let direction = match self.velocity_threshold > 0.0 {
+            true => (velocity / self.velocity_threshold).clamp(-1.0, 1.0),
+            false if velocity == 0.0 => 0.0,
+            false => velocity.signum(),
+        };
+        self.coulomb * direction + self.viscous * velocity
+    }
+}
+
+/// Gravity torques of the joints (in Nm) for their positions (in radians)
+pub type GravityModel<const N: usize> = Box<dyn Fn([f64; N]) -> [f64; N] + Send>;
+
+/// Feedforward torque compensating the friction and the gravity of the joints
+///
+/// It is added by the controller to the target torques and to the torques of
+/// the impedance commands.
+pub struct Feedforward<const N: usize> {
+    friction: [Option<FrictionModel>; N],
+    gravity: Option<GravityModel<N>>,
+}
+
+impl<const N: usize> Feedforward<N> {
+    /// Compensate the friction of each joint
+    pub fn new(friction: [Option<FrictionModel>; N]) -> Self {
+        Self {
+            friction,
+            gravity: None,
+        }
+    }
+
+    /// Also compensate the gravity, computed from the joint positions
+    pub fn with_gravity(mut self, gravity: impl Fn([f64; N]) -> [f64; N] + Send + 'static) -> Self {
+        self.gravity = Some(Box::new(gravity));
+        self
+    }
+
+    /// Friction model of each joint
+    pub fn friction(&self) -> [Option<FrictionModel>; N] {
+        self.friction
+    }
+
+    /// Feedforward torques (in Nm) for the current positions (in radians) and velocities (in rad/s)
+    pub fn torque(&self, position: [f64; N], velocity: [f64; N]) -> [f64; N] {
+        let mut torque = match &self.gravity {
+            Some(gravity) => gravity(position),
+            None => [0.0; N],
+        };
+        for i in 0..N {
+            if let Some(friction) = self.friction[i] {
+                torque[i] += friction.torque(velocity[i]);
+            }
+        }
+        torque
+    }
+}
+
+impl<const N: usize> std::fmt::Debug for Feedforward<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Feedforward")
+            .field("friction", &self.friction)
+            .field("gravity", &self.gravity.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Feedforward, FrictionModel};
+
+    #[test]
+    fn friction() {
+        let friction = FrictionModel {
+            coulomb: 0.2,
+            viscous: 0.1,
+            velocity_threshold: 0.0,
+        };
+        assert_eq!(friction.torque(0.0), 0.0);
+        assert!((friction.torque(2.0) - 0.4).abs() < 1e-12);
+        assert!((friction.torque(-2.0) + 0.4).abs() < 1e-12);
+        assert_eq!(friction.torque(f64::NAN), 0.0);
+
+        let smooth = FrictionModel {
+            velocity_threshold: 0.1,
+            ..friction
+        };
+        assert!((smooth.torque(0.05) - 0.105).abs() < 1e-12);
+        assert!((smooth.torque(1.0) - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn gravity() {
+        let feedforward = Feedforward::new([
+            Some(FrictionModel {
+                coulomb: 0.2,
+                viscous: 0.0,
+                velocity_threshold: 0.0,
+            }),
+            None,
+        ])
+        .with_gravity(|position: [f64; 2]| [0.0, 2.0 * position[1].cos()]);
+
+        assert_eq!(feedforward.torque([0.0, 0.0], [1.0, 1.0]), [0.2, 2.0]);
+        assert_eq!(feedforward.torque([0.0, 0.0], [-1.0, 0.0]), [-0.2, 2.0]);
+    }
+
+    #[test]
+    fn serde() {
+        let friction: [Option<FrictionModel>; 2] =
+            serde_json::from_str(r#"[{"coulomb": 0.2, "viscous": 0.1}, null]"#).unwrap();
+        assert_eq!(
+            friction,
+            [
+                Some(FrictionModel {
+                    coulomb: 0.2,
+                    viscous: 0.1,
+                    velocity_threshold: 0.0,
+                }),
+                None
+            ]
+        );
+    }
+}
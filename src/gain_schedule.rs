@@ -0,0 +1,343 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{MotorsController, Result, PID};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Joint state indexing a gain schedule
+pub enum ScheduleVariable {
+    /// Current position (in radians)
+    Position,
+    /// Current velocity (in rad/s)
+    Velocity,
+    /// Current torque (in Nm)
+    Torque,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Gains to use at a given value of the schedule variable
+pub struct SchedulePoint {
+    pub at: f64,
+    pub gains: PID,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "GainScheduleDef")]
+/// Gains of a joint, linearly interpolated between points of a schedule variable
+///
+/// Outside of the points, the gains of the closest point are used.
+pub struct GainSchedule {
+    variable: ScheduleVariable,
+    points: Vec<SchedulePoint>,
+}
+
+impl GainSchedule {
+    /// Create a schedule, checking the points are sorted and their gains valid
+    pub fn new(
+        variable: ScheduleVariable,
+        points: Vec<SchedulePoint>,
+    ) -> std::result::Result<Self, InvalidScheduleError> {
+        if points.is_empty() {
+            return Err(InvalidScheduleError("no point".to_string()));
+        }
+        if points
+            .windows(2)
+            .any(|w| w[0].at.partial_cmp(&w[1].at) != Some(Ordering::Less))
+        {
+            return Err(InvalidScheduleError(
+                "points must be sorted by increasing value".to_string(),
+            ));
+        }
+        for point in &points {
+            point
+                .gains
+                .validate()
+                .map_err(|e| InvalidScheduleError(e.to_string()))?;
+        }
+        Ok(Self { variable, points })
+    }
+
+    /// Variable indexing the schedule
+    pub fn variable(&self) -> ScheduleVariable {
+        self.variable
+    }
+
+    /// Points of the schedule
+    pub fn points(&self) -> &[SchedulePoint] {
+        &self.points
+    }
+
+    /// Interpolated gains at a value of the schedule variable
+    pub fn gains(&self, value: f64) -> PID {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if value.is_nan() || value <= first.at {
+            return first.gains;
+        }
+        if value >= last.at {
+            return last.gains;
+        }
+
+        let k = self.points.partition_point(|p| p.at <= value);
+        let (a, b) = (self.points[k - 1], self.points[k]);
+        let t = (value - a.at) / (b.at - a.at);
+        let lerp = |x: f64, y: f64| x + t * (y - x);
+        PID {
+            p: lerp(a.gains.p, b.gains.p),
+            i: lerp(a.gains.i, b.gains.i),
+            d: lerp(a.gains.d, b.gains.d),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GainScheduleDef {
+    variable: ScheduleVariable,
+    points: Vec<SchedulePoint>,
+}
+
+impl TryFrom<GainScheduleDef> for GainSchedule {
+    type Error = InvalidScheduleError;
+
+    fn try_from(value: GainScheduleDef) -> std::result::Result<Self, Self::Error> {
+        Self::new(value.variable, value.points)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidScheduleError(pub String);
+impl std::fmt::Display for InvalidScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid gain schedule: {reason})")
+    }
+}
+impl std::error::Error for InvalidScheduleError {}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Gain scheduler sending the scheduled PID gains through `set_pid_gains_partial`
+///
+/// Gains are only sent when one of them changed by more than the relative
+/// threshold since they were last sent, to limit the bus traffic. Joints
+/// without schedule are never written, so they keep their current gains.
+pub struct GainScheduler<const N: usize> {
+    schedules: [Option<GainSchedule>; N],
+    threshold: f64,
+
+    sent: Option<[Option<PID>; N]>,
+}
+
+impl<const N: usize> GainScheduler<N> {
+    /// Create a scheduler sending gains when they change by more than 5%
+    pub fn new(schedules: [Option<GainSchedule>; N]) -> Self {
+        Self {
+            schedules,
+            threshold: 0.05,
+
+            sent: None,
+        }
+    }
+
+    /// Relative change of a gain required to send the gains
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Gains last sent to the scheduled joints
+    pub fn sent(&self) -> Option<[Option<PID>; N]> {
+        self.sent
+    }
+
+    /// Force sending the gains on the next update
+    pub fn reset(&mut self) {
+        self.sent = None;
+    }
+
+    /// Read the joint states and send the scheduled gains if they changed enough
+    ///
+    /// Returns whether the gains were sent.
+    pub fn update(&mut self, controller: &mut dyn MotorsController<N>) -> Result<bool> {
+        let uses = |variable| {
+            self.schedules
+                .iter()
+                .flatten()
+                .any(|s| s.variable() == variable)
+        };
+        let position = match uses(ScheduleVariable::Position) {
+            true => controller.get_current_position()?,
+            false => [f64::NAN; N],
+        };
+        let velocity = match uses(ScheduleVariable::Velocity) {
+            true => controller.get_current_velocity()?,
+            false => [f64::NAN; N],
+        };
+        let torque = match uses(ScheduleVariable::Torque) {
+            true => controller.get_current_torque()?,
+            false => [f64::NAN; N],
+        };
+
+        let mut gains = [None; N];
+        for (i, schedule) in self.schedules.iter().enumerate() {
+            if let Some(schedule) = schedule {
+                gains[i] = Some(schedule.gains(match schedule.variable() {
+                    ScheduleVariable::Position => position[i],
+                    ScheduleVariable::Velocity => velocity[i],
+                    ScheduleVariable::Torque => torque[i],
+                }));
+            }
+        }
+
+        let changed = |old: f64, new: f64| {
+            (new - old).abs() > self.threshold * old.abs().max(new.abs()) || old.is_nan()
+        };
+        let send = match self.sent {
+            Some(sent) => gains.iter().zip(sent).any(|(new, old)| match (new, old) {
+                (Some(new), Some(old)) => {
+                    changed(old.p, new.p) || changed(old.i, new.i) || changed(old.d, new.d)
+                }
+                _ => false,
+            }),
+            None => true,
+        };
+        if !send {
+            return Ok(false);
+        }
+
+        log::debug!(target: "gain_scheduler::update", "sending gains {:?}", gains);
+        controller.set_pid_gains_partial(gains)?;
+        self.sent = Some(gains);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GainSchedule, GainScheduler, SchedulePoint, ScheduleVariable};
+    use crate::{FakeMotorsController, MotorsController, PID};
+
+    fn pid(p: f64) -> PID {
+        PID { p, i: 0.0, d: 0.1 }
+    }
+
+    fn schedule() -> GainSchedule {
+        GainSchedule::new(
+            ScheduleVariable::Position,
+            vec![
+                SchedulePoint {
+                    at: 0.0,
+                    gains: pid(1.0),
+                },
+                SchedulePoint {
+                    at: 1.0,
+                    gains: pid(3.0),
+                },
+                SchedulePoint {
+                    at: 2.0,
+                    gains: pid(3.0),
+                },
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn interpolation() {
+        let schedule = schedule();
+
+        assert_eq!(schedule.gains(-1.0), pid(1.0));
+        assert_eq!(schedule.gains(0.5), pid(2.0));
+        assert_eq!(schedule.gains(1.0), pid(3.0));
+        assert_eq!(schedule.gains(1.5), pid(3.0));
+        assert_eq!(schedule.gains(5.0), pid(3.0));
+        assert_eq!(schedule.gains(f64::NAN), pid(1.0));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(GainSchedule::new(ScheduleVariable::Torque, vec![]).is_err());
+        let unsorted = vec![
+            SchedulePoint {
+                at: 1.0,
+                gains: pid(1.0),
+            },
+            SchedulePoint {
+                at: 0.0,
+                gains: pid(1.0),
+            },
+        ];
+        assert!(GainSchedule::new(ScheduleVariable::Torque, unsorted).is_err());
+        let negative = vec![SchedulePoint {
+            at: 0.0,
+            gains: pid(-1.0),
+        }];
+        assert!(GainSchedule::new(ScheduleVariable::Torque, negative).is_err());
+    }
+
+    #[test]
+    fn scheduler() {
+        let mut motors = FakeMotorsController::<2>::new().with_offsets([Some(-0.5), None]);
+        motors.set_pid_gains([pid(10.0), pid(10.0)]).unwrap();
+        let mut scheduler = GainScheduler::new([Some(schedule()), None]).with_threshold(0.1);
+
+        // First update always sends, joints without schedule keep their gains
+        assert!(scheduler.update(&mut motors).unwrap());
+        assert_eq!(motors.get_pid_gains().unwrap(), [pid(2.0), pid(10.0)]);
+
+        // Small changes are not sent
+        motors.fake_io().set_current_position([0.05, 0.0]);
+        assert!(!scheduler.update(&mut motors).unwrap());
+        assert_eq!(motors.get_pid_gains().unwrap()[0], pid(2.0));
+
+        motors.fake_io().set_current_position([0.3, 0.0]);
+        assert!(scheduler.update(&mut motors).unwrap());
+        let p = motors.get_pid_gains().unwrap()[0].p;
+        assert!((p - 2.6).abs() < 1e-9);
+        assert_eq!(
+            scheduler.sent().unwrap(),
+            [Some(motors.get_pid_gains().unwrap()[0]), None]
+        );
+    }
+
+    #[test]
+    fn unscheduled_joints_untouched() {
+        // The fake motors report NaN gains until they are set
+        let mut motors = FakeMotorsController::<2>::new();
+        let mut scheduler = GainScheduler::new([Some(schedule()), None]);
+
+        assert!(scheduler.update(&mut motors).unwrap());
+        assert_eq!(motors.get_pid_gains().unwrap()[0], pid(1.0));
+        assert!(motors.get_pid_gains().unwrap()[1].p.is_nan());
+
+        // Gains changed elsewhere are not overwritten by the next sends
+        motors.set_pid_gains([pid(1.0), pid(7.0)]).unwrap();
+        motors.fake_io().set_current_position([0.5, 0.0]);
+        assert!(scheduler.update(&mut motors).unwrap());
+        assert_eq!(motors.get_pid_gains().unwrap(), [pid(2.0), pid(7.0)]);
+    }
+
+    #[test]
+    fn serde() {
+        let json = r#"{"variable": "torque", "points": [
+            {"at": 0.0, "gains": {"p": 1.0, "i": 0.0, "d": 0.1}},
+            {"at": 1.0, "gains": {"p": 3.0, "i": 0.0, "d": 0.1}}
+        ]}"#;
+        let schedule: GainSchedule = serde_json::from_str(json).unwrap();
+        assert_eq!(schedule.variable(), ScheduleVariable::Torque);
+        assert_eq!(schedule.gains(0.5), pid(2.0));
+
+        let json = serde_json::to_string(&schedule).unwrap();
+        assert_eq!(
+            serde_json::from_str::<GainSchedule>(&json).unwrap(),
+            schedule
+        );
+
+        let unsorted = r#"{"variable": "torque", "points": [
+            {"at": 1.0, "gains": {"p": 1.0, "i": 0.0, "d": 0.1}},
+            {"at": 0.0, "gains": {"p": 3.0, "i": 0.0, "d": 0.1}}
+        ]}"#;
+        assert!(serde_json::from_str::<GainSchedule>(unsorted).is_err());
+    }
+}
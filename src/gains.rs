@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+
+use crate::PID;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+/// Extended control gains of a motor
+///
+/// Fields left to None are not changed when the gains are sent to the motors.
+pub struct Gains {
+    /// Proportional gain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p: Option<f64>,
+    /// Integral gain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub i: Option<f64>,
+    /// Derivative gain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<f64>,
+    /// Velocity feedforward gain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity_feedforward: Option<f64>,
+    /// Acceleration feedforward gain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acceleration_feedforward: Option<f64>,
+    /// Cutoff frequency of the derivative filter (in Hz)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derivative_filter: Option<f64>,
+    /// Maximum magnitude of the integral term
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integral_limit: Option<f64>,
+}
+
+impl Gains {
+    /// Check that all the set gains are finite and non-negative
+    pub fn validate(&self) -> Result<(), InvalidGainsError> {
+        for (name, value) in self.fields() {
+            if let Some(value) = value {
+                if !value.is_finite() || value < 0.0 {
+                    return Err(InvalidGainsError(format!("{name} = {value}")));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Basic PID gains, the missing ones being taken from `current`
+    pub fn to_pid(&self, current: PID) -> PID {
+        PID {
+            p: self.p.unwrap_or(current.p),
+            i: self.i.unwrap_or(current.i),
+            d: self.d.unwrap_or(current.d),
+        }
+    }
+
+    /// Fields of the gains set but not honored by a backend
+    pub fn unsupported(&self, capabilities: &GainCapabilities) -> Vec<&'static str> {
+        self.fields()
+            .into_iter()
+            .zip(capabilities.fields())
+            .filter(|((_, value), supported)| value.is_some() && !supported)
+            .map(|((name, _), _)| name)
+            .collect()
+    }
+
+    fn fields(&self) -> [(&'static str, Option<f64>); 7] {
+        [
+            ("p", self.p),
+            ("i", self.i),
+            ("d", self.d),
+            ("velocity_feedforward", self.velocity_feedforward),
+            ("acceleration_feedforward", self.acceleration_feedforward),
+            ("derivative_filter", self.derivative_filter),
+            ("integral_limit", self.integral_limit),
+        ]
+    }
+}
+
+impl From<PID> for Gains {
+    /// Extended gains from basic PID gains (NaN gains are considered unknown)
+    fn from(pid: PID) -> Self {
+        let known = |value: f64| (!value.is_nan()).then_some(value);
+        Self {
+            p: known(pid.p),
+            i: known(pid.i),
+            d: known(pid.d),
+            ..Default::default()
+        }
+    }
+}
+
+impl PID {
+    /// Check that the gains are finite and non-negative
+    pub fn validate(&self) -> Result<(), InvalidGainsError> {
+        Gains {
+            p: Some(self.p),
+            i: Some(self.i),
+            d: Some(self.d),
+            ..Default::default()
+        }
+        .validate()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Gain fields honored by a backend
+pub struct GainCapabilities {
+    pub p: bool,
+    pub i: bool,
+    pub d: bool,
+    pub velocity_feedforward: bool,
+    pub acceleration_feedforward: bool,
+    pub derivative_filter: bool,
+    pub integral_limit: bool,
+}
+
+impl GainCapabilities {
+    /// Only the basic PID gains
+    pub const PID: Self = Self {
+        p: true,
+        i: true,
+        d: true,
+        velocity_feedforward: false,
+        acceleration_feedforward: false,
+        derivative_filter: false,
+        integral_limit: false,
+    };
+
+    /// All the extended gains
+    pub const ALL: Self = Self {
+        p: true,
+        i: true,
+        d: true,
+        velocity_feedforward: true,
+        acceleration_feedforward: true,
+        derivative_filter: true,
+        integral_limit: true,
+    };
+
+    fn fields(&self) -> [bool; 7] {
+        [
+            self.p,
+            self.i,
+            self.d,
+            self.velocity_feedforward,
+            self.acceleration_feedforward,
+            self.derivative_filter,
+            self.integral_limit,
+        ]
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidGainsError(pub String);
+impl std::fmt::Display for InvalidGainsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gain = &self.0;
+        write!(
+            f,
+            "(invalid gain {gain}, gains must be finite and non-negative)"
+        )
+    }
+}
+impl std::error::Error for InvalidGainsError {}
+
+#[derive(Debug)]
+pub struct UnsupportedGainsError(pub Vec<&'static str>);
+impl std::fmt::Display for UnsupportedGainsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gains = &self.0;
+        write!(f, "(gains {gains:?} not supported by the motors)")
+    }
+}
+impl std::error::Error for UnsupportedGainsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{GainCapabilities, Gains};
+    use crate::PID;
+
+    #[test]
+    fn pid_conversion() {
+        let pid = PID {
+            p: 1.0,
+            i: f64::NAN,
+            d: 3.0,
+        };
+        let gains = Gains::from(pid);
+        assert_eq!(
+            gains,
+            Gains {
+                p: Some(1.0),
+                d: Some(3.0),
+                ..Default::default()
+            }
+        );
+
+        let current = PID {
+            p: 10.0,
+            i: 20.0,
+            d: 30.0,
+        };
+        assert_eq!(
+            gains.to_pid(current),
+            PID {
+                p: 1.0,
+                i: 20.0,
+                d: 3.0,
+            }
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert!(Gains::default().validate().is_ok());
+        assert!(Gains {
+            p: Some(1.0),
+            velocity_feedforward: Some(0.5),
+            ..Default::default()
+        }
+        .validate()
+        .is_ok());
+
+        assert!(Gains {
+            d: Some(f64::NAN),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(Gains {
+            integral_limit: Some(-1.0),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        assert!(PID {
+            p: 1.0,
+            i: 0.0,
+            d: 0.0,
+        }
+        .validate()
+        .is_ok());
+        assert!(PID {
+            p: -1.0,
+            i: 0.0,
+            d: 0.0,
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn capabilities() {
+        let gains = Gains {
+            p: Some(1.0),
+            velocity_feedforward: Some(0.5),
+            integral_limit: Some(2.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            gains.unsupported(&GainCapabilities::PID),
+            vec!["velocity_feedforward", "integral_limit"]
+        );
+        assert!(gains.unsupported(&GainCapabilities::ALL).is_empty());
+    }
+
+    #[test]
+    fn serde() {
+        let gains: Gains =
+            serde_json::from_str(r#"{"p": 2.0, "derivative_filter": 50.0}"#).unwrap();
+        assert_eq!(
+            gains,
+            Gains {
+                p: Some(2.0),
+                derivative_filter: Some(50.0),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&gains).unwrap(),
+            r#"{"p":2.0,"derivative_filter":50.0}"#
+        );
+    }
+}
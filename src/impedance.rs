@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::InvalidGainsError;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+/// Hybrid impedance command of a motor (MIT-style)
+///
+/// The motor applies `torque + kp * (position - current_position) + kd * (velocity - current_velocity)`.
+pub struct ImpedanceCommand {
+    /// Target position (in radians)
+    pub position: f64,
+    /// Target velocity (in rad/s)
+    pub velocity: f64,
+    /// Stiffness (in Nm/rad)
+    pub kp: f64,
+    /// Damping (in Nm·s/rad)
+    pub kd: f64,
+    /// Feedforward torque (in Nm)
+    pub torque: f64,
+}
+
+impl ImpedanceCommand {
+    /// Check that the stiffness and damping are finite and non-negative
+    pub fn validate(&self) -> Result<(), InvalidGainsError> {
+        for (name, value) in [("kp", self.kp), ("kd", self.kd)] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(InvalidGainsError(format!("{name} = {value}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Torque applied at a given position (in radians) and velocity (in rad/s)
+    pub fn torque(&self, position: f64, velocity: f64) -> f64 {
+        self.torque + self.kp * (self.position - position) + self.kd * (self.velocity - velocity)
+    }
+
+    /// Same command seen from the motor side of an offset and a reduction
+    ///
+    /// Positions are offset then multiplied by the reduction, velocities are
+    /// multiplied by the reduction. The torque is divided by the torque ratio
+    /// (the reduction itself with a [Transmission](crate::Transmission), or the
+    /// sign of the joint when target torques are sent as is), and the stiffness
+    /// and damping by the product of the reduction and the torque ratio, so
+    /// that the motor torque is the joint one divided by the torque ratio.
+    pub fn to_motor(
+        &self,
+        offset: Option<f64>,
+        reduction: Option<f64>,
+        torque_ratio: Option<f64>,
+    ) -> Self {
+        let offset = offset.unwrap_or(0.0);
+        let reduction = reduction.unwrap_or(1.0);
+        let torque_ratio = torque_ratio.unwrap_or(1.0);
+        Self {
+            position: (self.position + offset) * reduction,
+            velocity: self.velocity * reduction,
+            kp: self.kp / (reduction * torque_ratio),
+            kd: self.kd / (reduction * torque_ratio),
+            torque: self.torque / torque_ratio,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImpedanceCommand;
+
+    fn command() -> ImpedanceCommand {
+        ImpedanceCommand {
+            position: 1.0,
+            velocity: 0.5,
+            kp: 10.0,
+            kd: 2.0,
+            torque: 0.3,
+        }
+    }
+
+    #[test]
+    fn torque() {
+        let command = command();
+        assert!((command.torque(1.0, 0.5) - 0.3).abs() < 1e-12);
+        assert!((command.torque(0.9, 0.0) - (0.3 + 1.0 + 1.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn to_motor() {
+        let joint = command();
+        let motor = joint.to_motor(Some(0.2), Some(-4.0), Some(-4.0));
+        assert_eq!(motor.position, -4.8);
+        assert_eq!(motor.velocity, -2.0);
+
+        // The motor torque for a joint state is the joint torque divided by the torque ratio
+        let (position, velocity) = (0.7, 0.1);
+        for ratio in [-4.0, -1.0] {
+            let motor = joint.to_motor(Some(0.2), Some(-4.0), Some(ratio));
+            let motor_torque = motor.torque((position + 0.2) * -4.0, velocity * -4.0);
+            assert!((motor_torque - joint.torque(position, velocity) / ratio).abs() < 1e-12);
+        }
+
+        assert_eq!(joint.to_motor(None, None, None), joint);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(command().validate().is_ok());
+        assert!(ImpedanceCommand {
+            kp: -1.0,
+            ..command()
+        }
+        .validate()
+        .is_err());
+        assert!(ImpedanceCommand {
+            kd: f64::NAN,
+            ..command()
+        }
+        .validate()
+        .is_err());
+    }
+}
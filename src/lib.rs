@@ -1,18 +1,102 @@
 // #![feature(generic_const_exprs)]
 #![allow(incomplete_features)]
 
+mod autotune;
+pub use autotune::{
+    AutoTuneState, AutoTuneTimeoutError, InvalidRelayConfigError, RelayAutoTuner, RelayConfig,
+    TuningRule, UltimateParameters,
+};
+
+mod calibration;
+pub use calibration::{
+    calibrate_zero, Homing, HomingConfig, HomingState, HomingTimeoutError, UncalibratedJointsError,
+};
+
+mod composite;
+pub use composite::{
+    CompositeController, CompositeCycle, CompositeError, CompositeIO, CompositePart,
+    InvalidCompositeError, PartError, PartReport,
+};
+
+mod config;
+pub use config::MotorsConfig;
+
+mod coupled_limit;
+pub use coupled_limit::{CoupledLimit, CoupledLimits, InvalidCoupledLimitError};
+
+mod emergency_stop;
+pub use emergency_stop::{EmergencyStop, EmergencyStopError, PendingStopError, StopMode};
+
+mod estimation;
+pub use estimation::{FeedbackFilter, VelocityEstimator};
+
 mod fake_motor;
-pub use fake_motor::FakeMotorsController;
+pub use fake_motor::{FakeDynamics, FakeMotorsController, FakeMotorsIO};
+
+mod feedforward;
+pub use feedforward::{Feedforward, FrictionModel, GravityModel};
+
+mod gain_schedule;
+pub use gain_schedule::{
+    GainSchedule, GainScheduler, InvalidScheduleError, SchedulePoint, ScheduleVariable,
+};
+
+mod gains;
+pub use gains::{GainCapabilities, Gains, InvalidGainsError, UnsupportedGainsError};
+
+mod impedance;
+pub use impedance::ImpedanceCommand;
 
 mod limit;
 pub use limit::Limit;
 
+mod lookup_table;
+pub use lookup_table::{InvalidLookupTableError, LookupTable};
+
+mod mimic;
+pub use mimic::{InvalidMimicError, MimicDisagreementError, MimicIO, MimicMotor};
+
 mod motors_io;
 pub use motors_io::RawMotorsIO;
 mod motors_controller;
-pub use motors_controller::{MissingRegisterErrror, MotorsController};
+pub use motors_controller::{
+    CoupledJointsError, MissingRegisterErrror, MotorsController, OffsetSpace, TorqueRamp,
+};
 
 mod pid;
-pub use pid::PID;
+pub use pid::{AntiWindup, PidController, PID};
+
+mod serde_array;
+
+mod shutdown;
+pub use shutdown::{park_and_release, Release, ShutdownConfig, ShutdownError, ShutdownGuard};
+
+mod soft_limit;
+pub use soft_limit::{SoftLimit, SoftLimits};
+
+mod teach;
+pub use teach::{InvalidPlaybackOptionsError, PlaybackOptions, RecordedTrajectory, Recorder};
+
+mod trajectory;
+pub use trajectory::{
+    Interpolation, InvalidWaypointError, Progress, StreamState, TrajectoryStreamer, Waypoint,
+};
+
+#[cfg(test)]
+mod test_utils;
+
+mod transmission;
+pub use transmission::{InvalidTransmissionError, Transmission};
+
+mod unwrapping;
+pub use unwrapping::Unwrapper;
+
+mod view;
+pub use view::{ControllerView, InvalidViewError, SplitController, ViewConflictError, ViewIO};
+
+mod watchdog;
+pub use watchdog::{
+    spawn_monitor, Watchdog, WatchdogReaction, WatchdogState, WatchdogTriggeredError,
+};
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
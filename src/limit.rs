@@ -1,21 +1,82 @@
+use std::f64::consts::TAU;
+
 use serde::{Deserialize, Serialize};
 
+use crate::unwrapping::wrap;
+
 #[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "LimitDef", into = "LimitDef")]
 /// Limit wrapper
+///
+/// A limit is either a (possibly one-sided or unbounded) interval, or an
+/// angular arc going counterclockwise from `min` to `max`, which wraps around
+/// ±π when `min > max`.
 pub struct Limit {
     /// lower limit
     min: f64,
     /// upper limit
     max: f64,
+    /// angular arc (positions compared modulo 2π)
+    angular: bool,
 }
 
 impl Limit {
     /// Create new limit
+    ///
+    /// # Panics
+    /// If `min > max` (see [try_new](Self::try_new) for a fallible version).
     pub fn new(min: f64, max: f64) -> Self {
-        if min > max {
-            panic!("min must be less than max");
+        Self::try_new(min, max).unwrap()
+    }
+
+    /// Create new limit, checking that `min <= max`
+    ///
+    /// Infinite bounds are allowed to express one-sided or unbounded limits.
+    pub fn try_new(min: f64, max: f64) -> Result<Self, &'static str> {
+        if min.is_nan() || max.is_nan() {
+            Err("limits must not be NaN")
+        } else if min > max {
+            Err("min must be less than max")
+        } else {
+            Ok(Self {
+                min,
+                max,
+                angular: false,
+            })
+        }
+    }
+
+    /// Unbounded limit (continuous joint)
+    pub fn continuous() -> Self {
+        Self::new(f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    /// Lower limit only
+    pub fn at_least(min: f64) -> Result<Self, &'static str> {
+        Self::try_new(min, f64::INFINITY)
+    }
+
+    /// Upper limit only
+    pub fn at_most(max: f64) -> Result<Self, &'static str> {
+        Self::try_new(f64::NEG_INFINITY, max)
+    }
+
+    /// Angular arc going counterclockwise from `min` to `max` (in radians)
+    ///
+    /// The arc wraps around ±π when `min > max`, e.g. `angular(3.0, -3.0)`
+    /// only allows angles close to π. A full turn gives a continuous limit.
+    pub fn angular(min: f64, max: f64) -> Result<Self, &'static str> {
+        if !min.is_finite() || !max.is_finite() {
+            Err("angular limits must be finite")
+        } else if max - min >= TAU {
+            Ok(Self::continuous())
+        } else {
+            Ok(Self {
+                min: wrap(min),
+                max: wrap(max),
+                angular: true,
+            })
         }
-        Self { min, max }
     }
 
     /// lower limit
@@ -28,9 +89,44 @@ impl Limit {
         self.max
     }
 
+    /// Check if the limit is an angular arc
+    pub fn is_angular(&self) -> bool {
+        self.angular
+    }
+
+    /// Check if the limit does not restrict any value
+    pub fn is_continuous(&self) -> bool {
+        !self.angular && self.min == f64::NEG_INFINITY && self.max == f64::INFINITY
+    }
+
+    /// Check if a value is within limits
+    pub fn contains(&self, value: f64) -> bool {
+        if self.angular {
+            (value - self.min).rem_euclid(TAU) <= (self.max - self.min).rem_euclid(TAU)
+        } else {
+            self.min <= value && value <= self.max
+        }
+    }
+
     /// Clamp value to limits
+    ///
+    /// For angular arcs, values outside the arc are moved to the closest bound
+    /// along the shortest path, staying in the same turn as the value.
     pub fn clamp(&self, value: f64) -> f64 {
-        value.clamp(self.min, self.max)
+        if !self.angular {
+            return value.clamp(self.min, self.max);
+        }
+        if self.contains(value) {
+            return value;
+        }
+
+        let to_min = wrap(self.min - value);
+        let to_max = wrap(self.max - value);
+        if to_min.abs() <= to_max.abs() {
+            value + to_min
+        } else {
+            value + to_max
+        }
     }
 }
 
@@ -38,16 +134,73 @@ impl TryFrom<(f64, f64)> for Limit {
     type Error = &'static str;
 
     fn try_from(value: (f64, f64)) -> Result<Self, Self::Error> {
-        if value.0 > value.1 {
-            Err("min must be less than max")
+        Self::try_new(value.0, value.1)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Continuous {
+    Continuous,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+/// Serialized forms of a limit
+enum LimitDef {
+    Range { min: f64, max: f64 },
+    Angular { angular: Range },
+    AtLeast { min: f64 },
+    AtMost { max: f64 },
+    Continuous(Continuous),
+}
+
+#[derive(Deserialize, Serialize)]
+struct Range {
+    min: f64,
+    max: f64,
+}
+
+impl TryFrom<LimitDef> for Limit {
+    type Error = &'static str;
+
+    fn try_from(value: LimitDef) -> Result<Self, Self::Error> {
+        match value {
+            LimitDef::Range { min, max } => Self::try_new(min, max),
+            LimitDef::Angular {
+                angular: Range { min, max },
+            } => Self::angular(min, max),
+            LimitDef::AtLeast { min } => Self::at_least(min),
+            LimitDef::AtMost { max } => Self::at_most(max),
+            LimitDef::Continuous(_) => Ok(Self::continuous()),
+        }
+    }
+}
+
+impl From<Limit> for LimitDef {
+    fn from(limit: Limit) -> Self {
+        let Limit { min, max, angular } = limit;
+
+        if angular {
+            LimitDef::Angular {
+                angular: Range { min, max },
+            }
+        } else if limit.is_continuous() {
+            LimitDef::Continuous(Continuous::Continuous)
+        } else if max == f64::INFINITY {
+            LimitDef::AtLeast { min }
+        } else if min == f64::NEG_INFINITY {
+            LimitDef::AtMost { max }
         } else {
-            Ok(Self::new(value.0, value.1))
+            LimitDef::Range { min, max }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::f64::consts::{PI, TAU};
+
     use crate::Limit;
 
     #[test]
@@ -88,4 +241,81 @@ mod tests {
         let limit: Result<Limit, _> = (1.0, 0.0).try_into();
         assert!(limit.is_err());
     }
+
+    #[test]
+    fn fallible() {
+        assert!(Limit::try_new(1.0, -1.0).is_err());
+        assert!(Limit::try_new(f64::NAN, 1.0).is_err());
+        assert_eq!(Limit::try_new(-1.0, 1.0).unwrap(), Limit::new(-1.0, 1.0));
+        assert!(Limit::angular(f64::INFINITY, 1.0).is_err());
+    }
+
+    #[test]
+    fn one_sided() {
+        let lower = Limit::at_least(-1.0).unwrap();
+        assert_eq!(lower.clamp(-2.0), -1.0);
+        assert_eq!(lower.clamp(1e9), 1e9);
+
+        let upper = Limit::at_most(1.0).unwrap();
+        assert_eq!(upper.clamp(2.0), 1.0);
+        assert_eq!(upper.clamp(-1e9), -1e9);
+
+        let continuous = Limit::continuous();
+        assert!(continuous.is_continuous());
+        assert!(!lower.is_continuous());
+        assert_eq!(continuous.clamp(-1e9), -1e9);
+    }
+
+    #[test]
+    fn angular() {
+        let limit = Limit::angular(-1.0, 1.0).unwrap();
+        assert!(limit.is_angular());
+        assert!(limit.contains(0.5));
+        assert!(limit.contains(0.5 + TAU));
+        assert!(!limit.contains(2.0));
+        assert!(Limit::angular(-PI, PI).unwrap().is_continuous());
+
+        assert_eq!(limit.clamp(0.5 + TAU), 0.5 + TAU);
+        assert!((limit.clamp(2.0) - 1.0).abs() < 1e-9);
+        assert!((limit.clamp(-2.0) - -1.0).abs() < 1e-9);
+        // Shortest path, staying in the same turn
+        assert!((limit.clamp(2.0 + TAU) - (1.0 + TAU)).abs() < 1e-9);
+        assert!((limit.clamp(PI - 0.5) - 1.0).abs() < 1e-9);
+        assert!((limit.clamp(PI + 0.5) - (TAU - 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn wrap_around() {
+        let limit = Limit::angular(2.5, -2.5).unwrap();
+        assert!(limit.contains(PI));
+        assert!(limit.contains(-PI));
+        assert!(limit.contains(3.0));
+        assert!(limit.contains(-3.0));
+        assert!(!limit.contains(0.0));
+
+        assert_eq!(limit.clamp(-3.0), -3.0);
+        assert!((limit.clamp(2.0) - 2.5).abs() < 1e-9);
+        assert!((limit.clamp(-2.0) - -2.5).abs() < 1e-9);
+        assert!((limit.clamp(0.1) - 2.5).abs() < 1e-9);
+        assert!((limit.clamp(-0.1) - -2.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn serde() {
+        for (limit, json) in [
+            (Limit::new(-1.0, 1.0), r#"{"min":-1.0,"max":1.0}"#),
+            (Limit::at_least(-1.0).unwrap(), r#"{"min":-1.0}"#),
+            (Limit::at_most(1.0).unwrap(), r#"{"max":1.0}"#),
+            (Limit::continuous(), r#""continuous""#),
+            (
+                Limit::angular(2.5, -2.5).unwrap(),
+                r#"{"angular":{"min":2.5,"max":-2.5}}"#,
+            ),
+        ] {
+            assert_eq!(serde_json::to_string(&limit).unwrap(), json);
+            assert_eq!(serde_json::from_str::<Limit>(json).unwrap(), limit);
+        }
+
+        assert!(serde_json::from_str::<Limit>(r#"{"min":1.0,"max":-1.0}"#).is_err());
+    }
 }
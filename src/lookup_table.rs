@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of iterations to invert a spline segment
+const MAX_ITERATIONS: usize = 100;
+/// Tolerance on the inverted position (in radians)
+const TOLERANCE: f64 = 1e-12;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "LookupTableDef", into = "LookupTableDef")]
+/// Nonlinear transmission of a joint, calibrated as a table of (joint, motor) positions
+///
+/// The motor position is interpolated with a monotonic cubic spline
+/// (Fritsch-Butland), so that the transmission stays invertible between the
+/// points, and linearly extrapolated outside of them.
+pub struct LookupTable {
+    joint: Vec<f64>,
+    motor: Vec<f64>,
+    tangents: Vec<f64>,
+}
+
+impl LookupTable {
+    /// Create a table from (joint, motor) positions (in radians)
+    ///
+    /// Joint positions must be increasing, and motor positions strictly
+    /// increasing or decreasing.
+    pub fn new(points: Vec<[f64; 2]>) -> Result<Self, InvalidLookupTableError> {
+        if points.len() < 2 {
+            return Err(InvalidLookupTableError("less than 2 points".to_string()));
+        }
+        if points.iter().flatten().any(|v| !v.is_finite()) {
+            return Err(InvalidLookupTableError("non finite position".to_string()));
+        }
+        let (joint, motor): (Vec<f64>, Vec<f64>) = points.iter().map(|p| (p[0], p[1])).unzip();
+
+        if joint.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(InvalidLookupTableError(
+                "joint positions must be increasing".to_string(),
+            ));
+        }
+        let slopes: Vec<f64> = (0..joint.len() - 1)
+            .map(|k| (motor[k + 1] - motor[k]) / (joint[k + 1] - joint[k]))
+            .collect();
+        if !(slopes.iter().all(|&s| s > 0.0) || slopes.iter().all(|&s| s < 0.0)) {
+            return Err(InvalidLookupTableError(
+                "motor positions must be strictly monotonic".to_string(),
+            ));
+        }
+
+        let mut tangents = vec![slopes[0]; joint.len()];
+        tangents[joint.len() - 1] = slopes[slopes.len() - 1];
+        for k in 1..joint.len() - 1 {
+            let (h0, h1) = (joint[k] - joint[k - 1], joint[k + 1] - joint[k]);
+            let (w0, w1) = (2.0 * h1 + h0, h1 + 2.0 * h0);
+            tangents[k] = (w0 + w1) / (w0 / slopes[k - 1] + w1 / slopes[k]);
+        }
+
+        Ok(Self {
+            joint,
+            motor,
+            tangents,
+        })
+    }
+
+    /// Calibration points, as (joint, motor) positions (in radians)
+    pub fn points(&self) -> Vec<[f64; 2]> {
+        self.joint
+            .iter()
+            .zip(&self.motor)
+            .map(|(&j, &m)| [j, m])
+            .collect()
+    }
+
+    /// Motor position for a joint position (in radians)
+    pub fn to_motor(&self, position: f64) -> f64 {
+        let last = self.joint.len() - 1;
+        if position <= self.joint[0] {
+            return self.motor[0] + self.tangents[0] * (position - self.joint[0]);
+        }
+        if position >= self.joint[last] {
+            return self.motor[last] + self.tangents[last] * (position - self.joint[last]);
+        }
+        let k = self.segment(position);
+        let (h, t) = self.local(k, position);
+        let (t2, t3) = (t * t, t * t * t);
+        (2.0 * t3 - 3.0 * t2 + 1.0) * self.motor[k]
+            + (t3 - 2.0 * t2 + t) * h * self.tangents[k]
+            + (-2.0 * t3 + 3.0 * t2) * self.motor[k + 1]
+            + (t3 - t2) * h * self.tangents[k + 1]
+    }
+
+    /// Joint position for a motor position (in radians)
+    pub fn to_joint(&self, position: f64) -> f64 {
+        if position.is_nan() {
+            return f64::NAN;
+        }
+        let last = self.joint.len() - 1;
+        let increasing = self.motor[last] > self.motor[0];
+        // Position along the increasing motor direction
+        let along = |motor: f64| if increasing { motor } else { -motor };
+
+        if along(position) <= along(self.motor[0]) {
+            return self.joint[0] + (position - self.motor[0]) / self.tangents[0];
+        }
+        if along(position) >= along(self.motor[last]) {
+            return self.joint[last] + (position - self.motor[last]) / self.tangents[last];
+        }
+
+        let k = self
+            .motor
+            .partition_point(|&m| along(m) <= along(position))
+            .clamp(1, last)
+            - 1;
+        // Safeguarded Newton iterations inside the segment
+        let (mut low, mut high) = (self.joint[k], self.joint[k + 1]);
+        let mut joint =
+            low + (position - self.motor[k]) / (self.motor[k + 1] - self.motor[k]) * (high - low);
+        for _ in 0..MAX_ITERATIONS {
+            let error = self.to_motor(joint) - position;
+            if along(error) > 0.0 {
+                high = joint;
+            } else {
+                low = joint;
+            }
+            let next = joint - error / self.jacobian(joint);
+            let next = match next > low && next < high {
+                true => next,
+                false => (low + high) / 2.0,
+            };
+            if (next - joint).abs() < TOLERANCE {
+                return next;
+            }
+            joint = next;
+        }
+        joint
+    }
+
+    /// Local ratio between the motor and joint motions at a joint position (d motor / d joint)
+    ///
+    /// Motor velocities are the joint velocities multiplied by it, and joint
+    /// torques the motor torques multiplied by it.
+    pub fn jacobian(&self, position: f64) -> f64 {
+        let last = self.joint.len() - 1;
+        if position <= self.joint[0] {
+            return self.tangents[0];
+        }
+        if position >= self.joint[last] {
+            return self.tangents[last];
+        }
+        let k = self.segment(position);
+        let (h, t) = self.local(k, position);
+        (6.0 * t * t - 6.0 * t) * (self.motor[k] - self.motor[k + 1]) / h
+            + (3.0 * t * t - 4.0 * t + 1.0) * self.tangents[k]
+            + (3.0 * t * t - 2.0 * t) * self.tangents[k + 1]
+    }
+
+    fn segment(&self, position: f64) -> usize {
+        let last = self.joint.len() - 1;
+        self.joint
+            .partition_point(|&j| j <= position)
+            .clamp(1, last)
+            - 1
+    }
+
+    fn local(&self, k: usize, position: f64) -> (f64, f64) {
+        let h = self.joint[k + 1] - self.joint[k];
+        (h, (position - self.joint[k]) / h)
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+/// Serialized form of a lookup table
+struct LookupTableDef {
+    points: Vec<[f64; 2]>,
+}
+
+impl TryFrom<LookupTableDef> for LookupTable {
+    type Error = InvalidLookupTableError;
+
+    fn try_from(value: LookupTableDef) -> Result<Self, Self::Error> {
+        Self::new(value.points)
+    }
+}
+
+impl From<LookupTable> for LookupTableDef {
+    fn from(table: LookupTable) -> Self {
+        Self {
+            points: table.points(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidLookupTableError(pub String);
+impl std::fmt::Display for InvalidLookupTableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid lookup table: {reason})")
+    }
+}
+impl std::error::Error for InvalidLookupTableError {}
+
+#[cfg(test)]
+mod tests {
+    use super::LookupTable;
+
+    /// Linkage whose ratio goes from 2 to about 0.5
+    fn linkage() -> LookupTable {
+        LookupTable::new(vec![
+            [-1.0, -2.0],
+            [0.0, 0.0],
+            [0.5, 0.6],
+            [1.0, 0.9],
+            [2.0, 1.4],
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn interpolation() {
+        let table = linkage();
+        for [joint, motor] in table.points() {
+            assert!((table.to_motor(joint) - motor).abs() < 1e-12);
+        }
+
+        // Monotonic between the points
+        let mut last = f64::NEG_INFINITY;
+        for k in -150..=250 {
+            let motor = table.to_motor(k as f64 * 0.01);
+            assert!(motor > last);
+            last = motor;
+        }
+
+        // Linear extrapolation
+        assert!((table.to_motor(3.0) - 1.9).abs() < 1e-12);
+    }
+
+    #[test]
+    fn inverse() {
+        let table = linkage();
+        let decreasing = LookupTable::new(vec![[0.0, 1.0], [1.0, 0.2], [2.0, -2.0]]).unwrap();
+
+        for table in [table, decreasing] {
+            for k in -300..=300 {
+                let joint = k as f64 * 0.01;
+                let motor = table.to_motor(joint);
+                assert!((table.to_joint(motor) - joint).abs() < 1e-9, "{joint}");
+            }
+        }
+    }
+
+    #[test]
+    fn jacobian() {
+        let table = linkage();
+        for k in -150..=250 {
+            let joint = k as f64 * 0.01 + 0.001;
+            let numeric = (table.to_motor(joint + 1e-6) - table.to_motor(joint - 1e-6)) / 2e-6;
+            assert!((table.jacobian(joint) - numeric).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(LookupTable::new(vec![[0.0, 0.0]]).is_err());
+        assert!(LookupTable::new(vec![[0.0, 0.0], [0.0, 1.0]]).is_err());
+        assert!(LookupTable::new(vec![[0.0, 0.0], [1.0, 1.0], [2.0, 0.5]]).is_err());
+        assert!(LookupTable::new(vec![[0.0, 0.0], [1.0, f64::NAN]]).is_err());
+    }
+
+    #[test]
+    fn serde() {
+        let table: LookupTable =
+            serde_json::from_str(r#"{"points": [[0.0, 0.0], [1.0, 2.0], [2.0, 3.0]]}"#).unwrap();
+        assert_eq!(table.points(), vec![[0.0, 0.0], [1.0, 2.0], [2.0, 3.0]]);
+
+        let json = serde_json::to_string(&table).unwrap();
+        assert_eq!(serde_json::from_str::<LookupTable>(&json).unwrap(), table);
+
+        assert!(serde_json::from_str::<LookupTable>(r#"{"points": [[0.0, 0.0]]}"#).is_err());
+    }
+}
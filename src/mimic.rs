@@ -0,0 +1,504 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{GainCapabilities, Gains, ImpedanceCommand, RawMotorsIO, Result, PID};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Physical motor driving a logical joint: `motor = ratio * joint + offset`
+pub struct MimicMotor {
+    /// Index of the logical joint
+    pub joint: usize,
+    /// Ratio between the motor and joint positions
+    pub ratio: f64,
+    /// Motor position when the joint is at zero (in radians)
+    #[serde(default)]
+    pub offset: f64,
+}
+
+impl MimicMotor {
+    /// Motor following a joint one to one
+    pub fn new(joint: usize) -> Self {
+        Self {
+            joint,
+            ratio: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+/// IO fanning out the commands of N logical joints to M physical motors
+///
+/// Several motors can drive the same joint, either mimicking each other (e.g.
+/// the two fingers of a gripper) or sharing its load. Positions and velocities
+/// are mapped through the ratio and offset of each motor, and torques are
+/// split evenly so that the motors add up to the commanded joint torque.
+///
+/// Readings of redundant motors are fused: positions and velocities are
+/// averaged, torques summed. When a position tolerance is set, reading positions
+/// fails with [MimicDisagreementError] if the motors of a joint disagree by more
+/// than it. Target readbacks, modes, gains and limits are read from the first
+/// motor of each joint (its leader), and gains are sent as is to every motor.
+pub struct MimicIO<IO, const N: usize, const M: usize>
+where
+    IO: RawMotorsIO<M>,
+{
+    inner: IO,
+    motors: [MimicMotor; M],
+    leaders: [usize; N],
+    counts: [usize; N],
+    tolerance: [Option<f64>; N],
+}
+
+impl<IO, const N: usize, const M: usize> MimicIO<IO, N, M>
+where
+    IO: RawMotorsIO<M>,
+{
+    /// Map the motors of an io to logical joints
+    ///
+    /// Every joint must be driven by at least one motor.
+    pub fn new(io: IO, motors: [MimicMotor; M]) -> std::result::Result<Self, InvalidMimicError> {
+        let mut leaders = [None; N];
+        let mut counts = [0; N];
+        for (k, motor) in motors.iter().enumerate() {
+            if motor.joint >= N {
+                return Err(InvalidMimicError(format!(
+                    "motor {k} drives joint {} out of {N}",
+                    motor.joint
+                )));
+            }
+            if !motor.ratio.is_finite() || motor.ratio == 0.0 || !motor.offset.is_finite() {
+                return Err(InvalidMimicError(format!(
+                    "motor {k} has ratio {} and offset {}",
+                    motor.ratio, motor.offset
+                )));
+            }
+            leaders[motor.joint].get_or_insert(k);
+            counts[motor.joint] += 1;
+        }
+        let mut joint_leaders = [0; N];
+        for (joint, leader) in leaders.iter().enumerate() {
+            joint_leaders[joint] =
+                leader.ok_or_else(|| InvalidMimicError(format!("joint {joint} has no motor")))?;
+        }
+
+        Ok(Self {
+            inner: io,
+            motors,
+            leaders: joint_leaders,
+            counts,
+            tolerance: [None; N],
+        })
+    }
+
+    /// Maximum disagreement between the positions of the motors of each joint (in radians)
+    pub fn with_position_tolerance(mut self, tolerance: [Option<f64>; N]) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Wrapped io
+    pub fn inner(&self) -> &IO {
+        &self.inner
+    }
+
+    /// Wrapped io (commands sent through it bypass the mimic relations)
+    pub fn inner_mut(&mut self) -> &mut IO {
+        &mut self.inner
+    }
+
+    /// Motors driving the joints
+    pub fn motors(&self) -> [MimicMotor; M] {
+        self.motors
+    }
+
+    /// Largest difference between the joint positions seen by the motors of each joint (in radians)
+    pub fn get_position_spread(&mut self) -> Result<[f64; N]> {
+        let position = self.inner.get_current_position()?;
+        Ok(self.spread(position))
+    }
+
+    fn fan_out<T: Copy>(&self, values: [T; N], map: impl Fn(&MimicMotor, T) -> T) -> [T; M] {
+        self.motors.map(|motor| map(&motor, values[motor.joint]))
+    }
+
+    fn leader<T: Copy>(&self, values: [T; M]) -> [T; N] {
+        self.leaders.map(|k| values[k])
+    }
+
+    fn sum(&self, values: [f64; M], map: impl Fn(&MimicMotor, f64) -> f64) -> [f64; N] {
+        let mut fused = [0.0; N];
+        for (motor, value) in self.motors.iter().zip(values) {
+            fused[motor.joint] += map(motor, value);
+        }
+        fused
+    }
+
+    fn mean(&self, values: [f64; M], map: impl Fn(&MimicMotor, f64) -> f64) -> [f64; N] {
+        let mut fused = self.sum(values, map);
+        for (fused, count) in fused.iter_mut().zip(self.counts) {
+            *fused /= count as f64;
+        }
+        fused
+    }
+
+    fn spread(&self, position: [f64; M]) -> [f64; N] {
+        let mut min = [f64::INFINITY; N];
+        let mut max = [f64::NEG_INFINITY; N];
+        for (motor, position) in self.motors.iter().zip(position) {
+            let position = (position - motor.offset) / motor.ratio;
+            min[motor.joint] = min[motor.joint].min(position);
+            max[motor.joint] = max[motor.joint].max(position);
+        }
+        let mut spread = [0.0; N];
+        for i in 0..N {
+            spread[i] = max[i] - min[i];
+        }
+        spread
+    }
+
+    /// Fused joint positions, checking the motors agree
+    fn joint_position(&self, position: [f64; M]) -> Result<[f64; N]> {
+        let disagreements: Vec<_> = self
+            .spread(position)
+            .iter()
+            .zip(self.tolerance)
+            .enumerate()
+            .filter_map(|(joint, (&spread, tolerance))| match tolerance {
+                Some(tolerance) if spread.is_nan() || spread > tolerance => Some((joint, spread)),
+                _ => None,
+            })
+            .collect();
+        if !disagreements.is_empty() {
+            log::warn!(target: "mimic_io::get_current_position", "redundant motors disagree: {:?}", disagreements);
+            return Err(Box::new(MimicDisagreementError(disagreements)));
+        }
+        Ok(self.mean(position, |motor, p| (p - motor.offset) / motor.ratio))
+    }
+
+    fn motor_position(&self, position: [f64; N]) -> [f64; M] {
+        self.fan_out(position, |motor, p| motor.ratio * p + motor.offset)
+    }
+
+    /// Impedance command of a motor, so that the motors of a joint add up to the joint impedance
+    fn motor_command(&self, motor: &MimicMotor, command: ImpedanceCommand) -> ImpedanceCommand {
+        let share = self.share(motor);
+        ImpedanceCommand {
+            position: motor.ratio * command.position + motor.offset,
+            velocity: motor.ratio * command.velocity,
+            kp: command.kp * share / motor.ratio,
+            kd: command.kd * share / motor.ratio,
+            torque: command.torque * share,
+        }
+    }
+
+    /// Share of a joint torque (or torque limit) applied by a motor
+    fn share(&self, motor: &MimicMotor) -> f64 {
+        1.0 / (self.counts[motor.joint] as f64 * motor.ratio)
+    }
+}
+
+impl<IO, const N: usize, const M: usize> RawMotorsIO<N> for MimicIO<IO, N, M>
+where
+    IO: RawMotorsIO<M>,
+{
+    fn is_torque_on(&mut self) -> Result<[bool; N]> {
+        let on = self.inner.is_torque_on()?;
+        let mut joint_on = [true; N];
+        for (motor, on) in self.motors.iter().zip(on) {
+            joint_on[motor.joint] &= on;
+        }
+        Ok(joint_on)
+    }
+
+    fn set_torque(&mut self, on: [bool; N]) -> Result<()> {
+        let on = self.fan_out(on, |_, on| on);
+        self.inner.set_torque(on)
+    }
+
+    fn get_current_position(&mut self) -> Result<[f64; N]> {
+        let position = self.inner.get_current_position()?;
+        self.joint_position(position)
+    }
+
+    fn get_current_velocity(&mut self) -> Result<[f64; N]> {
+        let velocity = self.inner.get_current_velocity()?;
+        Ok(self.mean(velocity, |motor, v| v / motor.ratio))
+    }
+
+    fn get_current_torque(&mut self) -> Result<[f64; N]> {
+        let torque = self.inner.get_current_torque()?;
+        Ok(self.sum(torque, |motor, t| t * motor.ratio))
+    }
+
+    fn get_target_position(&mut self) -> Result<[f64; N]> {
+        let position = self.inner.get_target_position()?;
+        let motors = self.leader(self.motors);
+        let mut position = self.leader(position);
+        for (position, motor) in position.iter_mut().zip(motors) {
+            *position = (*position - motor.offset) / motor.ratio;
+        }
+        Ok(position)
+    }
+
+    fn set_target_position(&mut self, position: [f64; N]) -> Result<()> {
+        let position = self.motor_position(position);
+        log::debug!(target: "mimic_io::set_target_position", "motor target_position: {:?}", position);
+        self.inner.set_target_position(position)
+    }
+
+    fn get_target_torque(&mut self) -> Result<[f64; N]> {
+        let torque = self.inner.get_target_torque()?;
+        Ok(self.sum(torque, |motor, t| t * motor.ratio))
+    }
+
+    fn set_target_torque(&mut self, torque: [f64; N]) -> Result<()> {
+        let torque = self.fan_out(torque, |motor, t| t * self.share(motor));
+        log::debug!(target: "mimic_io::set_target_torque", "motor target_torque: {:?}", torque);
+        self.inner.set_target_torque(torque)
+    }
+
+    fn set_target_velocity(&mut self, velocity: [f64; N]) -> Result<()> {
+        let velocity = self.fan_out(velocity, |motor, v| v * motor.ratio);
+        self.inner.set_target_velocity(velocity)
+    }
+
+    fn get_target_velocity(&mut self) -> Result<[f64; N]> {
+        let velocity = self.inner.get_target_velocity()?;
+        let motors = self.leader(self.motors);
+        let mut velocity = self.leader(velocity);
+        for (velocity, motor) in velocity.iter_mut().zip(motors) {
+            *velocity /= motor.ratio;
+        }
+        Ok(velocity)
+    }
+
+    fn set_control_mode(&mut self, mode: [u8; N]) -> Result<()> {
+        let mode = self.fan_out(mode, |_, mode| mode);
+        self.inner.set_control_mode(mode)
+    }
+
+    fn get_control_mode(&mut self) -> Result<[u8; N]> {
+        let mode = self.inner.get_control_mode()?;
+        Ok(self.leader(mode))
+    }
+
+    fn supports_impedance(&self) -> bool {
+        self.inner.supports_impedance()
+    }
+
+    fn set_impedance_command(&mut self, command: [ImpedanceCommand; N]) -> Result<()> {
+        let command = self.fan_out(command, |motor, command| self.motor_command(motor, command));
+        self.inner.set_impedance_command(command)
+    }
+
+    fn set_target_position_fb(&mut self, position: [f64; N]) -> Result<[f64; N]> {
+        let position = self.motor_position(position);
+        let fb = self.inner.set_target_position_fb(position)?;
+        self.joint_position(fb)
+    }
+
+    fn get_velocity_limit(&mut self) -> Result<[f64; N]> {
+        let velocity = self.inner.get_velocity_limit()?;
+        let motors = self.leader(self.motors);
+        let mut velocity = self.leader(velocity);
+        for (velocity, motor) in velocity.iter_mut().zip(motors) {
+            *velocity /= motor.ratio.abs();
+        }
+        Ok(velocity)
+    }
+
+    fn set_velocity_limit(&mut self, velocity: [f64; N]) -> Result<()> {
+        let velocity = self.fan_out(velocity, |motor, v| v * motor.ratio.abs());
+        self.inner.set_velocity_limit(velocity)
+    }
+
+    fn get_torque_limit(&mut self) -> Result<[f64; N]> {
+        let torque = self.inner.get_torque_limit()?;
+        Ok(self.sum(torque, |motor, t| t * motor.ratio.abs()))
+    }
+
+    fn set_torque_limit(&mut self, torque: [f64; N]) -> Result<()> {
+        let torque = self.fan_out(torque, |motor, t| t * self.share(motor).abs());
+        self.inner.set_torque_limit(torque)
+    }
+
+    fn get_pid_gains(&mut self) -> Result<[PID; N]> {
+        let pid = self.inner.get_pid_gains()?;
+        Ok(self.leader(pid))
+    }
+
+    fn set_pid_gains(&mut self, pid: [PID; N]) -> Result<()> {
+        let pid = self.fan_out(pid, |_, pid| pid);
+        self.inner.set_pid_gains(pid)
+    }
+
+    fn gain_capabilities(&self) -> GainCapabilities {
+        self.inner.gain_capabilities()
+    }
+
+    fn get_gains(&mut self) -> Result<[Gains; N]> {
+        let gains = self.inner.get_gains()?;
+        Ok(self.leader(gains))
+    }
+
+    fn set_gains(&mut self, gains: [Gains; N]) -> Result<()> {
+        let gains = self.fan_out(gains, |_, gains| gains);
+        self.inner.set_gains(gains)
+    }
+
+    fn get_axis_sensors(&mut self) -> Result<[f64; N]> {
+        let sensors = self.inner.get_axis_sensors()?;
+        Ok(self.leader(sensors))
+    }
+
+    fn get_board_state(&mut self) -> Result<u8> {
+        self.inner.get_board_state()
+    }
+
+    fn set_board_state(&mut self, state: u8) -> Result<()> {
+        self.inner.set_board_state(state)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidMimicError(pub String);
+impl std::fmt::Display for InvalidMimicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid mimic relation: {reason})")
+    }
+}
+impl std::error::Error for InvalidMimicError {}
+
+#[derive(Debug)]
+/// Joints whose redundant motors disagree, with their spread (in radians)
+pub struct MimicDisagreementError(pub Vec<(usize, f64)>);
+impl std::fmt::Display for MimicDisagreementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let disagreements = &self.0;
+        write!(
+            f,
+            "(redundant motors disagree (joint, spread): {disagreements:?})"
+        )
+    }
+}
+impl std::error::Error for MimicDisagreementError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{MimicIO, MimicMotor};
+    use crate::{FakeMotorsIO, ImpedanceCommand, RawMotorsIO};
+
+    /// Joint 0 driven by two opposed motors, joint 1 by a single geared motor
+    fn gripper() -> MimicIO<FakeMotorsIO<3>, 2, 3> {
+        MimicIO::new(
+            FakeMotorsIO::default(),
+            [
+                MimicMotor::new(0),
+                MimicMotor {
+                    joint: 0,
+                    ratio: -1.0,
+                    offset: 0.1,
+                },
+                MimicMotor {
+                    joint: 1,
+                    ratio: 2.0,
+                    offset: 0.0,
+                },
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fan_out() {
+        let mut io = gripper();
+        io.set_torque([true; 2]).unwrap();
+        assert_eq!(io.inner_mut().is_torque_on().unwrap(), [true; 3]);
+
+        io.set_target_position([0.5, 0.25]).unwrap();
+        assert_eq!(
+            io.inner_mut().get_target_position().unwrap(),
+            [0.5, -0.4, 0.5]
+        );
+        assert_eq!(io.get_target_position().unwrap(), [0.5, 0.25]);
+        assert_eq!(io.get_current_position().unwrap(), [0.5, 0.25]);
+
+        io.set_target_velocity([1.0, 1.0]).unwrap();
+        assert_eq!(
+            io.inner_mut().get_target_velocity().unwrap(),
+            [1.0, -1.0, 2.0]
+        );
+        assert_eq!(io.get_target_velocity().unwrap(), [1.0, 1.0]);
+
+        // The load is shared and the motor torques add up to the joint torque
+        io.set_target_torque([1.0, 1.0]).unwrap();
+        assert_eq!(
+            io.inner_mut().get_target_torque().unwrap(),
+            [0.5, -0.5, 0.5]
+        );
+        assert_eq!(io.get_target_torque().unwrap(), [1.0, 1.0]);
+
+        io.set_torque_limit([2.0, 1.0]).unwrap();
+        assert_eq!(io.inner_mut().get_torque_limit().unwrap(), [1.0, 1.0, 0.5]);
+        assert_eq!(io.get_torque_limit().unwrap(), [2.0, 1.0]);
+    }
+
+    #[test]
+    fn impedance() {
+        let mut io = gripper();
+        io.set_torque([true; 2]).unwrap();
+        let command = ImpedanceCommand {
+            position: 0.5,
+            velocity: 0.2,
+            kp: 10.0,
+            kd: 1.0,
+            torque: 1.0,
+        };
+        io.set_impedance_command([command; 2]).unwrap();
+        assert_eq!(
+            io.inner_mut().get_target_position().unwrap(),
+            [0.5, -0.4, 1.0]
+        );
+        assert_eq!(io.get_current_position().unwrap(), [0.5, 0.5]);
+
+        // The joint feels the commanded impedance, summed over its motors
+        let (mut kp, mut kd, mut torque) = (0.0, 0.0, 0.0);
+        for motor in &io.motors()[0..2] {
+            let motor_command = io.motor_command(motor, command);
+            kp += motor_command.kp * motor.ratio.powi(2);
+            kd += motor_command.kd * motor.ratio.powi(2);
+            torque += motor_command.torque * motor.ratio;
+        }
+        assert_eq!((kp, kd, torque), (command.kp, command.kd, command.torque));
+    }
+
+    #[test]
+    fn disagreement() {
+        let mut io = gripper().with_position_tolerance([Some(0.05), None]);
+        io.inner_mut().set_current_position([0.5, -0.4, 0.5]);
+        assert_eq!(io.get_current_position().unwrap(), [0.5, 0.25]);
+
+        // Readings are averaged within the tolerance
+        io.inner_mut().set_current_position([0.52, -0.4, 0.5]);
+        let position = io.get_current_position().unwrap();
+        assert!((position[0] - 0.51).abs() < 1e-12);
+
+        io.inner_mut().set_current_position([0.6, -0.4, 0.5]);
+        assert!(io.get_current_position().is_err());
+        let spread = io.get_position_spread().unwrap();
+        assert!((spread[0] - 0.1).abs() < 1e-12);
+        assert_eq!(spread[1], 0.0);
+    }
+
+    #[test]
+    fn invalid() {
+        let io = || FakeMotorsIO::<2>::default();
+        assert!(MimicIO::<_, 2, 2>::new(io(), [MimicMotor::new(0), MimicMotor::new(0)]).is_err());
+        assert!(MimicIO::<_, 1, 2>::new(io(), [MimicMotor::new(0), MimicMotor::new(1)]).is_err());
+        let zero = MimicMotor {
+            ratio: 0.0,
+            ..MimicMotor::new(0)
+        };
+        assert!(MimicIO::<_, 1, 2>::new(io(), [MimicMotor::new(0), zero]).is_err());
+    }
+}
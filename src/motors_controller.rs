@@ -1,4 +1,12 @@
-use crate::{Limit, RawMotorsIO, Result, PID};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CoupledLimits, EmergencyStop, EmergencyStopError, FeedbackFilter, Feedforward,
+    GainCapabilities, Gains, ImpedanceCommand, Limit, LookupTable, PidController, RawMotorsIO,
+    Result, SoftLimits, StopMode, Transmission, UnsupportedGainsError, Unwrapper, PID,
+};
 
 pub trait MotorsController<const N: usize> {
     fn io(&mut self) -> &mut dyn RawMotorsIO<N>;
@@ -6,69 +14,234 @@ pub trait MotorsController<const N: usize> {
     /// Get the offsets of the motors (in radians)
     fn offsets(&self) -> [Option<f64>; N];
     /// Get the reduction of the motors
+    ///
+    /// Motor positions are the joint ones multiplied by the reduction, and
+    /// current velocities and torques are divided by it. Target velocities and
+    /// torques are sent as is, only their sign follows [inverted](Self::inverted).
+    /// Use a [transmission](Self::transmission) (e.g. [Transmission::from_scalar])
+    /// to convert them with the reduction as well.
     fn reduction(&self) -> [Option<f64>; N];
+    /// Get which joints turn in the opposite direction of their motor
+    ///
+    /// Inverted joints use the opposite of their reduction (1 if unset). Joints
+    /// with a lookup table or a coupled transmission ignore it.
+    fn inverted(&self) -> [bool; N] {
+        [false; N]
+    }
+    /// Get whether the offsets are expressed in joint or in motor space
+    fn offset_space(&self) -> OffsetSpace {
+        OffsetSpace::Joint
+    }
     /// Get the limits of the motors
     fn limits(&self) -> [Option<Limit>; N];
+    /// Get the constraints coupling the joints (None if the joints are only limited independently)
+    fn coupled_limits(&self) -> Option<&CoupledLimits<N>> {
+        None
+    }
+    /// Get the coupled transmission between the joints and the motors
+    ///
+    /// When set, it replaces the offsets and reductions to convert positions,
+    /// velocities and torques, targets included, so that the power is the same
+    /// on both sides. Joint limits are still enforced on the joint side, while
+    /// velocity and torque limits are motor limits, sent as is.
+    fn transmission(&self) -> Option<&Transmission<N>> {
+        None
+    }
+    /// Get the nonlinear transmission of each joint (None if the joints only use offsets and reductions)
+    ///
+    /// Joints with a table ignore their offset and reduction, and use the local
+    /// slope of the table as their reduction for velocities and torques. Tables
+    /// are not used with a coupled [transmission](Self::transmission).
+    fn lookup_tables(&self) -> Option<&[Option<LookupTable>; N]> {
+        None
+    }
+    /// Set the offsets of the motors (in radians), e.g. after a calibration
+    fn set_offsets(&mut self, _offsets: [Option<f64>; N]) -> Result<()> {
+        Err(Box::new(MissingRegisterErrror("offsets".to_string())))
+    }
+
+    /// Get the multi-turn unwrapping state (None if the motors report continuous positions)
+    fn unwrapper(&mut self) -> Option<&mut Unwrapper<N>> {
+        None
+    }
+
+    /// Get the soft limits of the joints (None if the controller only uses hard limits)
+    fn soft_limits(&mut self) -> Option<&mut SoftLimits<N>> {
+        None
+    }
+    /// Get the penetration of each joint into its soft limit zone, from 0 (outside) to 1 (at the hard limit)
+    fn get_soft_limit_penetration(&mut self) -> Result<[f64; N]> {
+        if self.soft_limits().is_none() {
+            return Ok([0.0; N]);
+        }
+        let position = self.get_current_position()?;
+        let limits = self.limits();
+        Ok(self.soft_limits().map_or([0.0; N], |soft_limits| {
+            soft_limits.penetration(&limits, position)
+        }))
+    }
+
+    /// Get the velocity estimation and torque filtering state (None to use the raw feedback)
+    fn feedback_filter(&mut self) -> Option<&mut FeedbackFilter<N>> {
+        None
+    }
+
+    /// Get the software PID controllers of the joints (None if the controller has none)
+    fn pid_controllers(&mut self) -> Option<&mut [Option<PidController>; N]> {
+        None
+    }
+
+    /// Get the friction and gravity compensation (None if torques are sent as is)
+    fn feedforward(&self) -> Option<&Feedforward<N>> {
+        None
+    }
+
+    /// Get the emergency stop latch (None if the controller does not support latching)
+    fn emergency_stop_latch(&self) -> Option<&EmergencyStop> {
+        None
+    }
+    /// Get how each motor is stopped on emergency stop
+    fn stop_modes(&self) -> [StopMode; N] {
+        [StopMode::TorqueOff; N]
+    }
+
+    /// Stop the motors right away and latch the emergency stop
+    ///
+    /// Motion commands are then refused until [reset_emergency_stop](Self::reset_emergency_stop)
+    /// is called. From another thread, use a clone of the
+    /// [latch](Self::emergency_stop_latch) instead.
+    fn emergency_stop(&mut self) -> Result<()> {
+        match self.emergency_stop_latch().cloned() {
+            Some(latch) => {
+                latch.trigger();
+                latch.take_pending_stop();
+            }
+            None => {
+                log::warn!(target: "controller::emergency_stop", "no emergency stop latch, commands will not be refused");
+            }
+        }
+        apply_stop(self)
+    }
+    /// Release the emergency stop latch (the torque is not re-enabled)
+    ///
+    /// A stop triggered from another thread and not applied yet is applied first.
+    fn reset_emergency_stop(&mut self) -> Result<()> {
+        apply_pending_stop(self)?;
+        if let Some(latch) = self.emergency_stop_latch() {
+            log::info!(target: "controller::reset_emergency_stop", "emergency stop reset");
+            latch.reset()?;
+        }
+        Ok(())
+    }
+    /// Check if the emergency stop is latched
+    fn is_emergency_stopped(&self) -> bool {
+        self.emergency_stop_latch()
+            .is_some_and(|latch| latch.is_triggered())
+    }
 
     /// Check if the torque is ON or OFF
     fn is_torque_on(&mut self) -> Result<[bool; N]> {
+        apply_pending_stop(self)?;
         self.io().is_torque_on()
     }
     /// Enable the torque
     fn set_torque(&mut self, on: [bool; N]) -> Result<()> {
+        if on.iter().any(|&on| on) {
+            check_emergency_stop(self)?;
+        }
         self.io().set_torque(on)
     }
+    /// Enable the torque of all motors without jumping toward a stale target
+    ///
+    /// The current positions are written as targets for the motors that are
+    /// still off, then the torque is enabled. With a ramp, the torque limit starts
+    /// from zero and is progressively raised back to its previous value (see
+    /// [TorqueRamp::limit] for the infinite limits).
+    fn safe_enable_torque(&mut self, ramp: Option<TorqueRamp>) -> Result<()> {
+        check_emergency_stop(self)?;
+
+        let torque_on = self.io().is_torque_on()?;
+        let current_position = self.io().get_current_position()?;
+        let mut target_position = self.io().get_target_position()?;
+
+        for i in 0..N {
+            if !torque_on[i] {
+                target_position[i] = current_position[i];
+            }
+        }
+        log::debug!(target: "controller::safe_enable_torque", "raw target_position: {:?}", target_position);
+        self.io().set_target_position(target_position)?;
+
+        let ramp = match ramp {
+            Some(ramp) => ramp,
+            None => return self.io().set_torque([true; N]),
+        };
+
+        let torque_limit = self.io().get_torque_limit()?;
+        self.io().set_torque_limit(ramp.limit(torque_limit, 0.0))?;
+        self.io().set_torque([true; N])?;
+
+        let steps = ramp.steps.max(1);
+        for step in 1..=steps {
+            std::thread::sleep(ramp.duration / steps as u32);
+
+            let limit = ramp.limit(torque_limit, step as f64 / steps as f64);
+            log::debug!(target: "controller::safe_enable_torque", "raw torque_limit: {:?}", limit);
+            self.io().set_torque_limit(limit)?;
+        }
+        Ok(())
+    }
 
     /// Get the current position of the motors (in radians)
     fn get_current_position(&mut self) -> Result<[f64; N]> {
+        apply_pending_stop(self)?;
         let mut position = self.io().get_current_position()?;
         log::debug!(target: "controller::get_current_position", "raw current_position: {:?}", position);
 
-        let reductions = self.reduction();
-        let offsets = self.offsets();
+        if let Some(unwrapper) = self.unwrapper() {
+            position = unwrapper.unwrap(position);
+        }
 
-        for i in 0..N {
-            if let Some(reductions) = reductions[i] {
-                position[i] /= reductions;
-            }
-            if let Some(offsets) = offsets[i] {
-                position[i] -= offsets;
-            }
+        position = joint_position(self, position);
+        log::debug!(target: "controller::get_current_position", "after offset/reduction current_position: {:?} (reductions {:?} offsets {:?})", position, self.reduction(), self.offsets());
+
+        if let Some(filter) = self.feedback_filter() {
+            filter.update_position(position, Instant::now());
         }
-        log::debug!(target: "controller::get_current_position", "after offset/reduction current_position: {:?} (reductions {:?} offsets {:?})", position,reductions,offsets);
 
         Ok(position)
     }
     /// Get the current velocity of the motors (in radians per second)
     fn get_current_velocity(&mut self) -> Result<[f64; N]> {
-        let mut velocity = self.io().get_current_velocity()?;
-        log::debug!(target: "controller::get_current_velocity", "raw current_velocity: {:?}", velocity);
-
-        let reductions = self.reduction();
-
-        for i in 0..N {
-            if let Some(reductions) = reductions[i] {
-                velocity[i] /= reductions;
-            }
-        }
-        log::debug!(target: "controller::get_current_velocity", "after reduction current_velocity: {:?}", velocity);
-
-        Ok(velocity)
+        apply_pending_stop(self)?;
+        // A single position read, for the lookup tables and the velocity estimation
+        let position = match ratios_use_position(self) || self.feedback_filter().is_some() {
+            true => self.get_current_position()?,
+            false => [f64::NAN; N],
+        };
+        current_velocity(self, &position)
     }
     /// Get the current torque of the motors (in Nm)
     fn get_current_torque(&mut self) -> Result<[f64; N]> {
+        apply_pending_stop(self)?;
         let mut torque = self.io().get_current_torque()?;
         log::debug!(target: "controller::get_current_torque", "raw current_torque: {:?}", torque);
 
-        let reductions = self.reduction();
-
-        for i in 0..N {
-            if let Some(reductions) = reductions[i] {
-                torque[i] /= reductions;
+        if let Some(transmission) = self.transmission() {
+            torque = transmission.torque_to_joint(torque);
+        } else {
+            let position = peek_position(self)?;
+            for (torque, ratio) in torque.iter_mut().zip(motor_ratios(self, &position)) {
+                *torque /= ratio;
             }
         }
         log::debug!(target: "controller::get_current_torque", "after reduction current_torque: {:?}", torque);
 
+        if let Some(filter) = self.feedback_filter() {
+            torque = filter.filter_torque(torque, Instant::now());
+            log::debug!(target: "controller::get_current_torque", "filtered current_torque: {:?}", torque);
+        }
+
         Ok(torque)
     }
     /// Get the current target position of the motors (in radians)
@@ -76,81 +249,263 @@ pub trait MotorsController<const N: usize> {
         let mut position = self.io().get_target_position()?;
         log::debug!(target: "controller::get_target_position", "raw target_position: {:?}", position);
 
-        let reductions = self.reduction();
-        let offsets = self.offsets();
-
-        for i in 0..N {
-            if let Some(reductions) = reductions[i] {
-                position[i] /= reductions;
-            }
-            if let Some(offsets) = offsets[i] {
-                position[i] -= offsets;
-            }
+        if let Some(unwrapper) = self.unwrapper() {
+            position = unwrapper.unwrap_near(position);
         }
+
+        position = joint_position(self, position);
         log::debug!(target: "controller::get_target_position", "after offset/reduction target_position: {:?}", position);
 
         Ok(position)
     }
     /// Set the current target position of the motors (in radians)
     fn set_target_position(&mut self, position: [f64; N]) -> Result<()> {
+        self.set_target_position_partial(position.map(Some))
+    }
+    /// Set the target position of some joints only (in radians), the other joints keep their current target
+    ///
+    /// Joints coupled by a [transmission](Self::transmission) or by
+    /// [coupled limits](Self::coupled_limits) must all be written together.
+    fn set_target_position_partial(&mut self, position: [Option<f64>; N]) -> Result<()> {
+        check_emergency_stop(self)?;
         log::debug!(target: "controller::set_target_position", "real target_position: {:?}", position);
 
-        let mut limited_position = position;
-        for i in 0..N {
-            if let Some(limits) = self.limits()[i] {
-                limited_position[i] = limits.clamp(position[i]);
-            }
-        }
-
-        let reductions = self.reduction();
-        let offsets = self.offsets();
+        let coupled = self.transmission().is_some() || self.coupled_limits().is_some();
+        let position_values = written(position, coupled)?;
+        let mut limited_position = limit_position(self, position_values)?;
+        limited_position = motor_position(self, limited_position);
 
-        for i in 0..N {
-            if let Some(offsets) = offsets[i] {
-                limited_position[i] += offsets;
-            }
-            if let Some(reductions) = reductions[i] {
-                limited_position[i] *= reductions;
-            }
+        if let Some(unwrapper) = self.unwrapper() {
+            limited_position = unwrapper.wrap(limited_position);
         }
 
+        let limited_position = mask(limited_position, position);
         log::debug!(target: "controller::set_target_position", "raw target_position: {:?}", limited_position);
 
-        self.io().set_target_position(limited_position)
+        self.io().set_target_position_partial(limited_position)
     }
 
     /// Set the current target torque of the motors (in Nm)
+    ///
+    /// The friction and gravity compensation is added when the controller has one.
     fn set_target_torque(&mut self, torque: [f64; N]) -> Result<()> {
+        self.set_target_torque_partial(torque.map(Some))
+    }
+    /// Set the target torque of some joints only (in Nm), the other joints keep their current target
+    ///
+    /// Joints coupled by a [transmission](Self::transmission) must all be written together.
+    fn set_target_torque_partial(&mut self, torque: [Option<f64>; N]) -> Result<()> {
+        check_emergency_stop(self)?;
         log::debug!(target: "controller::set_target_torque", "real target_torque: {:?}", torque);
 
-        self.io().set_target_torque(torque)
+        let coupled = self.transmission().is_some();
+        let mut torque_values = written(torque, coupled)?;
+        let position = match self.feedforward().is_some() {
+            true => self.get_current_position()?,
+            false => [f64::NAN; N],
+        };
+        if let Some(feedforward) = feedforward_torque(self, &position)? {
+            for (torque, feedforward) in torque_values.iter_mut().zip(feedforward) {
+                *torque += feedforward;
+            }
+            log::debug!(target: "controller::set_target_torque", "compensated target_torque: {:?}", torque_values);
+        }
+        if let Some(transmission) = self.transmission() {
+            torque_values = transmission.torque_to_motor(torque_values);
+        } else {
+            for (torque, direction) in torque_values.iter_mut().zip(directions(self)) {
+                *torque *= direction;
+            }
+        }
+
+        let torque = mask(torque_values, torque);
+        log::debug!(target: "controller::set_target_torque", "raw target_torque: {:?}", torque);
+        self.io().set_target_torque_partial(torque)
     }
 
     /// Set the current target velocity of the motors (in rad/s)
     fn set_target_velocity(&mut self, velocity: [f64; N]) -> Result<()> {
+        self.set_target_velocity_partial(velocity.map(Some))
+    }
+    /// Set the target velocity of some joints only (in rad/s), the other joints keep their current target
+    ///
+    /// Joints coupled by a [transmission](Self::transmission) must all be written together.
+    fn set_target_velocity_partial(&mut self, velocity: [Option<f64>; N]) -> Result<()> {
+        check_emergency_stop(self)?;
         log::debug!(target: "controller::set_target_velocity", "real target_velocity: {:?}", velocity);
 
-        self.io().set_target_velocity(velocity)
+        let coupled = self.transmission().is_some();
+        let mut velocity_values = written(velocity, coupled)?;
+        if self.soft_limits().is_some() {
+            let position = self.get_current_position()?;
+            let limits = self.limits();
+            if let Some(soft_limits) = self.soft_limits() {
+                velocity_values = soft_limits.limit_velocity(&limits, position, velocity_values);
+            }
+            log::debug!(target: "controller::set_target_velocity", "soft limited target_velocity: {:?}", velocity_values);
+        }
+        if let Some(transmission) = self.transmission() {
+            velocity_values = transmission.velocity_to_motor(velocity_values);
+        } else {
+            for (velocity, direction) in velocity_values.iter_mut().zip(directions(self)) {
+                *velocity *= direction;
+            }
+        }
+
+        let velocity = mask(velocity_values, velocity);
+        log::debug!(target: "controller::set_target_velocity", "raw target_velocity: {:?}", velocity);
+        self.io().set_target_velocity_partial(velocity)
+    }
+
+    /// Set the hybrid impedance commands of the joints (see [ImpedanceCommand])
+    ///
+    /// Target positions are limited as by [set_target_position](Self::set_target_position)
+    /// (hard, coupled and soft limits), the friction and gravity compensation is
+    /// added to the torques, and every field is converted to the motor side with
+    /// the same torque convention as the target torques (see [ImpedanceCommand::to_motor]). When the motors do not
+    /// support impedance commands, or when a coupled transmission couples their
+    /// impedances, the torques are computed from the current feedback and sent
+    /// as target torques instead, so the command must then be sent at each
+    /// control cycle.
+    fn set_impedance_command(&mut self, command: [ImpedanceCommand; N]) -> Result<()> {
+        check_emergency_stop(self)?;
+        log::debug!(target: "controller::set_impedance_command", "real impedance_command: {:?}", command);
+        for command in &command {
+            command.validate()?;
+        }
+
+        let mut command = command;
+        let position = limit_position(self, command.map(|command| command.position))?;
+        for (command, position) in command.iter_mut().zip(position) {
+            command.position = position;
+        }
+        // Coupled and nonlinear transmissions change the motor impedances, which motors cannot express
+        // Torques follow the target torque convention, divided by the reduction only with a transmission
+        let scalar = match self.transmission() {
+            Some(transmission) => transmission.scalar().map(|scalar| {
+                let reductions = scalar.map(|(_, reduction)| Some(reduction));
+                (
+                    scalar.map(|(offset, _)| Some(offset)),
+                    reductions,
+                    reductions,
+                )
+            }),
+            None if self.lookup_tables().is_some() => None,
+            None => Some((
+                joint_offsets(self),
+                signed_reductions(self),
+                directions(self).map(Some),
+            )),
+        };
+        let (offsets, reductions, torque_ratios) = match scalar {
+            Some(scalar) if self.io().supports_impedance() => scalar,
+            _ => {
+                let position = self.get_current_position()?;
+                let velocity = current_velocity(self, &position)?;
+                let mut torque = self.feedforward().map_or([0.0; N], |feedforward| {
+                    feedforward.torque(position, velocity)
+                });
+                for i in 0..N {
+                    torque[i] += command[i].torque(position[i], velocity[i]);
+                }
+                torque = match self.transmission() {
+                    Some(transmission) => transmission.torque_to_motor(torque),
+                    None => {
+                        for (torque, direction) in torque.iter_mut().zip(directions(self)) {
+                            *torque *= direction;
+                        }
+                        torque
+                    }
+                };
+                log::debug!(target: "controller::set_impedance_command", "raw target_torque (software impedance): {:?}", torque);
+                return self.io().set_target_torque(torque);
+            }
+        };
+
+        let position = match self.feedforward().is_some() {
+            true => self.get_current_position()?,
+            false => [f64::NAN; N],
+        };
+        if let Some(feedforward) = feedforward_torque(self, &position)? {
+            for (command, feedforward) in command.iter_mut().zip(feedforward) {
+                command.torque += feedforward;
+            }
+        }
+        let mut raw_command = command;
+        for i in 0..N {
+            raw_command[i] = command[i].to_motor(offsets[i], reductions[i], torque_ratios[i]);
+        }
+        if let Some(unwrapper) = self.unwrapper() {
+            let position = unwrapper.wrap(raw_command.map(|c| c.position));
+            for (command, position) in raw_command.iter_mut().zip(position) {
+                command.position = position;
+            }
+        }
+        log::debug!(target: "controller::set_impedance_command", "raw impedance_command: {:?}", raw_command);
+
+        self.io().set_impedance_command(raw_command)
     }
 
     /// Set control mode
     fn set_control_mode(&mut self, mode: [u8; N]) -> Result<()> {
+        check_emergency_stop(self)?;
         log::debug!(target: "controller::set_control_mode", "real control_mode: {:?}", mode);
 
         self.io().set_control_mode(mode)
     }
 
+    /// Track target positions (in radians) with the software PID controllers, `dt` seconds after the previous update
+    ///
+    /// The torques computed for the joints with a controller are sent as target
+    /// torques (in Nm) and returned, the other joints are not written and keep
+    /// their target torque (None).
+    fn update_pid_controllers(&mut self, position: [f64; N], dt: f64) -> Result<[Option<f64>; N]> {
+        if self.pid_controllers().is_none() {
+            return Err(Box::new(MissingRegisterErrror(
+                "pid_controllers".to_string(),
+            )));
+        }
+        let current_position = self.get_current_position()?;
+        let mut torque = [None; N];
+
+        if let Some(controllers) = self.pid_controllers() {
+            for (i, controller) in controllers.iter_mut().enumerate() {
+                if let Some(controller) = controller {
+                    torque[i] = Some(controller.update(position[i], current_position[i], dt));
+                }
+            }
+        }
+        log::debug!(target: "controller::update_pid_controllers", "pid target_torque: {:?}", torque);
+
+        self.set_target_torque_partial(torque)?;
+        Ok(torque)
+    }
+
     /// Get the current target torque of the motors (in Nm)
     fn get_target_torque(&mut self) -> Result<[f64; N]> {
-        let torque = self.io().get_target_torque()?;
+        let mut torque = self.io().get_target_torque()?;
         log::debug!(target: "controller::get_target_torque", "raw target_torque: {:?}", torque);
+        if let Some(transmission) = self.transmission() {
+            torque = transmission.torque_to_joint(torque);
+        } else {
+            for (torque, direction) in torque.iter_mut().zip(directions(self)) {
+                *torque *= direction;
+            }
+        }
         Ok(torque)
     }
 
     /// Get the current target velocity of the motors (in rad/s)
     fn get_target_velocity(&mut self) -> Result<[f64; N]> {
-        let velocity = self.io().get_target_velocity()?;
+        let mut velocity = self.io().get_target_velocity()?;
         log::debug!(target: "controller::get_target_velocity", "raw target_velocity: {:?}", velocity);
+        if let Some(transmission) = self.transmission() {
+            velocity = transmission.velocity_to_joint(velocity);
+        } else {
+            for (velocity, direction) in velocity.iter_mut().zip(directions(self)) {
+                *velocity *= direction;
+            }
+        }
         Ok(velocity)
     }
 
@@ -163,42 +518,28 @@ pub trait MotorsController<const N: usize> {
 
     /// Set the current target position and returns the motor feeback (position, velocity, torque)
     fn set_target_position_fb(&mut self, position: [f64; N]) -> Result<[f64; N]> {
+        check_emergency_stop(self)?;
         log::debug!(target: "controller::set_target_position", "real target_position: {:?}", position);
 
-        let mut limited_position = position;
-        for i in 0..N {
-            if let Some(limits) = self.limits()[i] {
-                limited_position[i] = limits.clamp(position[i]);
-            }
-        }
-
-        let reductions = self.reduction();
-        let offsets = self.offsets();
+        let mut limited_position = limit_position(self, position)?;
+        limited_position = motor_position(self, limited_position);
 
-        for i in 0..N {
-            if let Some(offsets) = offsets[i] {
-                limited_position[i] += offsets;
-            }
-            if let Some(reductions) = reductions[i] {
-                limited_position[i] *= reductions;
-            }
+        if let Some(unwrapper) = self.unwrapper() {
+            limited_position = unwrapper.wrap(limited_position);
         }
 
         log::debug!(target: "controller::set_target_position", "raw target_position: {:?}", limited_position);
 
         let mut fb = self.io().set_target_position_fb(limited_position)?;
+        if let Some(unwrapper) = self.unwrapper() {
+            fb = unwrapper.unwrap(fb);
+        }
         // let ret:[f64;N*3]=fb?;
         // let ret=[0.0;N*3];
 
-        for i in 0..N {
-            if let Some(reductions) = reductions[i] {
-                fb[i] /= reductions; //position
-                                     // fb[i + N] /= reductions; //velocity
-                                     // fb[i+N*2] /= reductions; //torque
-            }
-            if let Some(offsets) = offsets[i] {
-                fb[i] -= offsets;
-            }
+        fb = joint_position(self, fb);
+        if let Some(filter) = self.feedback_filter() {
+            filter.update_position(fb, Instant::now());
         }
 
         Ok(fb)
@@ -273,9 +614,54 @@ pub trait MotorsController<const N: usize> {
         self.io().get_pid_gains()
     }
     /// Set the current PID gains of the motors
+    ///
+    /// The gains are validated first, NaN or negative gains are refused.
     fn set_pid_gains(&mut self, pid: [PID; N]) -> Result<()> {
+        for pid in &pid {
+            pid.validate()?;
+        }
         self.io().set_pid_gains(pid)
     }
+    /// Set the PID gains of some motors only, the other motors keep their current gains
+    ///
+    /// Only the given gains are validated.
+    fn set_pid_gains_partial(&mut self, pid: [Option<PID>; N]) -> Result<()> {
+        for pid in pid.iter().flatten() {
+            pid.validate()?;
+        }
+        let mut gains = self.io().get_pid_gains()?;
+        for (gains, pid) in gains.iter_mut().zip(pid) {
+            if let Some(pid) = pid {
+                *gains = pid;
+            }
+        }
+        self.io().set_pid_gains(gains)
+    }
+    /// Get the gain fields honored by the motors
+    fn gain_capabilities(&mut self) -> GainCapabilities {
+        self.io().gain_capabilities()
+    }
+    /// Get the current extended gains of the motors
+    fn get_gains(&mut self) -> Result<[Gains; N]> {
+        self.io().get_gains()
+    }
+    /// Set the extended gains of the motors (the missing fields are not changed)
+    ///
+    /// The gains are validated first, NaN or negative gains are refused, as well
+    /// as the fields not honored by the motors.
+    fn set_gains(&mut self, gains: [Gains; N]) -> Result<()> {
+        let capabilities = self.gain_capabilities();
+        let mut unsupported = vec![];
+        for gains in &gains {
+            gains.validate()?;
+            unsupported.extend(gains.unsupported(&capabilities));
+        }
+        if !unsupported.is_empty() {
+            return Err(Box::new(UnsupportedGainsError(unsupported)));
+        }
+        log::debug!(target: "controller::set_gains", "gains: {:?}", gains);
+        self.io().set_gains(gains)
+    }
 
     /// Get the current axis sensors of the articulation
     fn get_axis_sensors(&mut self) -> Result<[f64; N]> {
@@ -292,6 +678,531 @@ pub trait MotorsController<const N: usize> {
     }
 }
 
+/// Implement the [MotorsController] methods of a wrapper by forwarding them to its `$inner` controller
+///
+/// Every method is forwarded, so the overrides of the wrapped controller are
+/// used, except the motion commands (torque enable, control mode and targets)
+/// that the wrapper must forward itself with its own checks.
+macro_rules! delegate_motors_controller {
+    ($inner:ident, $n:ident) => {
+        fn io(&mut self) -> &mut dyn $crate::RawMotorsIO<$n> {
+            self.$inner.io()
+        }
+        fn offsets(&self) -> [Option<f64>; $n] {
+            self.$inner.offsets()
+        }
+        fn reduction(&self) -> [Option<f64>; $n] {
+            self.$inner.reduction()
+        }
+        fn inverted(&self) -> [bool; $n] {
+            self.$inner.inverted()
+        }
+        fn offset_space(&self) -> $crate::OffsetSpace {
+            self.$inner.offset_space()
+        }
+        fn limits(&self) -> [Option<$crate::Limit>; $n] {
+            self.$inner.limits()
+        }
+        fn coupled_limits(&self) -> Option<&$crate::CoupledLimits<$n>> {
+            self.$inner.coupled_limits()
+        }
+        fn transmission(&self) -> Option<&$crate::Transmission<$n>> {
+            self.$inner.transmission()
+        }
+        fn lookup_tables(&self) -> Option<&[Option<$crate::LookupTable>; $n]> {
+            self.$inner.lookup_tables()
+        }
+        fn set_offsets(&mut self, offsets: [Option<f64>; $n]) -> $crate::Result<()> {
+            self.$inner.set_offsets(offsets)
+        }
+        fn unwrapper(&mut self) -> Option<&mut $crate::Unwrapper<$n>> {
+            self.$inner.unwrapper()
+        }
+        fn soft_limits(&mut self) -> Option<&mut $crate::SoftLimits<$n>> {
+            self.$inner.soft_limits()
+        }
+        fn get_soft_limit_penetration(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_soft_limit_penetration()
+        }
+        fn feedback_filter(&mut self) -> Option<&mut $crate::FeedbackFilter<$n>> {
+            self.$inner.feedback_filter()
+        }
+        fn pid_controllers(&mut self) -> Option<&mut [Option<$crate::PidController>; $n]> {
+            self.$inner.pid_controllers()
+        }
+        fn feedforward(&self) -> Option<&$crate::Feedforward<$n>> {
+            self.$inner.feedforward()
+        }
+        fn emergency_stop_latch(&self) -> Option<&$crate::EmergencyStop> {
+            self.$inner.emergency_stop_latch()
+        }
+        fn stop_modes(&self) -> [$crate::StopMode; $n] {
+            self.$inner.stop_modes()
+        }
+        fn emergency_stop(&mut self) -> $crate::Result<()> {
+            self.$inner.emergency_stop()
+        }
+        fn reset_emergency_stop(&mut self) -> $crate::Result<()> {
+            self.$inner.reset_emergency_stop()
+        }
+        fn is_emergency_stopped(&self) -> bool {
+            self.$inner.is_emergency_stopped()
+        }
+        fn is_torque_on(&mut self) -> $crate::Result<[bool; $n]> {
+            self.$inner.is_torque_on()
+        }
+        fn get_current_position(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_current_position()
+        }
+        fn get_current_velocity(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_current_velocity()
+        }
+        fn get_current_torque(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_current_torque()
+        }
+        fn get_target_position(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_target_position()
+        }
+        fn get_target_torque(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_target_torque()
+        }
+        fn get_target_velocity(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_target_velocity()
+        }
+        fn get_control_mode(&mut self) -> $crate::Result<[u8; $n]> {
+            self.$inner.get_control_mode()
+        }
+        fn get_velocity_limit(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_velocity_limit()
+        }
+        fn set_velocity_limit(&mut self, velocity: [f64; $n]) -> $crate::Result<()> {
+            self.$inner.set_velocity_limit(velocity)
+        }
+        fn get_torque_limit(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_torque_limit()
+        }
+        fn set_torque_limit(&mut self, torque: [f64; $n]) -> $crate::Result<()> {
+            self.$inner.set_torque_limit(torque)
+        }
+        fn get_pid_gains(&mut self) -> $crate::Result<[$crate::PID; $n]> {
+            self.$inner.get_pid_gains()
+        }
+        fn set_pid_gains(&mut self, pid: [$crate::PID; $n]) -> $crate::Result<()> {
+            self.$inner.set_pid_gains(pid)
+        }
+        fn set_pid_gains_partial(&mut self, pid: [Option<$crate::PID>; $n]) -> $crate::Result<()> {
+            self.$inner.set_pid_gains_partial(pid)
+        }
+        fn gain_capabilities(&mut self) -> $crate::GainCapabilities {
+            self.$inner.gain_capabilities()
+        }
+        fn get_gains(&mut self) -> $crate::Result<[$crate::Gains; $n]> {
+            self.$inner.get_gains()
+        }
+        fn set_gains(&mut self, gains: [$crate::Gains; $n]) -> $crate::Result<()> {
+            self.$inner.set_gains(gains)
+        }
+        fn get_axis_sensors(&mut self) -> $crate::Result<[f64; $n]> {
+            self.$inner.get_axis_sensors()
+        }
+        fn get_board_state(&mut self) -> $crate::Result<u8> {
+            self.$inner.get_board_state()
+        }
+        fn set_board_state(&mut self, state: u8) -> $crate::Result<()> {
+            self.$inner.set_board_state(state)
+        }
+    };
+}
+pub(crate) use delegate_motors_controller;
+
+/// Refuse motion commands while the emergency stop is latched
+///
+/// The stop is applied first if it was triggered from another thread.
+fn check_emergency_stop<C, const N: usize>(controller: &mut C) -> Result<()>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    apply_pending_stop(controller)?;
+    if controller.is_emergency_stopped() {
+        return Err(Box::new(EmergencyStopError));
+    }
+    Ok(())
+}
+
+/// Apply the stop reaction if the emergency stop was triggered from another thread
+///
+/// Called by commands and feedback reads, so a control loop only reading
+/// the motors also reacts.
+fn apply_pending_stop<C, const N: usize>(controller: &mut C) -> Result<()>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let pending = controller
+        .emergency_stop_latch()
+        .is_some_and(|latch| latch.take_pending_stop());
+    match pending {
+        true => apply_stop(controller),
+        false => Ok(()),
+    }
+}
+
+/// Values of the written joints (zero for the others), refused when the joints are coupled and some are missing
+fn written<const N: usize>(values: [Option<f64>; N], coupled: bool) -> Result<[f64; N]> {
+    let missing: Vec<usize> = (0..N).filter(|&i| values[i].is_none()).collect();
+    if coupled && !missing.is_empty() {
+        return Err(Box::new(CoupledJointsError(missing)));
+    }
+    Ok(values.map(|value| value.unwrap_or(0.0)))
+}
+
+/// Keep only the values of the written joints
+fn mask<const N: usize>(values: [f64; N], written: [Option<f64>; N]) -> [Option<f64>; N] {
+    let mut masked = [None; N];
+    for (masked, (value, written)) in masked.iter_mut().zip(values.into_iter().zip(written)) {
+        if written.is_some() {
+            *masked = Some(value);
+        }
+    }
+    masked
+}
+
+/// Joint positions from raw motor positions, through the transmission or the offsets and reductions
+fn joint_position<C, const N: usize>(controller: &C, position: [f64; N]) -> [f64; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    if let Some(transmission) = controller.transmission() {
+        return transmission.position_to_joint(position);
+    }
+
+    let reductions = signed_reductions(controller);
+    let offsets = joint_offsets(controller);
+    let tables = controller.lookup_tables();
+    let mut position = position;
+
+    for i in 0..N {
+        if let Some(table) = tables.and_then(|tables| tables[i].as_ref()) {
+            position[i] = table.to_joint(position[i]);
+            continue;
+        }
+        if let Some(reductions) = reductions[i] {
+            position[i] /= reductions;
+        }
+        if let Some(offsets) = offsets[i] {
+            position[i] -= offsets;
+        }
+    }
+    position
+}
+
+/// Raw motor positions from joint positions, through the transmission or the offsets and reductions
+fn motor_position<C, const N: usize>(controller: &C, position: [f64; N]) -> [f64; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    if let Some(transmission) = controller.transmission() {
+        return transmission.position_to_motor(position);
+    }
+
+    let reductions = signed_reductions(controller);
+    let offsets = joint_offsets(controller);
+    let tables = controller.lookup_tables();
+    let mut position = position;
+
+    for i in 0..N {
+        if let Some(table) = tables.and_then(|tables| tables[i].as_ref()) {
+            position[i] = table.to_motor(position[i]);
+            continue;
+        }
+        if let Some(offsets) = offsets[i] {
+            position[i] += offsets;
+        }
+        if let Some(reductions) = reductions[i] {
+            position[i] *= reductions;
+        }
+    }
+    position
+}
+
+/// Reductions of the motors, negated (or -1 if unset) for the inverted joints
+pub(crate) fn signed_reductions<C, const N: usize>(controller: &C) -> [Option<f64>; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let mut reductions = controller.reduction();
+    for (reduction, inverted) in reductions.iter_mut().zip(controller.inverted()) {
+        if inverted {
+            *reduction = Some(-reduction.unwrap_or(1.0));
+        }
+    }
+    reductions
+}
+
+/// Sign of the target velocities and torques seen by the motors (-1 for the inverted joints)
+///
+/// Joints with a lookup table ignore their inversion.
+fn directions<C, const N: usize>(controller: &C) -> [f64; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let mut directions = controller.inverted().map(|inverted| match inverted {
+        true => -1.0,
+        false => 1.0,
+    });
+    if let Some(tables) = controller.lookup_tables() {
+        for (direction, table) in directions.iter_mut().zip(tables) {
+            if table.is_some() {
+                *direction = 1.0;
+            }
+        }
+    }
+    directions
+}
+
+/// Change of each offset for a joint motion of one radian
+///
+/// It is 1 for joint space offsets, and the signed reduction for motor space ones.
+pub(crate) fn offset_scales<C, const N: usize>(controller: &C) -> [f64; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    match controller.offset_space() {
+        OffsetSpace::Joint => [1.0; N],
+        OffsetSpace::Motor => signed_reductions(controller).map(|r| r.unwrap_or(1.0)),
+    }
+}
+
+/// Offsets of the motors converted to joint space (in radians)
+fn joint_offsets<C, const N: usize>(controller: &C) -> [Option<f64>; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let mut offsets = controller.offsets();
+    for (offset, scale) in offsets.iter_mut().zip(offset_scales(controller)) {
+        *offset = offset.map(|offset| offset / scale);
+    }
+    offsets
+}
+
+/// Whether converting velocities and torques needs the current positions (joints with a lookup table)
+fn ratios_use_position<C, const N: usize>(controller: &C) -> bool
+where
+    C: MotorsController<N> + ?Sized,
+{
+    controller.transmission().is_none() && controller.lookup_tables().is_some()
+}
+
+/// Current joint positions for the lookup table ratios, without updating the unwrapper and the filter
+///
+/// Nothing is read (NaN positions) when no joint has a lookup table.
+fn peek_position<C, const N: usize>(controller: &mut C) -> Result<[f64; N]>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    if !ratios_use_position(controller) {
+        return Ok([f64::NAN; N]);
+    }
+    let mut position = controller.io().get_current_position()?;
+    if let Some(unwrapper) = controller.unwrapper() {
+        position = unwrapper.unwrap_near(position);
+    }
+    Ok(joint_position(controller, position))
+}
+
+/// Local ratios (d motor / d joint) of the joints with a lookup table, at the given positions
+fn lookup_jacobians<C, const N: usize>(controller: &C, position: &[f64; N]) -> [Option<f64>; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let mut jacobians = [None; N];
+    if !ratios_use_position(controller) {
+        return jacobians;
+    }
+    if let Some(tables) = controller.lookup_tables() {
+        for i in 0..N {
+            jacobians[i] = tables[i].as_ref().map(|table| table.jacobian(position[i]));
+        }
+    }
+    jacobians
+}
+
+/// Local ratio (d motor / d joint) of each joint without a coupled transmission, at the given positions
+///
+/// It is the jacobian of the lookup table, or else the signed reduction. Current
+/// velocities and torques are divided by it (see [MotorsController::reduction]).
+fn motor_ratios<C, const N: usize>(controller: &C, position: &[f64; N]) -> [f64; N]
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let mut ratios = signed_reductions(controller).map(|reduction| reduction.unwrap_or(1.0));
+    for (ratio, jacobian) in ratios
+        .iter_mut()
+        .zip(lookup_jacobians(controller, position))
+    {
+        if let Some(jacobian) = jacobian {
+            *ratio = jacobian;
+        }
+    }
+    ratios
+}
+
+/// Current joint velocities, at positions already read (they are only used by the lookup tables)
+///
+/// The filter estimates the velocities from its last position update.
+fn current_velocity<C, const N: usize>(controller: &mut C, position: &[f64; N]) -> Result<[f64; N]>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let mut velocity = controller.io().get_current_velocity()?;
+    log::debug!(target: "controller::get_current_velocity", "raw current_velocity: {:?}", velocity);
+
+    if let Some(transmission) = controller.transmission() {
+        velocity = transmission.velocity_to_joint(velocity);
+    } else {
+        for (velocity, ratio) in velocity.iter_mut().zip(motor_ratios(controller, position)) {
+            *velocity /= ratio;
+        }
+    }
+    log::debug!(target: "controller::get_current_velocity", "after reduction current_velocity: {:?}", velocity);
+
+    if let Some(filter) = controller.feedback_filter() {
+        velocity = filter.velocity(velocity);
+        log::debug!(target: "controller::get_current_velocity", "estimated current_velocity: {:?}", velocity);
+    }
+    Ok(velocity)
+}
+
+/// Friction and gravity compensation at the current joint positions, already read (None without compensation)
+fn feedforward_torque<C, const N: usize>(
+    controller: &mut C,
+    position: &[f64; N],
+) -> Result<Option<[f64; N]>>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    if controller.feedforward().is_none() {
+        return Ok(None);
+    }
+
+    let velocity = current_velocity(controller, position)?;
+    Ok(controller
+        .feedforward()
+        .map(|feedforward| feedforward.torque(*position, velocity)))
+}
+
+/// Limit joint positions before sending them as targets: clamp them to the hard limits,
+/// project them into the coupled limits, then slow them down in the soft limit zones
+fn limit_position<C, const N: usize>(controller: &mut C, position: [f64; N]) -> Result<[f64; N]>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let limits = controller.limits();
+    let mut limited = position;
+    for (limited, limit) in limited.iter_mut().zip(limits) {
+        if let Some(limit) = limit {
+            *limited = limit.clamp(*limited);
+        }
+    }
+    if let Some(coupled_limits) = controller.coupled_limits() {
+        limited = coupled_limits.project(position, &limits);
+    }
+    apply_soft_limits(controller, limited)
+}
+
+/// Slow down position commands entering the soft limit zones, starting from the previous targets
+fn apply_soft_limits<C, const N: usize>(controller: &mut C, position: [f64; N]) -> Result<[f64; N]>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    if controller.soft_limits().is_none() {
+        return Ok(position);
+    }
+
+    let from = controller.get_target_position()?;
+    let limits = controller.limits();
+    let limited = match controller.soft_limits() {
+        Some(soft_limits) => soft_limits.limit_position(&limits, from, position),
+        None => position,
+    };
+    log::debug!(target: "controller::set_target_position", "soft limited target_position: {:?}", limited);
+    Ok(limited)
+}
+
+/// Apply the stop mode of each motor, directly on the raw io
+fn apply_stop<C, const N: usize>(controller: &mut C) -> Result<()>
+where
+    C: MotorsController<N> + ?Sized,
+{
+    let modes = controller.stop_modes();
+    log::warn!(target: "controller::emergency_stop", "emergency stop: {:?}", modes);
+
+    if modes.contains(&StopMode::TorqueOff) {
+        let mut on = controller.io().is_torque_on()?;
+        for (on, mode) in on.iter_mut().zip(modes) {
+            if mode == StopMode::TorqueOff {
+                *on = false;
+            }
+        }
+        controller.io().set_torque(on)?;
+    }
+    if modes.contains(&StopMode::Hold) {
+        let current_position = controller.io().get_current_position()?;
+        let mut target_position = controller.io().get_target_position()?;
+        for i in 0..N {
+            if modes[i] == StopMode::Hold {
+                target_position[i] = current_position[i];
+            }
+        }
+        controller.io().set_target_position(target_position)?;
+    }
+    if modes.contains(&StopMode::ZeroVelocity) {
+        let mut velocity = controller.io().get_target_velocity()?;
+        for (v, mode) in velocity.iter_mut().zip(modes) {
+            if mode == StopMode::ZeroVelocity {
+                *v = 0.0;
+            }
+        }
+        controller.io().set_target_velocity(velocity)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Torque limit ramp used when enabling the torque
+pub struct TorqueRamp {
+    /// Total duration of the ramp
+    pub duration: Duration,
+    /// Number of torque limit updates during the ramp
+    pub steps: usize,
+    /// Torque limit used instead of the infinite limits during the ramp (in Nm)
+    pub max_torque: f64,
+}
+
+impl TorqueRamp {
+    /// Torque limits at some ratio of the ramp, from 0 (no torque) to 1 (full limits)
+    ///
+    /// Infinite limits are ramped through [max_torque](Self::max_torque) and
+    /// only restored once the ratio reaches 1.
+    pub fn limit<const N: usize>(&self, limit: [f64; N], ratio: f64) -> [f64; N] {
+        limit.map(|limit| match limit.is_finite() {
+            _ if ratio >= 1.0 => limit,
+            true => limit * ratio,
+            false => self.max_torque * ratio,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Space in which the offsets of the motors are expressed
+pub enum OffsetSpace {
+    /// Joint positions of the motor zeros: `joint = motor / reduction - offset`
+    #[default]
+    Joint,
+    /// Motor positions of the joint zeros: `joint = (motor - offset) / reduction`
+    Motor,
+}
+
 #[derive(Debug)]
 pub struct MissingRegisterErrror(pub String);
 impl std::fmt::Display for MissingRegisterErrror {
@@ -301,3 +1212,17 @@ impl std::fmt::Display for MissingRegisterErrror {
     }
 }
 impl std::error::Error for MissingRegisterErrror {}
+
+#[derive(Debug)]
+/// Joints missing from a partial write, while the controller couples them to the written joints
+pub struct CoupledJointsError(pub Vec<usize>);
+impl std::fmt::Display for CoupledJointsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let joints = &self.0;
+        write!(
+            f,
+            "(joints {joints:?} not written, they are coupled to the written joints)"
+        )
+    }
+}
+impl std::error::Error for CoupledJointsError {}
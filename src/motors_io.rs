@@ -1,4 +1,7 @@
-use crate::{Result, PID};
+use crate::{
+    GainCapabilities, Gains, ImpedanceCommand, MissingRegisterErrror, Result,
+    UnsupportedGainsError, PID,
+};
 
 pub trait RawMotorsIO<const N: usize> {
     /// Check if the motors are ON or OFF
@@ -17,14 +20,29 @@ pub trait RawMotorsIO<const N: usize> {
     fn get_target_position(&mut self) -> Result<[f64; N]>;
     /// Set the current target position of the motors (in radians)
     fn set_target_position(&mut self, position: [f64; N]) -> Result<()>;
+    /// Set the target position of some motors only (in radians), the others keep their current target
+    fn set_target_position_partial(&mut self, position: [Option<f64>; N]) -> Result<()> {
+        let position = fill(position, || self.get_target_position())?;
+        self.set_target_position(position)
+    }
 
     /// Get the current target torque of the motors (in Nm)
     fn get_target_torque(&mut self) -> Result<[f64; N]>;
     /// Set the current target torque of the motors (in Nm)
     fn set_target_torque(&mut self, torque: [f64; N]) -> Result<()>;
+    /// Set the target torque of some motors only (in Nm), the others keep their current target
+    fn set_target_torque_partial(&mut self, torque: [Option<f64>; N]) -> Result<()> {
+        let torque = fill(torque, || self.get_target_torque())?;
+        self.set_target_torque(torque)
+    }
 
     /// Set the current target velocity of the motors (in rad/s)
     fn set_target_velocity(&mut self, velocity: [f64; N]) -> Result<()>;
+    /// Set the target velocity of some motors only (in rad/s), the others keep their current target
+    fn set_target_velocity_partial(&mut self, velocity: [Option<f64>; N]) -> Result<()> {
+        let velocity = fill(velocity, || self.get_target_velocity())?;
+        self.set_target_velocity(velocity)
+    }
 
     /// Get the current target velocity of the motors (in rad/s)
     fn get_target_velocity(&mut self) -> Result<[f64; N]>;
@@ -35,6 +53,17 @@ pub trait RawMotorsIO<const N: usize> {
     /// Get the control mode
     fn get_control_mode(&mut self) -> Result<[u8; N]>;
 
+    /// Check if the motors natively accept hybrid impedance commands
+    fn supports_impedance(&self) -> bool {
+        false
+    }
+    /// Set the hybrid impedance commands of the motors (see [ImpedanceCommand])
+    fn set_impedance_command(&mut self, _command: [ImpedanceCommand; N]) -> Result<()> {
+        Err(Box::new(MissingRegisterErrror(
+            "impedance_command".to_string(),
+        )))
+    }
+
     /// Set the current target position and returns the motor feeback (position, velocity, torque)
     fn set_target_position_fb(&mut self, position: [f64; N]) -> Result<[f64; N]>;
 
@@ -53,6 +82,36 @@ pub trait RawMotorsIO<const N: usize> {
     /// Set the current PID gains of the motors
     fn set_pid_gains(&mut self, pid: [PID; N]) -> Result<()>;
 
+    /// Get the gain fields honored by the motors
+    fn gain_capabilities(&self) -> GainCapabilities {
+        GainCapabilities::PID
+    }
+    /// Get the current extended gains of the motors
+    fn get_gains(&mut self) -> Result<[Gains; N]> {
+        Ok(self.get_pid_gains()?.map(Gains::from))
+    }
+    /// Set the extended gains of the motors (the missing fields are not changed)
+    ///
+    /// By default, the basic PID gains are completed with the current ones, and
+    /// refused when these are unknown (NaN).
+    fn set_gains(&mut self, gains: [Gains; N]) -> Result<()> {
+        let capabilities = self.gain_capabilities();
+        let unsupported: Vec<_> = gains
+            .iter()
+            .flat_map(|g| g.unsupported(&capabilities))
+            .collect();
+        if !unsupported.is_empty() {
+            return Err(Box::new(UnsupportedGainsError(unsupported)));
+        }
+
+        let mut pid = self.get_pid_gains()?;
+        for (pid, gains) in pid.iter_mut().zip(gains) {
+            *pid = gains.to_pid(*pid);
+            pid.validate()?;
+        }
+        self.set_pid_gains(pid)
+    }
+
     /// Get the current axis sensors
     fn get_axis_sensors(&mut self) -> Result<[f64; N]>;
 
@@ -62,3 +121,20 @@ pub trait RawMotorsIO<const N: usize> {
     /// Set the Board State byte
     fn set_board_state(&mut self, state: u8) -> Result<()>;
 }
+
+/// Complete a partial write with the raw values read back from the motors (only read when needed)
+fn fill<const N: usize>(
+    values: [Option<f64>; N],
+    read: impl FnOnce() -> Result<[f64; N]>,
+) -> Result<[f64; N]> {
+    let mut filled = match values.iter().all(Option::is_some) {
+        true => [0.0; N],
+        false => read()?,
+    };
+    for (filled, value) in filled.iter_mut().zip(values) {
+        if let Some(value) = value {
+            *filled = value;
+        }
+    }
+    Ok(filled)
+}
@@ -1,4 +1,10 @@
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+use std::f64::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Limit;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
 /// PID gains wrapper
 pub struct PID {
     /// Propotional gain
@@ -8,3 +14,268 @@ pub struct PID {
     /// Derivative gain
     pub d: f64,
 }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Integrator anti-windup strategy, used when the output saturates
+pub enum AntiWindup {
+    /// Keep integrating whatever the saturation
+    None,
+    /// Stop integrating while the error pushes further into the saturation
+    Clamping,
+    /// Bleed the integrator by the saturation excess, scaled by the tracking gain (in 1/s)
+    BackCalculation { gain: f64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Software PID controller
+///
+/// The derivative acts on the measurement (no kick on setpoint changes) and
+/// can be low-pass filtered. The integral term is stored as an output
+/// contribution, and is adjusted on gain changes so that the output does not
+/// jump (bumpless).
+pub struct PidController {
+    gains: PID,
+    output_limit: Limit,
+    anti_windup: AntiWindup,
+    derivative_cutoff: Option<f64>,
+
+    integral: f64,
+    derivative: f64,
+    last_error: Option<f64>,
+    last_measurement: Option<f64>,
+    initial_output: Option<f64>,
+}
+
+impl PidController {
+    /// Create a controller with unlimited output and clamping anti-windup
+    pub fn new(gains: PID) -> Self {
+        Self {
+            gains,
+            output_limit: Limit::continuous(),
+            anti_windup: AntiWindup::Clamping,
+            derivative_cutoff: None,
+
+            integral: 0.0,
+            derivative: 0.0,
+            last_error: None,
+            last_measurement: None,
+            initial_output: None,
+        }
+    }
+
+    /// Limit the output (e.g. to the torque limit)
+    pub fn with_output_limit(mut self, output_limit: Limit) -> Self {
+        self.output_limit = output_limit;
+        self
+    }
+
+    pub fn with_anti_windup(mut self, anti_windup: AntiWindup) -> Self {
+        self.anti_windup = anti_windup;
+        self
+    }
+
+    /// Low-pass filter the derivative term (cutoff frequency in Hz)
+    pub fn with_derivative_filter(mut self, cutoff: f64) -> Self {
+        self.derivative_cutoff = Some(cutoff);
+        self
+    }
+
+    /// Current gains
+    pub fn gains(&self) -> PID {
+        self.gains
+    }
+
+    /// Change the gains without jumping the output
+    ///
+    /// The integral term absorbs the change of the proportional term for the last error.
+    pub fn set_gains(&mut self, gains: PID) {
+        if let Some(error) = self.last_error {
+            self.integral += (self.gains.p - gains.p) * error;
+        }
+        self.gains = gains;
+    }
+
+    /// Output limit
+    pub fn output_limit(&self) -> Limit {
+        self.output_limit
+    }
+
+    /// Change the output limit (e.g. when the torque limit changes)
+    pub fn set_output_limit(&mut self, output_limit: Limit) {
+        self.output_limit = output_limit;
+    }
+
+    /// Integral term (as an output contribution)
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Clear the integral and derivative states
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative = 0.0;
+        self.last_error = None;
+        self.last_measurement = None;
+        self.initial_output = None;
+    }
+
+    /// Clear the states, the next update starting from `output` (bumpless transfer)
+    ///
+    /// The integral term is set so that the proportional and integral terms give `output`.
+    pub fn reset_to(&mut self, output: f64) {
+        self.reset();
+        self.initial_output = Some(output);
+    }
+
+    /// Compute the output for a setpoint and a measurement, `dt` seconds after the previous update
+    pub fn update(&mut self, setpoint: f64, measurement: f64, dt: f64) -> f64 {
+        let error = setpoint - measurement;
+
+        if let (Some(last), true) = (self.last_measurement, dt > 0.0) {
+            let derivative = -(measurement - last) / dt;
+            self.derivative = match self.derivative_cutoff {
+                Some(cutoff) => {
+                    let alpha = dt / (dt + 1.0 / (TAU * cutoff));
+                    self.derivative + alpha * (derivative - self.derivative)
+                }
+                None => derivative,
+            };
+        }
+        if let Some(output) = self.initial_output.take() {
+            self.integral = output - self.gains.p * error;
+        }
+        self.last_measurement = Some(measurement);
+        self.last_error = Some(error);
+
+        let increment = self.gains.i * error * dt.max(0.0);
+        self.integral += increment;
+
+        let unsaturated = self.gains.p * error + self.integral + self.gains.d * self.derivative;
+        let output = self.output_limit.clamp(unsaturated);
+
+        if output != unsaturated {
+            match self.anti_windup {
+                AntiWindup::None => {}
+                AntiWindup::Clamping => {
+                    if (unsaturated - output).signum() == increment.signum() {
+                        self.integral -= increment;
+                    }
+                }
+                AntiWindup::BackCalculation { gain } => {
+                    self.integral += gain * (output - unsaturated) * dt.max(0.0);
+                }
+            }
+        }
+        log::debug!(target: "pid::update", "error: {} integral: {} derivative: {} output: {}", error, self.integral, self.derivative, output);
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AntiWindup, PidController, PID};
+    use crate::Limit;
+
+    const DT: f64 = 0.01;
+
+    /// Simulate a unit inertia with a constant disturbance torque, returning the positions
+    fn simulate(
+        pid: &mut PidController,
+        setpoint: f64,
+        disturbance: f64,
+        steps: usize,
+    ) -> Vec<f64> {
+        let (mut position, mut velocity) = (0.0, 0.0);
+        let mut positions = vec![];
+        for _ in 0..steps {
+            let torque = pid.update(setpoint, position, DT);
+            velocity += (torque + disturbance) * DT;
+            position += velocity * DT;
+            positions.push(position);
+        }
+        positions
+    }
+
+    fn gains() -> PID {
+        PID {
+            p: 20.0,
+            i: 10.0,
+            d: 8.0,
+        }
+    }
+
+    #[test]
+    fn step_response() {
+        let mut pid = PidController::new(gains());
+        let positions = simulate(&mut pid, 1.0, -2.0, 2000);
+
+        // The integral term compensates the disturbance
+        assert!((positions.last().unwrap() - 1.0).abs() < 1e-3);
+        assert!((pid.integral() - 2.0).abs() < 1e-2);
+
+        pid.reset();
+        assert_eq!(pid.integral(), 0.0);
+    }
+
+    #[test]
+    fn derivative_on_measurement() {
+        let mut pid = PidController::new(PID {
+            p: 0.0,
+            i: 0.0,
+            d: 1.0,
+        });
+
+        // Setpoint changes do not kick the output
+        assert_eq!(pid.update(0.0, 0.0, DT), 0.0);
+        assert_eq!(pid.update(1.0, 0.0, DT), 0.0);
+        // The measurement moving toward the setpoint brakes
+        assert!((pid.update(1.0, 0.1, DT) + 10.0).abs() < 1e-9);
+
+        let mut filtered = PidController::new(pid.gains()).with_derivative_filter(1.0);
+        filtered.update(1.0, 0.0, DT);
+        let output = filtered.update(1.0, 0.1, DT);
+        assert!(output < 0.0 && output > -1.0);
+    }
+
+    #[test]
+    fn anti_windup() {
+        let limit = Limit::new(-1.0, 1.0);
+        let overshoot = |anti_windup| {
+            let mut pid = PidController::new(gains())
+                .with_output_limit(limit)
+                .with_anti_windup(anti_windup);
+            let positions = simulate(&mut pid, 1.0, 0.0, 3000);
+            assert!((positions.last().unwrap() - 1.0).abs() < 1e-2);
+            positions.into_iter().fold(f64::MIN, f64::max) - 1.0
+        };
+
+        let none = overshoot(AntiWindup::None);
+        assert!(overshoot(AntiWindup::Clamping) < none / 2.0);
+        assert!(overshoot(AntiWindup::BackCalculation { gain: 5.0 }) < none / 2.0);
+    }
+
+    #[test]
+    fn output_limit() {
+        let mut pid = PidController::new(gains()).with_output_limit(Limit::new(-1.0, 1.0));
+        assert_eq!(pid.update(10.0, 0.0, DT), 1.0);
+        assert_eq!(pid.update(-10.0, 0.0, DT), -1.0);
+    }
+
+    #[test]
+    fn bumpless() {
+        let gains = PID { d: 0.0, ..gains() };
+        let mut pid = PidController::new(gains);
+        pid.update(1.0, 0.0, DT);
+        let before = pid.update(1.0, 0.2, DT);
+
+        pid.set_gains(PID { p: 40.0, ..gains });
+        let after = pid.update(1.0, 0.2, DT);
+        // Only the integral increment changes the output
+        assert!((after - before - 10.0 * 0.8 * DT).abs() < 1e-9);
+
+        pid.reset_to(3.0);
+        let output = pid.update(1.0, 0.5, DT);
+        assert!((output - (3.0 + 10.0 * 0.5 * DT)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,83 @@
+//! Serde helpers for `[T; N]` with a generic `N` (serde only implements fixed sizes).
+//!
+//! Use with `#[serde(with = "crate::serde_array")]`, or the `vec` and `option`
+//! submodules for `Vec<[T; N]>` and `Option<[T; N]>`.
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub fn serialize<S, T, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize,
+{
+    serializer.collect_seq(array)
+}
+
+pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    to_array(Vec::<T>::deserialize(deserializer)?)
+}
+
+fn to_array<E: Error, T, const N: usize>(values: Vec<T>) -> Result<[T; N], E> {
+    let len = values.len();
+    values
+        .try_into()
+        .map_err(|_| E::invalid_length(len, &format!("an array of length {N}").as_str()))
+}
+
+pub mod vec {
+    use super::*;
+
+    pub fn serialize<S, T, const N: usize>(
+        arrays: &[[T; N]],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        serializer.collect_seq(arrays.iter().map(|a| a.as_slice()))
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<Vec<[T; N]>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        Vec::<Vec<T>>::deserialize(deserializer)?
+            .into_iter()
+            .map(to_array)
+            .collect()
+    }
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S, T, const N: usize>(
+        array: &Option<[T; N]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        array.as_ref().map(|a| a.as_slice()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(
+        deserializer: D,
+    ) -> Result<Option<[T; N]>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        Option::<Vec<T>>::deserialize(deserializer)?
+            .map(to_array)
+            .transpose()
+    }
+}
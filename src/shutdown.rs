@@ -0,0 +1,472 @@
+use std::panic::PanicHookInfo;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
+
+use crate::{EmergencyStop, MotorsController, Result, StreamState, TorqueRamp, TrajectoryStreamer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// How the motors are released once parked
+pub enum Release {
+    /// Turn the torque off right away
+    TorqueOff,
+    /// Ramp the torque limit down to zero, then turn the torque off
+    RampDown(TorqueRamp),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Shutdown sequence configuration
+pub struct ShutdownConfig<const N: usize> {
+    /// Pose to reach before releasing the motors (in radians), `None` to release in place
+    pub park_pose: Option<[f64; N]>,
+    /// Duration of the move to the park pose
+    pub park_duration: Duration,
+    /// Control period of the move to the park pose
+    pub period: Duration,
+    /// How the motors are released once parked
+    pub release: Release,
+    /// Maximum duration of the whole shutdown sequence
+    ///
+    /// The park move is aborted after half of it, so the motors can still be released.
+    pub timeout: Duration,
+}
+
+impl<const N: usize> Default for ShutdownConfig<N> {
+    fn default() -> Self {
+        Self {
+            park_pose: None,
+            park_duration: Duration::from_secs(2),
+            period: Duration::from_millis(10),
+            release: Release::TorqueOff,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ShutdownError(pub String);
+impl std::fmt::Display for ShutdownError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(shutdown failed: {reason})")
+    }
+}
+impl std::error::Error for ShutdownError {}
+
+/// Slowly move to the park pose then release the motors (blocking)
+///
+/// The park move is given up after half of the timeout or if it fails (e.g.
+/// emergency stop latched), the torque is turned off in any case.
+pub fn park_and_release<const N: usize>(
+    controller: &mut dyn MotorsController<N>,
+    config: &ShutdownConfig<N>,
+) -> Result<()> {
+    shutdown_sequence(controller, config, &AtomicBool::new(false))
+}
+
+/// Park then release the motors, skipping straight to the torque off once cancelled
+fn shutdown_sequence<const N: usize>(
+    controller: &mut dyn MotorsController<N>,
+    config: &ShutdownConfig<N>,
+    cancelled: &AtomicBool,
+) -> Result<()> {
+    let start = Instant::now();
+
+    if let Some(park_pose) = config.park_pose {
+        match park(controller, park_pose, config, cancelled) {
+            Ok(StreamState::Finished) => {}
+            Ok(_) => {
+                log::warn!(target: "shutdown::park", "park move timed out, releasing in place")
+            }
+            Err(e) => {
+                log::warn!(target: "shutdown::park", "park move failed {}, releasing in place", e)
+            }
+        }
+    }
+
+    if let Release::RampDown(ramp) = config.release {
+        let torque_limit = controller.get_torque_limit()?;
+        let steps = ramp.steps.max(1);
+
+        for step in 1..=steps {
+            if start.elapsed() > config.timeout || cancelled.load(Ordering::SeqCst) {
+                log::warn!(target: "shutdown::release", "torque limit ramp timed out");
+                break;
+            }
+            std::thread::sleep(ramp.duration / steps as u32);
+
+            let limit = ramp.limit(torque_limit, 1.0 - step as f64 / steps as f64);
+            controller.set_torque_limit(limit)?;
+        }
+    }
+
+    log::info!(target: "shutdown::release", "turning torque off");
+    controller.set_torque([false; N])
+}
+
+fn park<const N: usize>(
+    controller: &mut dyn MotorsController<N>,
+    park_pose: [f64; N],
+    config: &ShutdownConfig<N>,
+    cancelled: &AtomicBool,
+) -> Result<StreamState> {
+    let current = controller.get_current_position()?;
+    log::info!(target: "shutdown::park", "parking from {:?} to {:?}", current, park_pose);
+
+    let mut stream = TrajectoryStreamer::point_to_point(current, park_pose, config.park_duration)?;
+    let deadline = Instant::now() + config.timeout / 2;
+    let mut next = Instant::now();
+    loop {
+        match stream.update(controller, config.period.as_secs_f64())? {
+            StreamState::Running => {}
+            state => return Ok(state),
+        }
+        if Instant::now() > deadline || cancelled.load(Ordering::SeqCst) {
+            return Ok(stream.state());
+        }
+
+        next += config.period;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+type PanicHook = Arc<dyn Fn(&PanicHookInfo<'_>) + Send + Sync>;
+
+/// Shuts the motors down when dropped, or when a panic occurs once the panic hook is installed
+///
+/// The controller is shared behind a mutex so the panic hook can reach it.
+/// The shutdown runs on its own thread and is abandoned after the configured
+/// timeout, so it can never hang the process: the motors are then no longer
+/// moved and only the torque is turned off.
+pub struct ShutdownGuard<C, const N: usize>
+where
+    C: MotorsController<N> + Send + 'static,
+{
+    controller: Arc<Mutex<C>>,
+    config: ShutdownConfig<N>,
+    pending: Arc<AtomicBool>,
+    previous_hook: Mutex<Option<PanicHook>>,
+}
+
+impl<C, const N: usize> ShutdownGuard<C, N>
+where
+    C: MotorsController<N> + Send + 'static,
+{
+    pub fn new(controller: C, config: ShutdownConfig<N>) -> Self {
+        Self {
+            controller: Arc::new(Mutex::new(controller)),
+            config,
+            pending: Arc::new(AtomicBool::new(true)),
+            previous_hook: Mutex::new(None),
+        }
+    }
+
+    /// Shared handle on the guarded controller
+    pub fn controller(&self) -> Arc<Mutex<C>> {
+        self.controller.clone()
+    }
+
+    /// Lock the guarded controller
+    pub fn lock(&self) -> MutexGuard<'_, C> {
+        self.controller
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Also react when any thread panics, until the guard is disarmed or dropped
+    ///
+    /// The panicking thread may hold the controller lock, which is only
+    /// released once it unwinds. So the hook latches the emergency stop of the
+    /// controller right away, through a clone of its latch (running its
+    /// [stop action](crate::EmergencyStop::set_stop_action) if any), and starts the
+    /// shutdown sequence on another thread without waiting for it. The park
+    /// move is then refused, and the motors are released in place.
+    ///
+    /// The hook is process-wide: the previous hook is called after it, and
+    /// restored when the guard is done (guards with hooks must be dropped in
+    /// the reverse order of installation).
+    pub fn install_panic_hook(&self) {
+        let reaction = self.panic_reaction();
+        let previous: PanicHook = Arc::from(std::panic::take_hook());
+        let hook = previous.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            reaction();
+            hook(info);
+        }));
+
+        let mut slot = self
+            .previous_hook
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        slot.get_or_insert(previous);
+    }
+
+    /// Latch the emergency stop and start the shutdown sequence, without blocking
+    fn panic_reaction(&self) -> impl Fn() + Send + Sync + 'static {
+        let latch: Option<EmergencyStop> = self.lock().emergency_stop_latch().cloned();
+        let controller = self.controller.clone();
+        let config = self.config;
+        let pending = self.pending.clone();
+
+        move || {
+            if !pending.load(Ordering::SeqCst) {
+                return;
+            }
+            match &latch {
+                Some(latch) => latch.trigger(),
+                None => {
+                    log::warn!(target: "shutdown::panic_hook", "no emergency stop latch, waiting for the controller lock")
+                }
+            }
+
+            let (controller, pending) = (controller.clone(), pending.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = run_shutdown(controller, config, pending) {
+                    log::error!(target: "shutdown::panic_hook", "{}", e);
+                }
+            });
+        }
+    }
+
+    /// Put back the panic hook replaced by [install_panic_hook](Self::install_panic_hook)
+    fn restore_panic_hook(&self) {
+        let previous = self
+            .previous_hook
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        if let Some(previous) = previous {
+            std::panic::set_hook(Box::new(move |info| previous(info)));
+        }
+    }
+
+    /// Do not run the shutdown sequence on drop or panic
+    pub fn disarm(&self) {
+        self.pending.store(false, Ordering::SeqCst);
+        self.restore_panic_hook();
+    }
+
+    /// Run the shutdown sequence now (only once)
+    pub fn shutdown(&self) -> Result<()> {
+        run_shutdown(self.controller.clone(), self.config, self.pending.clone())
+    }
+}
+
+impl<C, const N: usize> Drop for ShutdownGuard<C, N>
+where
+    C: MotorsController<N> + Send + 'static,
+{
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            log::error!(target: "shutdown::drop", "{}", e);
+        }
+        self.restore_panic_hook();
+    }
+}
+
+fn run_shutdown<C, const N: usize>(
+    controller: Arc<Mutex<C>>,
+    config: ShutdownConfig<N>,
+    pending: Arc<AtomicBool>,
+) -> Result<()>
+where
+    C: MotorsController<N> + Send + 'static,
+{
+    if !pending.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let deadline = Instant::now() + config.timeout;
+    let (tx, rx) = mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let sequence_cancelled = cancelled.clone();
+
+    std::thread::spawn(move || {
+        let mut controller = loop {
+            match controller.try_lock() {
+                Ok(controller) => break controller,
+                Err(TryLockError::Poisoned(poisoned)) => break poisoned.into_inner(),
+                Err(TryLockError::WouldBlock)
+                    if Instant::now() > deadline || sequence_cancelled.load(Ordering::SeqCst) =>
+                {
+                    let _ = tx.send(Err("controller is locked".to_string()));
+                    return;
+                }
+                Err(TryLockError::WouldBlock) => std::thread::sleep(Duration::from_millis(1)),
+            }
+        };
+        // Another sequence may have completed, or the guard been disarmed, while waiting for the lock
+        if !pending.load(Ordering::SeqCst) {
+            let _ = tx.send(Ok(()));
+            return;
+        }
+
+        let res = shutdown_sequence(&mut *controller, &config, &sequence_cancelled)
+            .map_err(|e| e.to_string());
+        if res.is_ok() {
+            pending.store(false, Ordering::SeqCst);
+        }
+        let _ = tx.send(res);
+    });
+
+    match rx.recv_timeout(config.timeout) {
+        Ok(res) => res.map_err(|e| Box::new(ShutdownError(e)).into()),
+        Err(_) => {
+            // The late sequence must not keep moving the motors
+            cancelled.store(true, Ordering::SeqCst);
+            Err(Box::new(ShutdownError("timed out".to_string())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Release, ShutdownConfig, ShutdownGuard};
+    use crate::test_utils::enabled_motors;
+    use crate::{MotorsController, TorqueRamp};
+
+    fn config(park_pose: Option<[f64; 2]>, release: Release) -> ShutdownConfig<2> {
+        ShutdownConfig {
+            park_pose,
+            park_duration: Duration::from_millis(20),
+            period: Duration::from_millis(1),
+            release,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn park_on_drop() {
+        let guard = ShutdownGuard::new(
+            enabled_motors([1.0, -1.0]),
+            config(Some([0.0, 0.5]), Release::TorqueOff),
+        );
+        let controller = guard.controller();
+        drop(guard);
+
+        let mut motors = controller.lock().unwrap();
+        assert_eq!(motors.get_current_position().unwrap(), [0.0, 0.5]);
+        assert_eq!(motors.is_torque_on().unwrap(), [false; 2]);
+    }
+
+    #[test]
+    fn ramp_down() {
+        let mut motors = enabled_motors([1.0, -1.0]);
+        motors.set_torque_limit([2.0, f64::INFINITY]).unwrap();
+
+        let ramp = TorqueRamp {
+            duration: Duration::ZERO,
+            steps: 3,
+            max_torque: 1.0,
+        };
+        let guard = ShutdownGuard::new(motors, config(None, Release::RampDown(ramp)));
+        guard.shutdown().unwrap();
+
+        let mut motors = guard.lock();
+        assert_eq!(motors.get_current_position().unwrap(), [1.0, -1.0]);
+        assert_eq!(motors.get_torque_limit().unwrap(), [0.0, 0.0]);
+        assert_eq!(motors.is_torque_on().unwrap(), [false; 2]);
+    }
+
+    #[test]
+    fn disarm() {
+        let guard = ShutdownGuard::new(
+            enabled_motors([1.0, -1.0]),
+            config(None, Release::TorqueOff),
+        );
+        let controller = guard.controller();
+        guard.disarm();
+        drop(guard);
+
+        let mut motors = controller.lock().unwrap();
+        assert_eq!(motors.is_torque_on().unwrap(), [true; 2]);
+    }
+
+    #[test]
+    fn disarm_while_waiting() {
+        let guard = ShutdownGuard::new(
+            enabled_motors([1.0, -1.0]),
+            config(None, Release::TorqueOff),
+        );
+
+        std::thread::scope(|s| {
+            let locked = guard.lock();
+            let shutdown = s.spawn(|| guard.shutdown().is_ok());
+            std::thread::sleep(Duration::from_millis(20));
+            guard.disarm();
+            drop(locked);
+            assert!(shutdown.join().unwrap());
+        });
+
+        assert_eq!(guard.lock().is_torque_on().unwrap(), [true; 2]);
+    }
+
+    #[test]
+    fn release_when_stopped() {
+        let mut motors = enabled_motors([1.0, -1.0]);
+        motors.set_torque_limit([2.0, 2.0]).unwrap();
+        motors.emergency_stop_latch().unwrap().trigger();
+
+        let ramp = TorqueRamp {
+            duration: Duration::ZERO,
+            steps: 1,
+            max_torque: 1.0,
+        };
+        let guard = ShutdownGuard::new(motors, config(Some([0.0; 2]), Release::RampDown(ramp)));
+        guard.shutdown().unwrap();
+
+        let mut motors = guard.lock();
+        assert_eq!(motors.get_current_position().unwrap(), [1.0, -1.0]);
+        assert_eq!(motors.get_torque_limit().unwrap(), [0.0, 0.0]);
+        assert_eq!(motors.is_torque_on().unwrap(), [false; 2]);
+    }
+
+    #[test]
+    fn never_hang() {
+        let mut config = config(None, Release::TorqueOff);
+        config.timeout = Duration::from_millis(20);
+
+        let guard = ShutdownGuard::new(enabled_motors([1.0, -1.0]), config);
+        {
+            let _locked = guard.lock();
+            assert!(guard.shutdown().is_err());
+        }
+
+        // Still pending, so it can be run again
+        guard.shutdown().unwrap();
+        assert_eq!(guard.lock().is_torque_on().unwrap(), [false; 2]);
+    }
+
+    #[test]
+    fn on_panic() {
+        let guard = ShutdownGuard::new(
+            enabled_motors([1.0, -1.0]),
+            config(Some([0.25, 0.25]), Release::TorqueOff),
+        );
+        // The process-wide hook is not installed here, only its reaction is run
+        let reaction = guard.panic_reaction();
+        let controller = guard.controller();
+
+        // The panicking thread holds the controller lock while the hook runs
+        let res = std::thread::spawn(move || {
+            let _locked = controller.lock().unwrap();
+            reaction();
+            panic!("planner crashed");
+        })
+        .join();
+        assert!(res.is_err());
+
+        let start = Instant::now();
+        while guard.lock().is_torque_on().unwrap() != [false; 2] {
+            assert!(start.elapsed() < Duration::from_secs(1));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let mut motors = guard.lock();
+        assert!(motors.is_emergency_stopped());
+        assert_eq!(motors.get_current_position().unwrap(), [1.0, -1.0]);
+    }
+}
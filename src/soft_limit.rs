@@ -0,0 +1,319 @@
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Limit;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Soft limit of a joint: a deceleration zone inside its hard limit
+///
+/// Within `margin` of a bound of the hard limit, the velocity allowed toward
+/// this bound shrinks linearly from `max_velocity` (at the edge of the zone) to
+/// zero (at the bound). Moving away from the bound is never restricted.
+pub struct SoftLimit {
+    /// Width of the deceleration zone (in radians)
+    pub margin: f64,
+    /// Velocity allowed toward the bound at the edge of the zone (in rad/s)
+    pub max_velocity: f64,
+}
+
+impl SoftLimit {
+    pub fn new(margin: f64, max_velocity: f64) -> Self {
+        Self {
+            margin,
+            max_velocity,
+        }
+    }
+
+    /// Distances from a position to the lower and upper bounds (negative when past the bound)
+    ///
+    /// For angular limits, distances are measured along the arc.
+    fn distances(limit: &Limit, position: f64) -> (f64, f64) {
+        let (min, max) = (limit.min(), limit.max());
+        if !limit.is_angular() {
+            return (position - min, max - position);
+        }
+
+        let width = (max - min).rem_euclid(TAU);
+        let rel = (position - min).rem_euclid(TAU);
+        if rel <= width || rel - width < TAU - rel {
+            (rel, width - rel)
+        } else {
+            (rel - TAU, width + TAU - rel)
+        }
+    }
+
+    /// Scale of the allowed velocity at a given distance from a bound (1 outside the zone)
+    fn scale(&self, distance: f64) -> f64 {
+        if distance >= self.margin {
+            1.0
+        } else if self.margin > 0.0 {
+            (distance / self.margin).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Penetration into the deceleration zone of a hard limit, from 0 (outside) to 1 (at or past the bound)
+    pub fn penetration(&self, limit: &Limit, position: f64) -> f64 {
+        let (lower, upper) = Self::distances(limit, position);
+        1.0 - self.scale(lower.min(upper))
+    }
+
+    /// Range of velocities allowed at a position inside a hard limit (in rad/s)
+    ///
+    /// A bound is infinite when the position is outside the corresponding zone.
+    pub fn velocity_range(&self, limit: &Limit, position: f64) -> (f64, f64) {
+        let (lower, upper) = Self::distances(limit, position);
+        let bound = |distance: f64| match self.scale(distance) {
+            scale if scale >= 1.0 => f64::INFINITY,
+            scale => self.max_velocity * scale,
+        };
+        (-bound(lower), bound(upper))
+    }
+
+    /// Limit a velocity command at the current position (in rad/s)
+    pub fn limit_velocity(&self, limit: &Limit, position: f64, velocity: f64) -> f64 {
+        let (min, max) = self.velocity_range(limit, position);
+        velocity.clamp(min, max)
+    }
+
+    /// Limit a position command moving from `from` to `to` during `dt` seconds
+    ///
+    /// The target is clamped to the hard limit, and its depth in the zone is
+    /// limited to what the decelerating motion could reach during `dt` (the
+    /// distance to the bound decreases at most exponentially with a rate of
+    /// `max_velocity / margin`).
+    pub fn limit_position(&self, limit: &Limit, from: f64, to: f64, dt: f64) -> f64 {
+        let to = limit.clamp(to);
+        if self.margin <= 0.0 {
+            return to;
+        }
+
+        let decay = (-self.max_velocity.abs() * dt.max(0.0) / self.margin).exp();
+        let (from_lower, from_upper) = Self::distances(limit, from);
+        let (to_lower, to_upper) = Self::distances(limit, to);
+
+        if to_upper < from_upper && to_upper < self.margin {
+            let min_distance = from_upper.clamp(0.0, self.margin) * decay;
+            if to_upper < min_distance {
+                return to - (min_distance - to_upper);
+            }
+        }
+        if to_lower < from_lower && to_lower < self.margin {
+            let min_distance = from_lower.clamp(0.0, self.margin) * decay;
+            if to_lower < min_distance {
+                return to + (min_distance - to_lower);
+            }
+        }
+        to
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Soft limits of all joints, with the period of the position commands
+///
+/// The zones are placed inside the hard limits of the controller; joints
+/// without a hard limit are not restricted.
+pub struct SoftLimits<const N: usize> {
+    limits: [Option<SoftLimit>; N],
+    period: Duration,
+}
+
+impl<const N: usize> SoftLimits<N> {
+    /// Create soft limits, assuming position commands are sent every 10ms
+    pub fn new(limits: [Option<SoftLimit>; N]) -> Self {
+        Self {
+            limits,
+            period: Duration::from_millis(10),
+        }
+    }
+
+    /// Period of the control cycle sending the position commands
+    ///
+    /// Each position command can only go as deep in the zone as allowed
+    /// during this period.
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// Period of the control cycle sending the position commands
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Soft limit of each joint
+    pub fn limits(&self) -> [Option<SoftLimit>; N] {
+        self.limits
+    }
+
+    /// Penetration of each joint into its deceleration zone (0 without soft or hard limit)
+    pub fn penetration(&self, limits: &[Option<Limit>; N], position: [f64; N]) -> [f64; N] {
+        let mut penetration = [0.0; N];
+        for i in 0..N {
+            if let (Some(soft), Some(limit)) = (self.limits[i], limits[i]) {
+                penetration[i] = soft.penetration(&limit, position[i]);
+            }
+        }
+        penetration
+    }
+
+    /// Limit velocity commands at the current positions
+    pub fn limit_velocity(
+        &self,
+        limits: &[Option<Limit>; N],
+        position: [f64; N],
+        velocity: [f64; N],
+    ) -> [f64; N] {
+        let mut limited = velocity;
+        for i in 0..N {
+            if let (Some(soft), Some(limit)) = (self.limits[i], limits[i]) {
+                limited[i] = soft.limit_velocity(&limit, position[i], velocity[i]);
+            }
+        }
+        limited
+    }
+
+    /// Limit position commands moving from the previous targets during one period
+    pub fn limit_position(
+        &self,
+        limits: &[Option<Limit>; N],
+        from: [f64; N],
+        to: [f64; N],
+    ) -> [f64; N] {
+        self.limit_position_dt(limits, from, to, self.period.as_secs_f64())
+    }
+
+    /// Limit position commands moving from `from` to `to` during `dt` seconds
+    pub fn limit_position_dt(
+        &self,
+        limits: &[Option<Limit>; N],
+        from: [f64; N],
+        to: [f64; N],
+        dt: f64,
+    ) -> [f64; N] {
+        let mut limited = to;
+        for i in 0..N {
+            if let (Some(soft), Some(limit)) = (self.limits[i], limits[i]) {
+                limited[i] = soft.limit_position(&limit, from[i], to[i], dt);
+            }
+        }
+        limited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::time::Duration;
+
+    use super::{SoftLimit, SoftLimits};
+    use crate::Limit;
+
+    fn soft_limit() -> (SoftLimit, Limit) {
+        (SoftLimit::new(0.5, 2.0), Limit::new(-1.0, 1.0))
+    }
+
+    #[test]
+    fn penetration() {
+        let (soft, limit) = soft_limit();
+
+        assert_eq!(soft.penetration(&limit, 0.0), 0.0);
+        assert_eq!(soft.penetration(&limit, 0.5), 0.0);
+        assert!((soft.penetration(&limit, 0.75) - 0.5).abs() < 1e-9);
+        assert!((soft.penetration(&limit, -0.9) - 0.8).abs() < 1e-9);
+        assert_eq!(soft.penetration(&limit, 1.0), 1.0);
+        assert_eq!(soft.penetration(&limit, 1.5), 1.0);
+    }
+
+    #[test]
+    fn velocity() {
+        let (soft, limit) = soft_limit();
+
+        assert_eq!(soft.limit_velocity(&limit, 0.0, 10.0), 10.0);
+        assert!((soft.limit_velocity(&limit, 0.75, 10.0) - 1.0).abs() < 1e-9);
+        // Moving away from the bound is not restricted
+        assert_eq!(soft.limit_velocity(&limit, 0.75, -10.0), -10.0);
+        assert_eq!(soft.limit_velocity(&limit, 1.0, 1.0), 0.0);
+        assert_eq!(soft.limit_velocity(&limit, 1.2, 1.0), 0.0);
+        assert!((soft.limit_velocity(&limit, -0.9, -10.0) + 0.4).abs() < 1e-9);
+
+        let (min, max) = soft.velocity_range(&limit, 0.0);
+        assert_eq!((min, max), (f64::NEG_INFINITY, f64::INFINITY));
+    }
+
+    #[test]
+    fn position() {
+        let (soft, limit) = soft_limit();
+
+        // Outside the zone or moving away, only the hard limit applies
+        assert_eq!(soft.limit_position(&limit, 0.0, 0.4, 0.01), 0.4);
+        assert_eq!(soft.limit_position(&limit, 0.9, 0.0, 0.01), 0.0);
+        assert_eq!(soft.limit_position(&limit, 0.0, -2.0, 10.0), -1.0);
+
+        // A jump toward the bound stops close to the edge of the zone
+        let position = soft.limit_position(&limit, 0.0, 1.0, 0.01);
+        assert!((position - (1.0 - 0.5 * (-0.04f64).exp())).abs() < 1e-9);
+
+        // Streaming toward the bound slows down and never reaches it
+        let mut position = 0.4;
+        let mut steps = vec![];
+        for _ in 0..100 {
+            let next = soft.limit_position(&limit, position, 2.0, 0.01);
+            steps.push(next - position);
+            position = next;
+        }
+        assert!(position < 1.0);
+        assert!(position > 0.99);
+        assert!(steps.windows(2).skip(1).all(|s| s[1] < s[0]));
+        assert!(steps[1] <= 2.0 * 0.01);
+    }
+
+    #[test]
+    fn angular() {
+        let soft = SoftLimit::new(0.1, 1.0);
+        let limit = Limit::angular(3.0, -3.0).unwrap();
+
+        assert_eq!(soft.penetration(&limit, PI), 0.0);
+        assert!((soft.penetration(&limit, -3.05) - 0.5).abs() < 1e-9);
+        assert!((soft.limit_velocity(&limit, -3.05, 1.0) - 0.5).abs() < 1e-9);
+        assert_eq!(soft.limit_velocity(&limit, -3.05, -1.0), -1.0);
+        assert_eq!(soft.penetration(&limit, 0.0), 1.0);
+    }
+
+    #[test]
+    fn joints() {
+        let (soft, limit) = soft_limit();
+        let soft_limits =
+            SoftLimits::new([Some(soft), Some(soft), None]).with_period(Duration::from_millis(10));
+        let limits = [Some(limit), None, Some(limit)];
+
+        // Joints need both a soft and a hard limit
+        assert_eq!(
+            soft_limits.penetration(&limits, [0.75, 0.75, 0.75]),
+            [0.5, 0.0, 0.0]
+        );
+        let velocity = soft_limits.limit_velocity(&limits, [0.75, 10.0, 0.75], [10.0, 10.0, 10.0]);
+        assert!((velocity[0] - 1.0).abs() < 1e-9);
+        assert_eq!(velocity[1..], [10.0, 10.0]);
+
+        // Position commands are limited over one period, the same for every call
+        let position = soft_limits.limit_position(&limits, [0.0; 3], [1.0, 5.0, 5.0]);
+        assert!((position[0] - (1.0 - 0.5 * (-0.04f64).exp())).abs() < 1e-9);
+        assert_eq!(position[1..], [5.0, 5.0]);
+        assert_eq!(
+            soft_limits.limit_position(&limits, [0.0; 3], [1.0, 5.0, 5.0]),
+            position
+        );
+    }
+
+    #[test]
+    fn serde() {
+        let (soft, _) = soft_limit();
+        let json = serde_json::to_string(&soft).unwrap();
+        assert_eq!(json, r#"{"margin":0.5,"max_velocity":2.0}"#);
+        assert_eq!(serde_json::from_str::<SoftLimit>(&json).unwrap(), soft);
+    }
+}
@@ -0,0 +1,336 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Interpolation, InvalidWaypointError, MotorsController, Result, TrajectoryStreamer, Waypoint,
+};
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// Positions sampled at a fixed rate during a demonstration
+pub struct RecordedTrajectory<const N: usize> {
+    /// Sampling period
+    pub period: Duration,
+    /// Sampled positions (in radians)
+    #[serde(with = "crate::serde_array::vec")]
+    pub positions: Vec<[f64; N]>,
+}
+
+impl<const N: usize> RecordedTrajectory<N> {
+    /// Duration of the recording
+    pub fn duration(&self) -> Duration {
+        self.period * self.positions.len().saturating_sub(1) as u32
+    }
+
+    /// Smooth the positions with a centered moving average over `window` samples
+    pub fn smoothed(&self, window: usize) -> Self {
+        let half = window / 2;
+        let len = self.positions.len();
+
+        let positions = (0..len)
+            .map(|k| {
+                let (start, end) = (k.saturating_sub(half), (k + half + 1).min(len));
+                let mut mean = [0.0; N];
+                for position in &self.positions[start..end] {
+                    for (m, p) in mean.iter_mut().zip(position) {
+                        *m += p / (end - start) as f64;
+                    }
+                }
+                mean
+            })
+            .collect();
+
+        Self {
+            period: self.period,
+            positions,
+        }
+    }
+
+    /// Prepare the playback of the recording, starting from the current position of the controller
+    ///
+    /// The returned stream first moves to the first recorded position in
+    /// `approach_duration`, then replays the recording. With a zero
+    /// `approach_duration`, the playback starts right at the first recorded
+    /// position. The torque must already be enabled.
+    pub fn playback(
+        &self,
+        controller: &mut dyn MotorsController<N>,
+        options: &PlaybackOptions,
+    ) -> Result<TrajectoryStreamer<N>> {
+        options.validate()?;
+        let trajectory = if options.smoothing > 1 {
+            self.smoothed(options.smoothing)
+        } else {
+            self.clone()
+        };
+        let first = match trajectory.positions.first() {
+            Some(first) => *first,
+            None => {
+                return Err(Box::new(InvalidWaypointError(
+                    "empty recording".to_string(),
+                )))
+            }
+        };
+
+        let current = controller.get_current_position()?;
+        log::info!(target: "teach::playback", "approach from {:?} to {:?}", current, first);
+
+        let mut stream = TrajectoryStreamer::new(options.interpolation);
+        let approach = options.approach_duration.as_secs_f64();
+        if approach > 0.0 {
+            stream.push(Waypoint::with_velocity(0.0, current, [0.0; N]))?;
+        }
+        stream.push(Waypoint::with_velocity(approach, first, [0.0; N]))?;
+
+        let period = trajectory.period.as_secs_f64() * options.time_scale;
+        for (k, position) in trajectory.positions.iter().enumerate().skip(1) {
+            stream.push(Waypoint::new(approach + k as f64 * period, *position))?;
+        }
+        stream.finish();
+
+        Ok(stream)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Options for the playback of a recorded trajectory
+pub struct PlaybackOptions {
+    /// Time scaling factor applied to the recording (2.0 plays twice slower)
+    pub time_scale: f64,
+    /// Size of the moving average window (in samples, 0 or 1 disables smoothing)
+    pub smoothing: usize,
+    /// Duration of the approach move to the first recorded position
+    pub approach_duration: Duration,
+    /// Spline used between recorded positions
+    pub interpolation: Interpolation,
+}
+
+impl PlaybackOptions {
+    /// Check that the time scale is strictly positive
+    pub fn validate(&self) -> std::result::Result<(), InvalidPlaybackOptionsError> {
+        if !(self.time_scale > 0.0 && self.time_scale.is_finite()) {
+            return Err(InvalidPlaybackOptionsError(format!(
+                "time_scale = {}",
+                self.time_scale
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            time_scale: 1.0,
+            smoothing: 0,
+            approach_duration: Duration::from_secs(2),
+            interpolation: Interpolation::Cubic,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidPlaybackOptionsError(pub String);
+impl std::fmt::Display for InvalidPlaybackOptionsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid playback options: {reason})")
+    }
+}
+impl std::error::Error for InvalidPlaybackOptionsError {}
+
+#[derive(Debug)]
+/// Records the positions of compliant motors moved by hand
+pub struct Recorder<const N: usize> {
+    period: Duration,
+    positions: Vec<[f64; N]>,
+}
+
+impl<const N: usize> Recorder<N> {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            positions: Vec::new(),
+        }
+    }
+
+    /// Turn the torque off so the motors can be moved by hand and clear previous samples
+    pub fn start(&mut self, controller: &mut dyn MotorsController<N>) -> Result<()> {
+        controller.set_torque([false; N])?;
+        self.positions.clear();
+        Ok(())
+    }
+
+    /// Record the current position (to be called every period)
+    pub fn sample(&mut self, controller: &mut dyn MotorsController<N>) -> Result<[f64; N]> {
+        let position = controller.get_current_position()?;
+        log::debug!(target: "teach::sample", "sample {}: {:?}", self.positions.len(), position);
+        self.positions.push(position);
+        Ok(position)
+    }
+
+    /// Sample the current position at a fixed rate for `duration` (blocking)
+    pub fn record(
+        &mut self,
+        controller: &mut dyn MotorsController<N>,
+        duration: Duration,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let mut next = start;
+
+        while next - start <= duration {
+            self.sample(controller)?;
+
+            next += self.period;
+            if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop recording and return the recorded trajectory
+    pub fn finish(self) -> RecordedTrajectory<N> {
+        RecordedTrajectory {
+            period: self.period,
+            positions: self.positions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{InvalidPlaybackOptionsError, PlaybackOptions, RecordedTrajectory, Recorder};
+    use crate::{FakeMotorsController, MotorsController, StreamState};
+
+    #[test]
+    fn record_by_hand() {
+        let mut motors = FakeMotorsController::<2>::new();
+        motors.set_torque([true; 2]).unwrap();
+
+        let mut recorder = Recorder::new(Duration::from_millis(10));
+        recorder.start(&mut motors).unwrap();
+        assert_eq!(motors.is_torque_on().unwrap(), [false; 2]);
+
+        for k in 0..5 {
+            let k = k as f64;
+            motors.fake_io().set_current_position([0.5 * k, -0.5 * k]);
+            recorder.sample(&mut motors).unwrap();
+        }
+
+        let trajectory = recorder.finish();
+        assert_eq!(trajectory.period, Duration::from_millis(10));
+        assert_eq!(trajectory.positions.len(), 5);
+        assert_eq!(trajectory.positions[4], [2.0, -2.0]);
+        assert_eq!(trajectory.duration(), Duration::from_millis(40));
+
+        let json = serde_json::to_string(&trajectory).unwrap();
+        let loaded: RecordedTrajectory<2> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, trajectory);
+
+        assert!(serde_json::from_str::<RecordedTrajectory<3>>(&json).is_err());
+    }
+
+    #[test]
+    fn record_fixed_rate() {
+        let mut motors = FakeMotorsController::<1>::new();
+
+        let mut recorder = Recorder::new(Duration::from_millis(2));
+        recorder.start(&mut motors).unwrap();
+        recorder
+            .record(&mut motors, Duration::from_millis(10))
+            .unwrap();
+
+        assert_eq!(recorder.finish().positions.len(), 6);
+    }
+
+    #[test]
+    fn smoothing() {
+        let trajectory = RecordedTrajectory {
+            period: Duration::from_millis(100),
+            positions: vec![[0.0], [0.0], [3.0], [0.0], [0.0]],
+        };
+
+        assert_eq!(trajectory.smoothed(1), trajectory);
+        assert_eq!(
+            trajectory.smoothed(3).positions,
+            vec![[0.0], [1.0], [1.0], [1.0], [0.0]]
+        );
+    }
+
+    #[test]
+    fn playback() {
+        let trajectory = RecordedTrajectory {
+            period: Duration::from_millis(250),
+            positions: vec![[0.5], [1.0], [1.5]],
+        };
+
+        let mut motors = FakeMotorsController::<1>::new();
+        motors.set_torque([true]).unwrap();
+        motors.set_target_position([-1.0]).unwrap();
+
+        let options = PlaybackOptions {
+            time_scale: 2.0,
+            approach_duration: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let mut stream = trajectory.playback(&mut motors, &options).unwrap();
+
+        stream.update(&mut motors, 0.0).unwrap();
+        assert_eq!(motors.get_current_position().unwrap(), [-1.0]);
+
+        for _ in 0..4 {
+            stream.update(&mut motors, 0.25).unwrap();
+        }
+        assert_eq!(motors.get_current_position().unwrap(), [0.5]);
+
+        for _ in 0..2 {
+            stream.update(&mut motors, 0.25).unwrap();
+        }
+        assert_eq!(motors.get_current_position().unwrap(), [1.0]);
+
+        for _ in 0..3 {
+            stream.update(&mut motors, 0.25).unwrap();
+        }
+        assert_eq!(stream.state(), StreamState::Finished);
+        assert_eq!(motors.get_current_position().unwrap(), [1.5]);
+        assert!((stream.progress().end_time - 2.0).abs() < 1e-9);
+
+        let empty = RecordedTrajectory::<1> {
+            period: Duration::from_millis(100),
+            positions: vec![],
+        };
+        assert!(empty.playback(&mut motors, &options).is_err());
+    }
+
+    #[test]
+    fn playback_options() {
+        let trajectory = RecordedTrajectory {
+            period: Duration::from_millis(250),
+            positions: vec![[0.5], [1.0]],
+        };
+        let mut motors = FakeMotorsController::<1>::new();
+        motors.set_torque([true]).unwrap();
+
+        for time_scale in [0.0, -1.0, f64::NAN] {
+            let options = PlaybackOptions {
+                time_scale,
+                ..Default::default()
+            };
+            let err = trajectory.playback(&mut motors, &options).unwrap_err();
+            assert!(err.is::<InvalidPlaybackOptionsError>());
+        }
+
+        // Without approach, the playback starts at the first recorded position
+        let options = PlaybackOptions {
+            approach_duration: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut stream = trajectory.playback(&mut motors, &options).unwrap();
+        stream.update(&mut motors, 0.0).unwrap();
+        assert_eq!(motors.get_current_position().unwrap(), [0.5]);
+        assert!((stream.progress().end_time - 0.25).abs() < 1e-9);
+    }
+}
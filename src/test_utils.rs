@@ -0,0 +1,18 @@
+//! Helpers shared by the tests of the modules
+
+use crate::{FakeMotorsController, MotorsController};
+
+/// Assert that the values are equal up to the tolerance
+pub(crate) fn assert_close<const N: usize>(a: [f64; N], b: [f64; N], tolerance: f64) {
+    for (x, y) in a.iter().zip(b) {
+        assert!((x - y).abs() < tolerance, "{:?} != {:?}", a, b);
+    }
+}
+
+/// Fake controller with torque on, holding the given target positions
+pub(crate) fn enabled_motors<const N: usize>(target: [f64; N]) -> FakeMotorsController<N> {
+    let mut motors = FakeMotorsController::<N>::new();
+    motors.set_torque([true; N]).unwrap();
+    motors.set_target_position(target).unwrap();
+    motors
+}
@@ -0,0 +1,631 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{MotorsController, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+/// Time-stamped waypoint of a trajectory
+pub struct Waypoint<const N: usize> {
+    /// Time of the waypoint (in seconds)
+    pub time: f64,
+    /// Position of the motors (in radians)
+    #[serde(with = "crate::serde_array")]
+    pub position: [f64; N],
+    /// Optional velocity of the motors (in radians per second)
+    ///
+    /// When not given, it is estimated from the neighboring waypoints.
+    #[serde(default, with = "crate::serde_array::option")]
+    pub velocity: Option<[f64; N]>,
+}
+
+impl<const N: usize> Waypoint<N> {
+    /// Create a waypoint without velocity
+    pub fn new(time: f64, position: [f64; N]) -> Self {
+        Self {
+            time,
+            position,
+            velocity: None,
+        }
+    }
+
+    /// Create a waypoint with an explicit velocity
+    pub fn with_velocity(time: f64, position: [f64; N], velocity: [f64; N]) -> Self {
+        Self {
+            time,
+            position,
+            velocity: Some(velocity),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Spline used between two consecutive waypoints
+pub enum Interpolation {
+    /// Cubic Hermite spline (continuous velocity)
+    Cubic,
+    /// Quintic Hermite spline with zero acceleration at waypoints (continuous acceleration)
+    Quintic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// State of a trajectory stream
+pub enum StreamState {
+    /// No waypoint received yet
+    Idle,
+    /// Trajectory is being executed
+    Running,
+    /// The buffer ran out of waypoints before the stream was finished, position is held
+    Underrun,
+    /// The last waypoint has been reached
+    Finished,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Progress report of a trajectory stream
+pub struct Progress {
+    /// Current trajectory time (in seconds)
+    pub time: f64,
+    /// Time of the last buffered waypoint (in seconds)
+    pub end_time: f64,
+    /// Number of waypoints already reached
+    pub completed: usize,
+    /// Number of waypoints still ahead in the buffer
+    pub buffered: usize,
+    /// Number of underruns since the stream started
+    pub underruns: usize,
+    /// Current state of the stream
+    pub state: StreamState,
+}
+
+impl Progress {
+    /// Remaining buffered time (in seconds)
+    pub fn remaining(&self) -> f64 {
+        (self.end_time - self.time).max(0.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidWaypointError(pub String);
+impl std::fmt::Display for InvalidWaypointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid waypoint: {reason})")
+    }
+}
+impl std::error::Error for InvalidWaypointError {}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment<const N: usize> {
+    t0: f64,
+    t1: f64,
+    p0: [f64; N],
+    p1: [f64; N],
+    v0: [f64; N],
+    v1: [f64; N],
+    /// Built before the waypoint after its end was known, so it ends at rest
+    provisional: bool,
+}
+
+impl<const N: usize> Segment<N> {
+    fn sample(&self, interpolation: Interpolation, time: f64) -> [f64; N] {
+        let h = self.t1 - self.t0;
+        let s = ((time - self.t0) / h).clamp(0.0, 1.0);
+        let (s2, s3) = (s * s, s * s * s);
+
+        let (h_p0, h_v0, h_v1, h_p1) = match interpolation {
+            Interpolation::Cubic => (
+                2.0 * s3 - 3.0 * s2 + 1.0,
+                s3 - 2.0 * s2 + s,
+                s3 - s2,
+                -2.0 * s3 + 3.0 * s2,
+            ),
+            Interpolation::Quintic => {
+                let (s4, s5) = (s3 * s, s3 * s2);
+                (
+                    1.0 - 10.0 * s3 + 15.0 * s4 - 6.0 * s5,
+                    s - 6.0 * s3 + 8.0 * s4 - 3.0 * s5,
+                    -4.0 * s3 + 7.0 * s4 - 3.0 * s5,
+                    10.0 * s3 - 15.0 * s4 + 6.0 * s5,
+                )
+            }
+        };
+
+        let mut position = [0.0; N];
+        for (i, p) in position.iter_mut().enumerate() {
+            *p = h_p0 * self.p0[i]
+                + h_v0 * h * self.v0[i]
+                + h_v1 * h * self.v1[i]
+                + h_p1 * self.p1[i];
+        }
+        position
+    }
+
+    fn velocity(&self, interpolation: Interpolation, time: f64) -> [f64; N] {
+        let h = self.t1 - self.t0;
+        let s = ((time - self.t0) / h).clamp(0.0, 1.0);
+        let (s2, s3) = (s * s, s * s * s);
+
+        // Derivatives of the basis functions with respect to s
+        let (d_p0, d_v0, d_v1, d_p1) = match interpolation {
+            Interpolation::Cubic => (
+                6.0 * s2 - 6.0 * s,
+                3.0 * s2 - 4.0 * s + 1.0,
+                3.0 * s2 - 2.0 * s,
+                -6.0 * s2 + 6.0 * s,
+            ),
+            Interpolation::Quintic => {
+                let s4 = s3 * s;
+                (
+                    -30.0 * s2 + 60.0 * s3 - 30.0 * s4,
+                    1.0 - 18.0 * s2 + 32.0 * s3 - 15.0 * s4,
+                    -12.0 * s2 + 28.0 * s3 - 15.0 * s4,
+                    30.0 * s2 - 60.0 * s3 + 30.0 * s4,
+                )
+            }
+        };
+
+        let mut velocity = [0.0; N];
+        for (i, v) in velocity.iter_mut().enumerate() {
+            *v =
+                (d_p0 * self.p0[i] + d_p1 * self.p1[i]) / h + d_v0 * self.v0[i] + d_v1 * self.v1[i];
+        }
+        velocity
+    }
+}
+
+#[derive(Debug)]
+/// Streams a buffer of time-stamped waypoints to a controller as a spline
+///
+/// Waypoints can be appended while the trajectory is executed. Until the
+/// waypoint after a segment is known, the segment ends at rest, and it is
+/// re-planned from the current position and velocity when that waypoint
+/// arrives. If the trajectory time reaches the last buffered waypoint before
+/// [finish](Self::finish) has been called, the stream underruns: the last
+/// waypoint position is held and the trajectory clock is paused until new
+/// waypoints are pushed.
+pub struct TrajectoryStreamer<const N: usize> {
+    interpolation: Interpolation,
+
+    buffer: VecDeque<Waypoint<N>>,
+    previous: Option<Waypoint<N>>,
+    segment: Option<Segment<N>>,
+    entry_velocity: Option<[f64; N]>,
+
+    time: Option<f64>,
+    closed: bool,
+    state: StreamState,
+
+    completed: usize,
+    underruns: usize,
+}
+
+impl<const N: usize> TrajectoryStreamer<N> {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            interpolation,
+
+            buffer: VecDeque::new(),
+            previous: None,
+            segment: None,
+            entry_velocity: None,
+
+            time: None,
+            closed: false,
+            state: StreamState::Idle,
+
+            completed: 0,
+            underruns: 0,
+        }
+    }
+
+    /// Create a finished stream moving smoothly from one position to another, starting and ending at rest
+    pub fn point_to_point(from: [f64; N], to: [f64; N], duration: Duration) -> Result<Self> {
+        let mut stream = Self::new(Interpolation::Quintic);
+        stream.push(Waypoint::with_velocity(0.0, from, [0.0; N]))?;
+        stream.push(Waypoint::with_velocity(
+            duration.as_secs_f64().max(f64::EPSILON),
+            to,
+            [0.0; N],
+        ))?;
+        stream.finish();
+        Ok(stream)
+    }
+
+    /// Append a waypoint at the end of the buffer
+    ///
+    /// Waypoint times must be strictly increasing.
+    pub fn push(&mut self, waypoint: Waypoint<N>) -> Result<()> {
+        if self.closed {
+            return Err(Box::new(InvalidWaypointError(
+                "stream already finished".to_string(),
+            )));
+        }
+        if !waypoint.time.is_finite()
+            || waypoint.position.iter().any(|p| !p.is_finite())
+            || waypoint
+                .velocity
+                .is_some_and(|v| v.iter().any(|v| !v.is_finite()))
+        {
+            return Err(Box::new(InvalidWaypointError(format!(
+                "non finite value in {:?}",
+                waypoint
+            ))));
+        }
+        if let Some(last) = self.buffer.back().or(self.previous.as_ref()) {
+            if waypoint.time <= last.time {
+                return Err(Box::new(InvalidWaypointError(format!(
+                    "time {} is not after {}",
+                    waypoint.time, last.time
+                ))));
+            }
+        }
+
+        log::debug!(target: "trajectory::push", "push waypoint {:?}", waypoint);
+        self.buffer.push_back(waypoint);
+
+        Ok(())
+    }
+
+    /// Append several waypoints at the end of the buffer
+    pub fn extend(&mut self, waypoints: impl IntoIterator<Item = Waypoint<N>>) -> Result<()> {
+        for waypoint in waypoints {
+            self.push(waypoint)?;
+        }
+        Ok(())
+    }
+
+    /// Mark the stream as complete: reaching the last waypoint is no longer an underrun
+    pub fn finish(&mut self) {
+        self.closed = true;
+    }
+
+    /// Current state of the stream
+    pub fn state(&self) -> StreamState {
+        self.state
+    }
+
+    /// Report the progress of the stream
+    pub fn progress(&self) -> Progress {
+        let time = self
+            .time
+            .or(self.buffer.front().map(|w| w.time))
+            .unwrap_or(0.0);
+        let end_time = self.buffer.back().map(|w| w.time).unwrap_or(time);
+
+        Progress {
+            time,
+            end_time,
+            completed: self.completed,
+            buffered: self.buffer.len().saturating_sub(1),
+            underruns: self.underruns,
+            state: self.state,
+        }
+    }
+
+    /// Advance the trajectory by `dt` seconds and send the new target position to the controller
+    ///
+    /// The first call starts the trajectory at the time of the first waypoint.
+    pub fn update(
+        &mut self,
+        controller: &mut dyn MotorsController<N>,
+        dt: f64,
+    ) -> Result<StreamState> {
+        let position = match self.step(dt) {
+            Some(position) => position,
+            None => return Ok(self.state),
+        };
+
+        log::debug!(target: "trajectory::update", "t={:?} state={:?} position={:?}", self.time, self.state, position);
+        controller.set_target_position(position)?;
+
+        Ok(self.state)
+    }
+
+    /// Execute the stream every `period` until it is finished or underruns (blocking)
+    ///
+    /// The execution is given up after `timeout`, the state reached is returned.
+    pub fn run(
+        &mut self,
+        controller: &mut dyn MotorsController<N>,
+        period: Duration,
+        timeout: Duration,
+    ) -> Result<StreamState> {
+        let start = Instant::now();
+        let mut next = start;
+
+        loop {
+            match self.update(controller, period.as_secs_f64())? {
+                StreamState::Running => {}
+                state => return Ok(state),
+            }
+            if start.elapsed() > timeout {
+                log::warn!(target: "trajectory::run", "timed out after {:?}", timeout);
+                return Ok(self.state);
+            }
+
+            next += period;
+            if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+
+    /// Advance the trajectory by `dt` seconds and return the position to send
+    fn step(&mut self, dt: f64) -> Option<[f64; N]> {
+        let first = self.buffer.front()?;
+        let mut time = match self.time {
+            Some(time) => time + dt,
+            None => first.time,
+        };
+
+        // The waypoint after the current segment arrived: re-plan its remainder
+        // from the current position and velocity instead of stopping
+        if let (Some(segment), Some(now)) = (self.segment, self.time) {
+            if segment.provisional && (self.buffer.len() >= 3 || self.closed) && now < segment.t1 {
+                let mut replanned = self.build_segment();
+                replanned.t0 = now;
+                replanned.p0 = segment.sample(self.interpolation, now);
+                replanned.v0 = segment.velocity(self.interpolation, now);
+                self.segment = Some(replanned);
+            }
+        }
+
+        while self.buffer.len() >= 2 && time >= self.buffer[1].time {
+            // Segments skipped within a single step still carry their exit velocity over
+            let segment = self.segment.take().unwrap_or_else(|| self.build_segment());
+            self.entry_velocity = Some(segment.v1);
+            self.previous = self.buffer.pop_front();
+            self.completed += 1;
+        }
+
+        let position = if self.buffer.len() >= 2 {
+            self.state = StreamState::Running;
+
+            let segment = match self.segment {
+                Some(segment) => segment,
+                None => {
+                    let segment = self.build_segment();
+                    self.segment = Some(segment);
+                    segment
+                }
+            };
+            segment.sample(self.interpolation, time)
+        } else {
+            let last = self.buffer[0];
+            time = last.time;
+
+            if self.closed {
+                self.state = StreamState::Finished;
+            } else {
+                // The position is held, the next segment starts at rest
+                self.entry_velocity = Some([0.0; N]);
+                if self.state != StreamState::Underrun {
+                    log::warn!(target: "trajectory::update", "buffer underrun at t={}, holding position", time);
+                    self.underruns += 1;
+                }
+                self.state = StreamState::Underrun;
+            }
+            last.position
+        };
+
+        self.time = Some(time);
+        Some(position)
+    }
+
+    fn build_segment(&self) -> Segment<N> {
+        let (start, end) = (self.buffer[0], self.buffer[1]);
+
+        // The velocity the previous segment ended with (at rest after an underrun) comes first
+        let v0 = self
+            .entry_velocity
+            .or(start.velocity)
+            .unwrap_or_else(|| match self.previous {
+                Some(previous) => estimate_velocity(&previous, &end),
+                None => [0.0; N],
+            });
+        // Without the next waypoint yet (streamed just in time), end at rest until it arrives
+        let provisional = end.velocity.is_none() && !self.closed && self.buffer.len() < 3;
+        let v1 = end.velocity.unwrap_or_else(|| match self.buffer.get(2) {
+            Some(next) => estimate_velocity(&start, next),
+            None => [0.0; N],
+        });
+
+        Segment {
+            t0: start.time,
+            t1: end.time,
+            p0: start.position,
+            p1: end.position,
+            v0,
+            v1,
+            provisional,
+        }
+    }
+}
+
+/// Central difference velocity between the neighbors of a waypoint
+fn estimate_velocity<const N: usize>(before: &Waypoint<N>, after: &Waypoint<N>) -> [f64; N] {
+    let dt = after.time - before.time;
+    let mut velocity = [0.0; N];
+    for (v, (a, b)) in velocity
+        .iter_mut()
+        .zip(after.position.iter().zip(before.position))
+    {
+        *v = (a - b) / dt;
+    }
+    velocity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Interpolation, StreamState, TrajectoryStreamer, Waypoint};
+    use crate::test_utils::assert_close;
+    use crate::{FakeMotorsController, MotorsController};
+
+    #[test]
+    fn reject_bad_waypoints() {
+        let mut stream = TrajectoryStreamer::<1>::new(Interpolation::Cubic);
+
+        stream.push(Waypoint::new(1.0, [0.0])).unwrap();
+        assert!(stream.push(Waypoint::new(1.0, [1.0])).is_err());
+        assert!(stream.push(Waypoint::new(0.5, [1.0])).is_err());
+        assert!(stream.push(Waypoint::new(2.0, [f64::NAN])).is_err());
+
+        stream.finish();
+        assert!(stream.push(Waypoint::new(3.0, [1.0])).is_err());
+    }
+
+    #[test]
+    fn go_through_waypoints() {
+        for interpolation in [Interpolation::Cubic, Interpolation::Quintic] {
+            let mut motors = FakeMotorsController::<2>::new();
+            motors.set_torque([true; 2]).unwrap();
+
+            let mut stream = TrajectoryStreamer::new(interpolation);
+            stream
+                .extend([
+                    Waypoint::new(0.0, [0.0, 0.0]),
+                    Waypoint::new(1.0, [1.0, -1.0]),
+                    Waypoint::new(2.0, [2.0, 0.0]),
+                ])
+                .unwrap();
+            stream.finish();
+
+            assert_eq!(
+                stream.update(&mut motors, 0.25).unwrap(),
+                StreamState::Running
+            );
+            assert_close(motors.get_target_position().unwrap(), [0.0, 0.0], 1e-9);
+
+            for _ in 0..4 {
+                stream.update(&mut motors, 0.25).unwrap();
+            }
+            assert_close(motors.get_target_position().unwrap(), [1.0, -1.0], 1e-9);
+            assert_eq!(stream.progress().completed, 1);
+
+            for _ in 0..2 {
+                stream.update(&mut motors, 0.25).unwrap();
+            }
+            let mid = motors.get_target_position().unwrap();
+            assert!(mid[0] > 1.0 && mid[0] < 2.0);
+            assert!(mid[1] > -1.0 && mid[1] < 0.0);
+
+            for _ in 0..4 {
+                stream.update(&mut motors, 0.25).unwrap();
+            }
+            assert_eq!(stream.state(), StreamState::Finished);
+            assert_close(motors.get_target_position().unwrap(), [2.0, 0.0], 1e-9);
+        }
+    }
+
+    #[test]
+    fn explicit_velocity() {
+        let mut stream = TrajectoryStreamer::<1>::new(Interpolation::Cubic);
+        stream
+            .extend([
+                Waypoint::with_velocity(0.0, [0.0], [1.0]),
+                Waypoint::with_velocity(1.0, [1.0], [1.0]),
+            ])
+            .unwrap();
+
+        // Constant velocity through both waypoints is a straight line
+        stream.step(0.0);
+        assert_close(stream.step(0.25).unwrap(), [0.25], 1e-9);
+        assert_close(stream.step(0.5).unwrap(), [0.75], 1e-9);
+    }
+
+    #[test]
+    fn streamed_just_in_time() {
+        let mut stream = TrajectoryStreamer::<1>::new(Interpolation::Cubic);
+        stream
+            .extend([
+                Waypoint::with_velocity(0.0, [0.0], [1.0]),
+                Waypoint::new(1.0, [1.0]),
+            ])
+            .unwrap();
+        stream.step(0.0);
+        // Nothing is known after the waypoint yet: the segment plans to end at rest
+        assert_close(stream.step(0.5).unwrap(), [0.625], 1e-9);
+
+        // The next waypoint arrives after the segment is built: no stop at the waypoint
+        stream.push(Waypoint::new(2.0, [2.0])).unwrap();
+        stream.push(Waypoint::new(3.0, [3.0])).unwrap();
+        assert_close(stream.step(0.75).unwrap(), [1.25], 1e-9);
+
+        // Several waypoints passed in one step keep the velocity as well
+        stream.push(Waypoint::new(4.0, [4.0])).unwrap();
+        stream.push(Waypoint::new(5.0, [5.0])).unwrap();
+        assert_close(stream.step(2.0).unwrap(), [3.25], 1e-9);
+        assert_eq!(stream.progress().completed, 3);
+    }
+
+    #[test]
+    fn underrun_holds_and_resumes() {
+        let mut motors = FakeMotorsController::<1>::new();
+        motors.set_torque([true]).unwrap();
+
+        let mut stream = TrajectoryStreamer::new(Interpolation::Quintic);
+        assert_eq!(stream.update(&mut motors, 0.1).unwrap(), StreamState::Idle);
+
+        stream
+            .extend([Waypoint::new(0.0, [0.0]), Waypoint::new(1.0, [1.0])])
+            .unwrap();
+        for _ in 0..15 {
+            stream.update(&mut motors, 0.1).unwrap();
+        }
+        assert_eq!(stream.state(), StreamState::Underrun);
+        assert_close(motors.get_current_position().unwrap(), [1.0], 1e-9);
+
+        let progress = stream.progress();
+        assert_eq!(progress.underruns, 1);
+        assert_eq!(progress.time, 1.0);
+        assert_eq!(progress.remaining(), 0.0);
+
+        // Resumes from rest, ending at rest on the last buffered waypoint
+        stream.push(Waypoint::new(2.0, [0.0])).unwrap();
+        assert_eq!(
+            stream.update(&mut motors, 0.5).unwrap(),
+            StreamState::Running
+        );
+        assert_close(motors.get_current_position().unwrap(), [0.5], 1e-9);
+        assert_eq!(stream.progress().buffered, 1);
+
+        stream.finish();
+        for _ in 0..10 {
+            stream.update(&mut motors, 0.1).unwrap();
+        }
+        assert_eq!(stream.state(), StreamState::Finished);
+        assert_eq!(stream.progress().underruns, 1);
+        assert_close(motors.get_current_position().unwrap(), [0.0], 1e-9);
+    }
+
+    #[test]
+    fn velocity_continuous_across_underrun() {
+        let mut stream = TrajectoryStreamer::<1>::new(Interpolation::Cubic);
+        stream
+            .extend([Waypoint::new(0.0, [0.0]), Waypoint::new(1.0, [1.0])])
+            .unwrap();
+
+        let dt = 0.01;
+        let mut positions = vec![stream.step(0.0).unwrap()[0]];
+        for i in 0..300 {
+            // The next waypoints arrive late, once the stream underran
+            match i {
+                150 => stream
+                    .push(Waypoint::with_velocity(2.0, [2.0], [1.0]))
+                    .unwrap(),
+                200 => stream.push(Waypoint::new(3.0, [3.0])).unwrap(),
+                _ => (),
+            }
+            positions.push(stream.step(dt).unwrap()[0]);
+        }
+        assert_eq!(stream.progress().underruns, 1);
+
+        // Neither a velocity step when holding nor when resuming
+        let velocities: Vec<f64> = positions.windows(2).map(|p| (p[1] - p[0]) / dt).collect();
+        for v in velocities.windows(2) {
+            assert!((v[1] - v[0]).abs() < 0.1, "{} -> {}", v[0], v[1]);
+        }
+    }
+}
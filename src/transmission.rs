@@ -0,0 +1,298 @@
+use serde::{Deserialize, Serialize};
+
+/// Pivot magnitude under which the coupling matrix is considered singular
+const SINGULAR_PIVOT: f64 = 1e-12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "TransmissionDef", into = "TransmissionDef")]
+/// Linear transmission between the joints and the motors: `motor = matrix · joint + offset`
+///
+/// It generalizes the per-motor joint space offset and reduction (the diagonal
+/// case, `matrix[i][i] = reduction[i]` and `offset[i] = reduction[i] * offsets[i]`)
+/// to coupled transmissions such as differentials. Motor velocities are the
+/// joint ones mapped by the matrix alone, and motor torques the joint ones
+/// mapped by the inverse of its transpose (divided by the reduction in the
+/// diagonal case), so that the power is the same on both sides. Unlike the
+/// scalar reductions, target velocities and torques are converted as well.
+///
+/// Joint limits are not mapped to the motors: velocity and torque limits are
+/// motor limits, sent as is.
+pub struct Transmission<const N: usize> {
+    matrix: [[f64; N]; N],
+    offset: [f64; N],
+    inverse: [[f64; N]; N],
+}
+
+impl<const N: usize> Transmission<N> {
+    /// Create a transmission, checking that the matrix is invertible
+    pub fn new(matrix: [[f64; N]; N], offset: [f64; N]) -> Result<Self, InvalidTransmissionError> {
+        if matrix
+            .iter()
+            .flatten()
+            .chain(&offset)
+            .any(|v| !v.is_finite())
+        {
+            return Err(InvalidTransmissionError("non finite value".to_string()));
+        }
+        let inverse = invert(matrix).ok_or_else(|| {
+            InvalidTransmissionError(format!("singular coupling matrix {matrix:?}"))
+        })?;
+        Ok(Self {
+            matrix,
+            offset,
+            inverse,
+        })
+    }
+
+    /// Diagonal transmission equivalent to per-motor offsets (in radians) and reductions
+    pub fn from_scalar(
+        offsets: [Option<f64>; N],
+        reduction: [Option<f64>; N],
+    ) -> Result<Self, InvalidTransmissionError> {
+        let mut matrix = [[0.0; N]; N];
+        let mut offset = [0.0; N];
+        for i in 0..N {
+            matrix[i][i] = reduction[i].unwrap_or(1.0);
+            offset[i] = matrix[i][i] * offsets[i].unwrap_or(0.0);
+        }
+        Self::new(matrix, offset)
+    }
+
+    /// Coupling matrix
+    pub fn matrix(&self) -> [[f64; N]; N] {
+        self.matrix
+    }
+
+    /// Motor positions when all the joints are at zero (in radians)
+    pub fn offset(&self) -> [f64; N] {
+        self.offset
+    }
+
+    /// Equivalent (offset, reduction) of each motor, if the transmission is diagonal
+    pub fn scalar(&self) -> Option<[(f64, f64); N]> {
+        let coupled = (0..N).any(|i| (0..N).any(|j| i != j && self.matrix[i][j] != 0.0));
+        if coupled {
+            return None;
+        }
+        let mut scalar = [(0.0, 1.0); N];
+        for (i, (offset, reduction)) in scalar.iter_mut().enumerate() {
+            *reduction = self.matrix[i][i];
+            *offset = self.offset[i] / self.matrix[i][i];
+        }
+        Some(scalar)
+    }
+
+    /// Motor positions for joint positions (in radians)
+    pub fn position_to_motor(&self, position: [f64; N]) -> [f64; N] {
+        let mut motor = multiply(&self.matrix, position);
+        for (motor, offset) in motor.iter_mut().zip(self.offset) {
+            *motor += offset;
+        }
+        motor
+    }
+
+    /// Joint positions for motor positions (in radians)
+    pub fn position_to_joint(&self, position: [f64; N]) -> [f64; N] {
+        let mut motor = position;
+        for (motor, offset) in motor.iter_mut().zip(self.offset) {
+            *motor -= offset;
+        }
+        multiply(&self.inverse, motor)
+    }
+
+    /// Motor velocities for joint velocities (in rad/s)
+    pub fn velocity_to_motor(&self, velocity: [f64; N]) -> [f64; N] {
+        multiply(&self.matrix, velocity)
+    }
+
+    /// Joint velocities for motor velocities (in rad/s)
+    pub fn velocity_to_joint(&self, velocity: [f64; N]) -> [f64; N] {
+        multiply(&self.inverse, velocity)
+    }
+
+    /// Motor torques producing joint torques (in Nm)
+    pub fn torque_to_motor(&self, torque: [f64; N]) -> [f64; N] {
+        multiply(&transpose(&self.inverse), torque)
+    }
+
+    /// Joint torques produced by motor torques (in Nm)
+    pub fn torque_to_joint(&self, torque: [f64; N]) -> [f64; N] {
+        multiply(&transpose(&self.matrix), torque)
+    }
+}
+
+fn multiply<const N: usize>(matrix: &[[f64; N]; N], vector: [f64; N]) -> [f64; N] {
+    matrix.map(|row| row.iter().zip(vector).map(|(a, v)| a * v).sum())
+}
+
+fn transpose<const N: usize>(matrix: &[[f64; N]; N]) -> [[f64; N]; N] {
+    let mut transposed = [[0.0; N]; N];
+    for i in 0..N {
+        for j in 0..N {
+            transposed[j][i] = matrix[i][j];
+        }
+    }
+    transposed
+}
+
+/// Gauss-Jordan elimination with partial pivoting (None if the matrix is singular)
+fn invert<const N: usize>(matrix: [[f64; N]; N]) -> Option<[[f64; N]; N]> {
+    let mut a = matrix;
+    let mut inverse = [[0.0; N]; N];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+
+    for col in 0..N {
+        let pivot = (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < SINGULAR_PIVOT {
+            return None;
+        }
+        a.swap(col, pivot);
+        inverse.swap(col, pivot);
+
+        let p = a[col][col];
+        for j in 0..N {
+            a[col][j] /= p;
+            inverse[col][j] /= p;
+        }
+        for row in 0..N {
+            let factor = a[row][col];
+            if row == col || factor == 0.0 {
+                continue;
+            }
+            for j in 0..N {
+                a[row][j] -= factor * a[col][j];
+                inverse[row][j] -= factor * inverse[col][j];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+/// Serialized form of a transmission
+struct TransmissionDef {
+    matrix: Vec<Vec<f64>>,
+    #[serde(default)]
+    offset: Vec<f64>,
+}
+
+impl<const N: usize> TryFrom<TransmissionDef> for Transmission<N> {
+    type Error = InvalidTransmissionError;
+
+    fn try_from(value: TransmissionDef) -> Result<Self, Self::Error> {
+        let invalid = || InvalidTransmissionError(format!("expected a {N}x{N} matrix"));
+
+        let mut matrix = [[0.0; N]; N];
+        if value.matrix.len() != N {
+            return Err(invalid());
+        }
+        for (row, values) in matrix.iter_mut().zip(&value.matrix) {
+            *row = values.as_slice().try_into().map_err(|_| invalid())?;
+        }
+        let offset = match value.offset.len() {
+            0 => [0.0; N],
+            _ => value
+                .offset
+                .as_slice()
+                .try_into()
+                .map_err(|_| InvalidTransmissionError(format!("expected {N} offsets")))?,
+        };
+        Self::new(matrix, offset)
+    }
+}
+
+impl<const N: usize> From<Transmission<N>> for TransmissionDef {
+    fn from(transmission: Transmission<N>) -> Self {
+        Self {
+            matrix: transmission.matrix.iter().map(|row| row.to_vec()).collect(),
+            offset: transmission.offset.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidTransmissionError(pub String);
+impl std::fmt::Display for InvalidTransmissionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid transmission: {reason})")
+    }
+}
+impl std::error::Error for InvalidTransmissionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::Transmission;
+    use crate::test_utils::assert_close;
+
+    fn differential() -> Transmission<2> {
+        Transmission::new([[1.0, 1.0], [1.0, -1.0]], [0.5, 0.0]).unwrap()
+    }
+
+    #[test]
+    fn scalar() {
+        let transmission = Transmission::from_scalar([Some(0.5), None], [Some(2.0), None]).unwrap();
+        assert_eq!(transmission.position_to_motor([1.0, 1.0]), [3.0, 1.0]);
+        assert_eq!(transmission.position_to_joint([3.0, 1.0]), [1.0, 1.0]);
+        assert_eq!(transmission.velocity_to_joint([2.0, 2.0]), [1.0, 2.0]);
+        assert_eq!(transmission.scalar(), Some([(0.5, 2.0), (0.0, 1.0)]));
+
+        assert!(differential().scalar().is_none());
+        assert!(Transmission::from_scalar([None], [Some(0.0)]).is_err());
+    }
+
+    #[test]
+    fn differential_round_trip() {
+        let transmission = differential();
+        let joint = [0.3, -0.2];
+
+        let motor = transmission.position_to_motor(joint);
+        assert_close(motor, [0.6, 0.5], 1e-12);
+        assert_close(transmission.position_to_joint(motor), joint, 1e-12);
+        assert_close(
+            transmission.velocity_to_joint(transmission.velocity_to_motor(joint)),
+            joint,
+            1e-12,
+        );
+        assert_close(
+            transmission.torque_to_joint(transmission.torque_to_motor(joint)),
+            joint,
+            1e-12,
+        );
+
+        // The power is the same on both sides
+        let (velocity, torque) = ([1.0, 2.0], [0.5, -1.5]);
+        let joint_power: f64 = velocity.iter().zip(torque).map(|(v, t)| v * t).sum();
+        let motor_power: f64 = transmission
+            .velocity_to_motor(velocity)
+            .iter()
+            .zip(transmission.torque_to_motor(torque))
+            .map(|(v, t)| v * t)
+            .sum();
+        assert!((joint_power - motor_power).abs() < 1e-12);
+
+        assert!(Transmission::new([[1.0, 1.0], [2.0, 2.0]], [0.0; 2]).is_err());
+    }
+
+    #[test]
+    fn serde() {
+        let transmission: Transmission<2> =
+            serde_json::from_str(r#"{"matrix": [[1.0, 1.0], [1.0, -1.0]], "offset": [0.5, 0.0]}"#)
+                .unwrap();
+        assert_eq!(transmission, differential());
+
+        let json = serde_json::to_string(&transmission).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Transmission<2>>(&json).unwrap(),
+            transmission
+        );
+
+        assert!(serde_json::from_str::<Transmission<2>>(r#"{"matrix": [[1.0, 1.0]]}"#).is_err());
+        assert!(
+            serde_json::from_str::<Transmission<2>>(r#"{"matrix": [[1.0, 1.0], [1.0, 1.0]]}"#)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::f64::consts::{PI, TAU};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Multi-turn tracking of motors reporting positions wrapped to [-π, π)
+///
+/// Positions are unwrapped in motor space (before reduction and offset), by
+/// counting the turns each time the reported position jumps by more than π
+/// between two reads. It assumes the motors never move more than half a turn
+/// between two reads.
+pub struct Unwrapper<const N: usize> {
+    wrapped: [bool; N],
+    turns: [i64; N],
+    last: [Option<f64>; N],
+}
+
+impl<const N: usize> Unwrapper<N> {
+    /// Create an unwrapper for the motors reporting wrapped positions
+    pub fn new(wrapped: [bool; N]) -> Self {
+        Self {
+            wrapped,
+            turns: [0; N],
+            last: [None; N],
+        }
+    }
+
+    /// Motors reporting wrapped positions
+    pub fn wrapped(&self) -> [bool; N] {
+        self.wrapped
+    }
+
+    /// Number of turns counted for each motor
+    pub fn turns(&self) -> [i64; N] {
+        self.turns
+    }
+
+    /// Reset the turn counters (the next read is considered in the first turn)
+    pub fn reset(&mut self) {
+        self.turns = [0; N];
+        self.last = [None; N];
+    }
+
+    /// Convert wrapped current positions into continuous ones, updating the turn counters
+    pub fn unwrap(&mut self, position: [f64; N]) -> [f64; N] {
+        let mut unwrapped = position;
+
+        for i in 0..N {
+            if !self.wrapped[i] || !position[i].is_finite() {
+                continue;
+            }
+            if let Some(last) = self.last[i] {
+                let delta = position[i] - last;
+                if delta > PI {
+                    self.turns[i] -= 1;
+                } else if delta < -PI {
+                    self.turns[i] += 1;
+                }
+            }
+            self.last[i] = Some(position[i]);
+            unwrapped[i] = position[i] + self.turns[i] as f64 * TAU;
+        }
+        log::debug!(target: "unwrapper::unwrap", "turns: {:?}", self.turns);
+
+        unwrapped
+    }
+
+    /// Convert wrapped positions (e.g. targets) into the continuous ones closest to the last read
+    pub fn unwrap_near(&self, position: [f64; N]) -> [f64; N] {
+        let mut unwrapped = position;
+
+        for i in 0..N {
+            if let (true, Some(last)) = (self.wrapped[i], self.last[i]) {
+                let reference = last + self.turns[i] as f64 * TAU;
+                unwrapped[i] = reference + wrap(position[i] - reference);
+            }
+        }
+        unwrapped
+    }
+
+    /// Convert continuous positions back into the wrapped range of the motors
+    pub fn wrap(&self, position: [f64; N]) -> [f64; N] {
+        let mut wrapped = position;
+        for (w, &is_wrapped) in wrapped.iter_mut().zip(&self.wrapped) {
+            if is_wrapped {
+                *w = wrap(*w);
+            }
+        }
+        wrapped
+    }
+}
+
+/// Wrap an angle into [-π, π)
+pub(crate) fn wrap(angle: f64) -> f64 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{PI, TAU};
+
+    use super::Unwrapper;
+    use crate::test_utils::assert_close;
+
+    #[test]
+    fn count_turns() {
+        let mut unwrapper = Unwrapper::new([true, false]);
+
+        assert_close(unwrapper.unwrap([3.0, 3.0]), [3.0, 3.0], 1e-9);
+        assert_close(unwrapper.unwrap([-3.0, -3.0]), [TAU - 3.0, -3.0], 1e-9);
+        assert_eq!(unwrapper.turns(), [1, 0]);
+
+        assert_close(unwrapper.unwrap([0.0, 0.0]), [TAU, 0.0], 1e-9);
+        assert_close(unwrapper.unwrap([3.0, 3.0]), [TAU + 3.0, 3.0], 1e-9);
+        assert_close(unwrapper.unwrap([-3.0, 0.0]), [2.0 * TAU - 3.0, 0.0], 1e-9);
+        assert_eq!(unwrapper.turns(), [2, 0]);
+
+        assert_close(unwrapper.unwrap([3.0, 0.0]), [TAU + 3.0, 0.0], 1e-9);
+        assert_eq!(unwrapper.turns(), [1, 0]);
+
+        unwrapper.reset();
+        assert_eq!(unwrapper.turns(), [0, 0]);
+        assert_close(unwrapper.unwrap([1.0, 1.0]), [1.0, 1.0], 1e-9);
+    }
+
+    #[test]
+    fn wrap_targets() {
+        let mut unwrapper = Unwrapper::new([true, false]);
+
+        assert_close(unwrapper.wrap([PI, PI]), [-PI, PI], 1e-9);
+        assert_close(
+            unwrapper.wrap([TAU + 1.0, TAU + 1.0]),
+            [1.0, TAU + 1.0],
+            1e-9,
+        );
+        assert_close(unwrapper.wrap([-TAU - 1.0, 0.0]), [-1.0, 0.0], 1e-9);
+
+        // Without any read, wrapped positions are kept as is
+        assert_close(unwrapper.unwrap_near([1.0, 1.0]), [1.0, 1.0], 1e-9);
+
+        unwrapper.unwrap([3.0, 0.0]);
+        unwrapper.unwrap([-3.0, 0.0]);
+        assert_close(unwrapper.unwrap_near([-2.5, -2.5]), [TAU - 2.5, -2.5], 1e-9);
+        assert_close(unwrapper.unwrap_near([2.5, 2.5]), [2.5, 2.5], 1e-9);
+    }
+}
@@ -0,0 +1,612 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{
+    EmergencyStop, GainCapabilities, Gains, Limit, MotorsController, RawMotorsIO, Result, PID,
+};
+
+/// Values written by the views during a cycle, with the view that wrote each joint
+struct Pending<T, const N: usize>([Option<(T, usize)>; N]);
+
+impl<T: Copy, const N: usize> Pending<T, N> {
+    fn new() -> Self {
+        Self([None; N])
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(Option::is_none)
+    }
+
+    /// Record the values of a view (None to leave a joint untouched), refusing them all if another view already wrote one of its joints
+    fn write<const M: usize>(
+        &mut self,
+        view: usize,
+        joints: [usize; M],
+        values: [Option<T>; M],
+    ) -> Result<()> {
+        let conflicts: Vec<usize> = joints
+            .into_iter()
+            .zip(&values)
+            .filter(|&(joint, value)| {
+                value.is_some() && matches!(self.0[joint], Some((_, other)) if other != view)
+            })
+            .map(|(joint, _)| joint)
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(Box::new(ViewConflictError(conflicts)));
+        }
+        for (joint, value) in joints.into_iter().zip(values) {
+            if let Some(value) = value {
+                self.0[joint] = Some((value, view));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the values of the joints written during the cycle
+    fn overlay<const M: usize>(&self, joints: [usize; M], values: &mut [T; M]) {
+        for (value, joint) in values.iter_mut().zip(joints) {
+            if let Some((pending, _)) = self.0[joint] {
+                *value = pending;
+            }
+        }
+    }
+
+    /// Values written during the cycle (None for the joints not written)
+    fn values(&self) -> [Option<T>; N] {
+        self.0.map(|pending| pending.map(|(value, _)| value))
+    }
+
+    /// Values of all the joints, from the base for the joints not written during the cycle
+    fn merge(&self, base: [T; N]) -> [T; N] {
+        let mut values = base;
+        for (value, pending) in values.iter_mut().zip(&self.0) {
+            if let Some((pending, _)) = pending {
+                *value = *pending;
+            }
+        }
+        values
+    }
+}
+
+/// Writes of the views waiting for the next flush
+struct PendingWrites<const N: usize> {
+    control_mode: Pending<u8, N>,
+    position: Pending<f64, N>,
+    velocity: Pending<f64, N>,
+    torque: Pending<f64, N>,
+    torque_on: Pending<bool, N>,
+}
+
+impl<const N: usize> PendingWrites<N> {
+    fn new() -> Self {
+        Self {
+            control_mode: Pending::new(),
+            position: Pending::new(),
+            velocity: Pending::new(),
+            torque: Pending::new(),
+            torque_on: Pending::new(),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn select<T: Copy, const N: usize, const M: usize>(values: [T; N], joints: [usize; M]) -> [T; M] {
+    joints.map(|joint| values[joint])
+}
+
+fn replace<T: Copy, const N: usize, const M: usize>(
+    values: &mut [T; N],
+    joints: [usize; M],
+    subset: [T; M],
+) {
+    for (joint, value) in joints.into_iter().zip(subset) {
+        values[joint] = value;
+    }
+}
+
+/// Controller split into views over subsets of its joints
+///
+/// Each [view](Self::view) is a [MotorsController] of its own, that can be
+/// handed to a different module (or thread). Target positions, velocities and
+/// torques, control modes and torque enables written through the views are
+/// buffered, then sent to the controller by [flush](Self::flush) as a single
+/// transaction per register, once per cycle. Within a cycle, a view writing a
+/// joint already written by another view is refused with a [ViewConflictError].
+///
+/// Readings, limits and gains go to the controller right away.
+pub struct SplitController<C, const N: usize>
+where
+    C: MotorsController<N>,
+{
+    controller: Arc<Mutex<C>>,
+    pending: Arc<Mutex<PendingWrites<N>>>,
+    views: usize,
+}
+
+impl<C, const N: usize> SplitController<C, N>
+where
+    C: MotorsController<N>,
+{
+    pub fn new(controller: C) -> Self {
+        Self {
+            controller: Arc::new(Mutex::new(controller)),
+            pending: Arc::new(Mutex::new(PendingWrites::new())),
+            views: 0,
+        }
+    }
+
+    /// Create a view over joints of the controller, in the given order
+    pub fn view<const M: usize>(
+        &mut self,
+        joints: [usize; M],
+    ) -> std::result::Result<ControllerView<C, N, M>, InvalidViewError> {
+        for (i, &joint) in joints.iter().enumerate() {
+            if joint >= N {
+                return Err(InvalidViewError(format!("joint {joint} out of {N}")));
+            }
+            if joints[..i].contains(&joint) {
+                return Err(InvalidViewError(format!("joint {joint} repeated")));
+            }
+        }
+        self.views += 1;
+
+        let controller = self.lock();
+        let limits = select(controller.limits(), joints);
+        let emergency_stop = controller.emergency_stop_latch().cloned();
+        drop(controller);
+
+        Ok(ControllerView {
+            io: ViewIO {
+                id: self.views,
+                joints,
+                controller: self.controller.clone(),
+                pending: self.pending.clone(),
+            },
+            limits,
+            emergency_stop,
+        })
+    }
+
+    /// Lock the controller (e.g. to send commands to all the joints)
+    pub fn lock(&self) -> MutexGuard<'_, C> {
+        lock(&self.controller)
+    }
+
+    /// Send the writes of the views since the last flush
+    ///
+    /// Targets are only sent for the joints written by a view (see
+    /// [MotorsController::set_target_position_partial]), the other joints keep
+    /// their current targets, modes and torque. The writes are dropped if the
+    /// flush fails.
+    pub fn flush(&self) -> Result<()> {
+        let mut controller = self.lock();
+        let pending = std::mem::replace(&mut *lock(&self.pending), PendingWrites::new());
+
+        if !pending.control_mode.is_empty() {
+            let mode = pending.control_mode.merge(controller.get_control_mode()?);
+            controller.set_control_mode(mode)?;
+        }
+        if !pending.position.is_empty() {
+            let position = pending.position.values();
+            log::debug!(target: "split_controller::flush", "target_position: {:?}", position);
+            controller.set_target_position_partial(position)?;
+        }
+        if !pending.velocity.is_empty() {
+            let velocity = pending.velocity.values();
+            log::debug!(target: "split_controller::flush", "target_velocity: {:?}", velocity);
+            controller.set_target_velocity_partial(velocity)?;
+        }
+        if !pending.torque.is_empty() {
+            let torque = pending.torque.values();
+            log::debug!(target: "split_controller::flush", "target_torque: {:?}", torque);
+            controller.set_target_torque_partial(torque)?;
+        }
+        // Torque last, so that joints are enabled on their new targets
+        if !pending.torque_on.is_empty() {
+            let on = pending.torque_on.merge(controller.is_torque_on()?);
+            controller.set_torque(on)?;
+        }
+        Ok(())
+    }
+}
+
+/// IO of a view, buffering its writes until the split controller is flushed
+pub struct ViewIO<C, const N: usize, const M: usize>
+where
+    C: MotorsController<N>,
+{
+    id: usize,
+    joints: [usize; M],
+    controller: Arc<Mutex<C>>,
+    pending: Arc<Mutex<PendingWrites<N>>>,
+}
+
+impl<C, const N: usize, const M: usize> ViewIO<C, N, M>
+where
+    C: MotorsController<N>,
+{
+    /// Joints of the controller seen by the view
+    pub fn joints(&self) -> [usize; M] {
+        self.joints
+    }
+
+    fn controller(&self) -> MutexGuard<'_, C> {
+        lock(&self.controller)
+    }
+
+    fn pending(&self) -> MutexGuard<'_, PendingWrites<N>> {
+        lock(&self.pending)
+    }
+
+    fn read<T: Copy>(&self, read: impl FnOnce(&mut C) -> Result<[T; N]>) -> Result<[T; M]> {
+        Ok(select(read(&mut self.controller())?, self.joints))
+    }
+
+    /// Write the joints of the view right away, keeping the values of the other joints
+    fn read_modify_write<T: Copy>(
+        &self,
+        values: [T; M],
+        read: impl FnOnce(&mut C) -> Result<[T; N]>,
+        write: impl FnOnce(&mut C, [T; N]) -> Result<()>,
+    ) -> Result<()> {
+        let mut controller = self.controller();
+        let mut all = read(&mut controller)?;
+        replace(&mut all, self.joints, values);
+        write(&mut controller, all)
+    }
+}
+
+impl<C, const N: usize, const M: usize> RawMotorsIO<M> for ViewIO<C, N, M>
+where
+    C: MotorsController<N>,
+{
+    fn is_torque_on(&mut self) -> Result<[bool; M]> {
+        self.read(|c| c.is_torque_on())
+    }
+
+    fn set_torque(&mut self, on: [bool; M]) -> Result<()> {
+        self.pending()
+            .torque_on
+            .write(self.id, self.joints, on.map(Some))
+    }
+
+    fn get_current_position(&mut self) -> Result<[f64; M]> {
+        self.read(|c| c.get_current_position())
+    }
+
+    fn get_current_velocity(&mut self) -> Result<[f64; M]> {
+        self.read(|c| c.get_current_velocity())
+    }
+
+    fn get_current_torque(&mut self) -> Result<[f64; M]> {
+        self.read(|c| c.get_current_torque())
+    }
+
+    fn get_target_position(&mut self) -> Result<[f64; M]> {
+        let mut position = self.read(|c| c.get_target_position())?;
+        self.pending().position.overlay(self.joints, &mut position);
+        Ok(position)
+    }
+
+    fn set_target_position(&mut self, position: [f64; M]) -> Result<()> {
+        self.set_target_position_partial(position.map(Some))
+    }
+
+    fn set_target_position_partial(&mut self, position: [Option<f64>; M]) -> Result<()> {
+        self.pending()
+            .position
+            .write(self.id, self.joints, position)
+    }
+
+    fn get_target_torque(&mut self) -> Result<[f64; M]> {
+        let mut torque = self.read(|c| c.get_target_torque())?;
+        self.pending().torque.overlay(self.joints, &mut torque);
+        Ok(torque)
+    }
+
+    fn set_target_torque(&mut self, torque: [f64; M]) -> Result<()> {
+        self.set_target_torque_partial(torque.map(Some))
+    }
+
+    fn set_target_torque_partial(&mut self, torque: [Option<f64>; M]) -> Result<()> {
+        self.pending().torque.write(self.id, self.joints, torque)
+    }
+
+    fn set_target_velocity(&mut self, velocity: [f64; M]) -> Result<()> {
+        self.set_target_velocity_partial(velocity.map(Some))
+    }
+
+    fn set_target_velocity_partial(&mut self, velocity: [Option<f64>; M]) -> Result<()> {
+        self.pending()
+            .velocity
+            .write(self.id, self.joints, velocity)
+    }
+
+    fn get_target_velocity(&mut self) -> Result<[f64; M]> {
+        let mut velocity = self.read(|c| c.get_target_velocity())?;
+        self.pending().velocity.overlay(self.joints, &mut velocity);
+        Ok(velocity)
+    }
+
+    fn set_control_mode(&mut self, mode: [u8; M]) -> Result<()> {
+        self.pending()
+            .control_mode
+            .write(self.id, self.joints, mode.map(Some))
+    }
+
+    fn get_control_mode(&mut self) -> Result<[u8; M]> {
+        let mut mode = self.read(|c| c.get_control_mode())?;
+        self.pending().control_mode.overlay(self.joints, &mut mode);
+        Ok(mode)
+    }
+
+    /// Buffer the target positions and return the current positions (the write happens on flush)
+    fn set_target_position_fb(&mut self, position: [f64; M]) -> Result<[f64; M]> {
+        self.set_target_position(position)?;
+        self.get_current_position()
+    }
+
+    fn get_velocity_limit(&mut self) -> Result<[f64; M]> {
+        self.read(|c| c.get_velocity_limit())
+    }
+
+    fn set_velocity_limit(&mut self, velocity: [f64; M]) -> Result<()> {
+        self.read_modify_write(
+            velocity,
+            |c| c.get_velocity_limit(),
+            |c, velocity| c.set_velocity_limit(velocity),
+        )
+    }
+
+    fn get_torque_limit(&mut self) -> Result<[f64; M]> {
+        self.read(|c| c.get_torque_limit())
+    }
+
+    fn set_torque_limit(&mut self, torque: [f64; M]) -> Result<()> {
+        self.read_modify_write(
+            torque,
+            |c| c.get_torque_limit(),
+            |c, torque| c.set_torque_limit(torque),
+        )
+    }
+
+    fn get_pid_gains(&mut self) -> Result<[PID; M]> {
+        self.read(|c| c.get_pid_gains())
+    }
+
+    fn set_pid_gains(&mut self, pid: [PID; M]) -> Result<()> {
+        self.read_modify_write(pid, |c| c.get_pid_gains(), |c, pid| c.set_pid_gains(pid))
+    }
+
+    fn gain_capabilities(&self) -> GainCapabilities {
+        self.controller().gain_capabilities()
+    }
+
+    fn get_gains(&mut self) -> Result<[Gains; M]> {
+        self.read(|c| c.get_gains())
+    }
+
+    fn set_gains(&mut self, gains: [Gains; M]) -> Result<()> {
+        // Missing fields are not changed, the other joints need no value
+        let mut all = [Gains::default(); N];
+        replace(&mut all, self.joints, gains);
+        self.controller().set_gains(all)
+    }
+
+    fn get_axis_sensors(&mut self) -> Result<[f64; M]> {
+        self.read(|c| c.get_axis_sensors())
+    }
+
+    fn get_board_state(&mut self) -> Result<u8> {
+        self.controller().get_board_state()
+    }
+
+    fn set_board_state(&mut self, state: u8) -> Result<()> {
+        self.controller().set_board_state(state)
+    }
+}
+
+/// View over a subset of the joints of a [SplitController]
+///
+/// Offsets, reductions and limits are applied by the split controller, the
+/// view reports its limits and shares its emergency stop latch. Impedance
+/// commands are computed in software and sent as target torques.
+pub struct ControllerView<C, const N: usize, const M: usize>
+where
+    C: MotorsController<N>,
+{
+    io: ViewIO<C, N, M>,
+    limits: [Option<Limit>; M],
+    emergency_stop: Option<EmergencyStop>,
+}
+
+impl<C, const N: usize, const M: usize> ControllerView<C, N, M>
+where
+    C: MotorsController<N>,
+{
+    /// Joints of the controller seen by the view
+    pub fn joints(&self) -> [usize; M] {
+        self.io.joints()
+    }
+}
+
+impl<C, const N: usize, const M: usize> MotorsController<M> for ControllerView<C, N, M>
+where
+    C: MotorsController<N>,
+{
+    fn io(&mut self) -> &mut dyn RawMotorsIO<M> {
+        &mut self.io
+    }
+
+    fn offsets(&self) -> [Option<f64>; M] {
+        [None; M]
+    }
+
+    fn reduction(&self) -> [Option<f64>; M] {
+        [None; M]
+    }
+
+    fn limits(&self) -> [Option<Limit>; M] {
+        self.limits
+    }
+
+    fn emergency_stop_latch(&self) -> Option<&EmergencyStop> {
+        self.emergency_stop.as_ref()
+    }
+
+    /// Stop the whole controller right away (not only the joints of the view)
+    fn emergency_stop(&mut self) -> Result<()> {
+        self.io.controller().emergency_stop()
+    }
+
+    fn reset_emergency_stop(&mut self) -> Result<()> {
+        self.io.controller().reset_emergency_stop()
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidViewError(pub String);
+impl std::fmt::Display for InvalidViewError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = &self.0;
+        write!(f, "(invalid view: {reason})")
+    }
+}
+impl std::error::Error for InvalidViewError {}
+
+#[derive(Debug)]
+/// Joints already written by another view during the cycle
+pub struct ViewConflictError(pub Vec<usize>);
+impl std::fmt::Display for ViewConflictError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let joints = &self.0;
+        write!(f, "(joints {joints:?} already written by another view)")
+    }
+}
+impl std::error::Error for ViewConflictError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{SplitController, ViewConflictError};
+    use crate::{FakeMotorsController, Limit, MotorsController};
+
+    fn split() -> SplitController<FakeMotorsController<4>, 4> {
+        SplitController::new(
+            FakeMotorsController::new()
+                .with_reduction([Some(2.0), None, None, None])
+                .with_limits([None, Some(Limit::new(-1.0, 1.0)), None, None]),
+        )
+    }
+
+    #[test]
+    fn merged_writes() {
+        let mut split = split();
+        let mut left = split.view([0, 1]).unwrap();
+        let mut right = split.view([3, 2]).unwrap();
+        assert_eq!(left.limits(), [None, Some(Limit::new(-1.0, 1.0))]);
+
+        left.set_torque([true; 2]).unwrap();
+        right.set_torque([true; 2]).unwrap();
+        left.set_target_position([0.5, 2.0]).unwrap();
+        right.set_target_position([0.4, 0.3]).unwrap();
+
+        // Nothing is sent before the flush, but the views see their targets
+        assert_eq!(split.lock().get_target_position().unwrap(), [0.0; 4]);
+        assert_eq!(left.get_target_position().unwrap(), [0.5, 1.0]);
+
+        split.flush().unwrap();
+        assert_eq!(split.lock().is_torque_on().unwrap(), [true; 4]);
+        assert_eq!(
+            split.lock().get_current_position().unwrap(),
+            [0.5, 1.0, 0.3, 0.4]
+        );
+        assert_eq!(split.lock().io().get_target_position().unwrap()[0], 1.0);
+        assert_eq!(right.get_current_position().unwrap(), [0.4, 0.3]);
+
+        // Joints not written keep their targets
+        right.set_target_position([0.0, 0.0]).unwrap();
+        split.flush().unwrap();
+        assert_eq!(
+            split.lock().get_target_position().unwrap(),
+            [0.5, 1.0, 0.0, 0.0]
+        );
+
+        // Their targets are not read back and sent again
+        split
+            .lock()
+            .io()
+            .set_target_position([1.0, 5.0, 0.0, 0.0])
+            .unwrap();
+        right.set_target_position([0.1, 0.1]).unwrap();
+        split.flush().unwrap();
+        assert_eq!(
+            split.lock().io().get_target_position().unwrap(),
+            [1.0, 5.0, 0.1, 0.1]
+        );
+
+        // Partial writes only claim the joints they write
+        left.set_target_velocity_partial([None, Some(0.5)]).unwrap();
+        right.set_target_velocity([0.2, 0.3]).unwrap();
+        split.flush().unwrap();
+        let velocity = split.lock().get_target_velocity().unwrap();
+        assert!(velocity[0].is_nan());
+        assert_eq!(velocity[1..], [0.5, 0.3, 0.2]);
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut split = split();
+        let mut left = split.view([0, 1]).unwrap();
+        let mut middle = split.view([1, 2]).unwrap();
+        split.lock().set_torque([true; 4]).unwrap();
+
+        left.set_target_position([0.5, 0.5]).unwrap();
+        let error = middle.set_target_position([0.2, 0.2]).unwrap_err();
+        assert_eq!(error.downcast_ref::<ViewConflictError>().unwrap().0, [1]);
+        // A view can overwrite its own writes, and other registers are independent
+        left.set_target_position([0.6, 0.6]).unwrap();
+        middle.set_target_velocity([0.0, 0.0]).unwrap();
+
+        split.flush().unwrap();
+        assert_eq!(
+            split.lock().get_target_position().unwrap(),
+            [0.6, 0.6, 0.0, 0.0]
+        );
+
+        // Conflicts only last for a cycle
+        middle.set_target_position([0.2, 0.2]).unwrap();
+        split.flush().unwrap();
+        assert_eq!(
+            split.lock().get_target_position().unwrap(),
+            [0.6, 0.2, 0.2, 0.0]
+        );
+    }
+
+    #[test]
+    fn emergency_stop() {
+        let mut split = split();
+        let mut left = split.view([0, 1]).unwrap();
+        let mut right = split.view([2, 3]).unwrap();
+        split.lock().set_torque([true; 4]).unwrap();
+
+        left.emergency_stop().unwrap();
+        assert_eq!(split.lock().is_torque_on().unwrap(), [false; 4]);
+        assert!(right.is_emergency_stopped());
+        assert!(right.set_target_position([0.0; 2]).is_err());
+
+        right.reset_emergency_stop().unwrap();
+        assert!(!left.is_emergency_stopped());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut split = split();
+        assert!(split.view([0, 4]).is_err());
+        assert!(split.view([1, 1]).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::motors_controller::delegate_motors_controller;
+use crate::{ImpedanceCommand, MotorsController, Result, TorqueRamp, TrajectoryStreamer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Reaction of the watchdog when commands stop
+pub enum WatchdogReaction<const N: usize> {
+    /// Hold the current position
+    Hold,
+    /// Turn the torque off
+    TorqueOff,
+    /// Smoothly move to a safe pose (in radians)
+    SafePose {
+        pose: [f64; N],
+        duration: Duration,
+        period: Duration,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// State of the watchdog
+pub enum WatchdogState {
+    /// Commands are forwarded to the controller
+    Armed,
+    /// Commands stopped for too long, the reaction has been run and commands are refused
+    Triggered,
+}
+
+#[derive(Debug)]
+pub struct WatchdogTriggeredError;
+impl std::fmt::Display for WatchdogTriggeredError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(watchdog triggered, re-arm required)")
+    }
+}
+impl std::error::Error for WatchdogTriggeredError {}
+
+/// Controller wrapper reacting when no command has been sent for too long
+///
+/// Every motion command (targets, control mode, torque enable) feeds the
+/// watchdog. [check](Self::check) must be called periodically (e.g. from the io
+/// loop or with [spawn_monitor]) to detect the timeout. Once triggered, all
+/// motion commands are refused until [rearm](Self::rearm) is called, while the
+/// other methods (e.g. emergency stop, torque off) are forwarded as is.
+pub struct Watchdog<C, const N: usize>
+where
+    C: MotorsController<N>,
+{
+    inner: C,
+    timeout: Duration,
+    reaction: WatchdogReaction<N>,
+
+    last_command: Instant,
+    state: WatchdogState,
+}
+
+impl<C, const N: usize> Watchdog<C, N>
+where
+    C: MotorsController<N>,
+{
+    pub fn new(controller: C, timeout: Duration, reaction: WatchdogReaction<N>) -> Self {
+        Self {
+            inner: controller,
+            timeout,
+            reaction,
+
+            last_command: Instant::now(),
+            state: WatchdogState::Armed,
+        }
+    }
+
+    /// Wrapped controller
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Wrapped controller (commands sent through it bypass the watchdog)
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Current state of the watchdog
+    pub fn state(&self) -> WatchdogState {
+        self.state
+    }
+
+    /// Time elapsed since the last command
+    pub fn elapsed(&self) -> Duration {
+        self.last_command.elapsed()
+    }
+
+    /// Keep the watchdog alive without sending a command
+    pub fn feed(&mut self) {
+        self.last_command = Instant::now();
+    }
+
+    /// Re-arm a triggered watchdog
+    pub fn rearm(&mut self) {
+        log::info!(target: "watchdog::rearm", "watchdog re-armed");
+        self.state = WatchdogState::Armed;
+        self.feed();
+    }
+
+    /// Check the time since the last command and run the reaction on timeout
+    pub fn check(&mut self) -> Result<WatchdogState> {
+        if self.state == WatchdogState::Armed && self.elapsed() > self.timeout {
+            log::warn!(target: "watchdog::check", "no command for {:?}, running {:?}", self.elapsed(), self.reaction);
+            self.state = WatchdogState::Triggered;
+            self.react()?;
+        }
+        Ok(self.state)
+    }
+
+    fn react(&mut self) -> Result<()> {
+        match self.reaction {
+            WatchdogReaction::Hold => {
+                let position = self.inner.io().get_current_position()?;
+                self.inner.io().set_target_position(position)
+            }
+            WatchdogReaction::TorqueOff => self.inner.set_torque([false; N]),
+            WatchdogReaction::SafePose {
+                pose,
+                duration,
+                period,
+            } => {
+                let current = self.inner.get_current_position()?;
+                TrajectoryStreamer::point_to_point(current, pose, duration)?.run(
+                    &mut self.inner,
+                    period,
+                    duration * 2,
+                )?;
+                Ok(())
+            }
+        }
+    }
+
+    fn command(&mut self) -> Result<()> {
+        if self.check()? == WatchdogState::Triggered {
+            return Err(Box::new(WatchdogTriggeredError));
+        }
+        self.feed();
+        Ok(())
+    }
+}
+
+impl<C, const N: usize> MotorsController<N> for Watchdog<C, N>
+where
+    C: MotorsController<N>,
+{
+    delegate_motors_controller!(inner, N);
+
+    fn set_torque(&mut self, on: [bool; N]) -> Result<()> {
+        if on.iter().any(|&on| on) {
+            self.command()?;
+        }
+        self.inner.set_torque(on)
+    }
+
+    fn safe_enable_torque(&mut self, ramp: Option<TorqueRamp>) -> Result<()> {
+        self.command()?;
+        self.inner.safe_enable_torque(ramp)
+    }
+
+    fn set_control_mode(&mut self, mode: [u8; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_control_mode(mode)
+    }
+
+    fn set_target_position(&mut self, position: [f64; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_target_position(position)
+    }
+
+    fn set_target_position_partial(&mut self, position: [Option<f64>; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_target_position_partial(position)
+    }
+
+    fn set_target_position_fb(&mut self, position: [f64; N]) -> Result<[f64; N]> {
+        self.command()?;
+        self.inner.set_target_position_fb(position)
+    }
+
+    fn set_target_velocity(&mut self, velocity: [f64; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_target_velocity(velocity)
+    }
+
+    fn set_target_velocity_partial(&mut self, velocity: [Option<f64>; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_target_velocity_partial(velocity)
+    }
+
+    fn set_target_torque(&mut self, torque: [f64; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_target_torque(torque)
+    }
+
+    fn set_target_torque_partial(&mut self, torque: [Option<f64>; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_target_torque_partial(torque)
+    }
+
+    fn set_impedance_command(&mut self, command: [ImpedanceCommand; N]) -> Result<()> {
+        self.command()?;
+        self.inner.set_impedance_command(command)
+    }
+
+    fn update_pid_controllers(&mut self, position: [f64; N], dt: f64) -> Result<[Option<f64>; N]> {
+        self.command()?;
+        self.inner.update_pid_controllers(position, dt)
+    }
+}
+
+/// Check a shared watchdog every `period` on a dedicated thread
+///
+/// The thread stops when it holds the last reference to the watchdog.
+pub fn spawn_monitor<C, const N: usize>(
+    watchdog: Arc<Mutex<Watchdog<C, N>>>,
+    period: Duration,
+) -> JoinHandle<()>
+where
+    C: MotorsController<N> + Send + 'static,
+{
+    std::thread::spawn(move || {
+        while Arc::strong_count(&watchdog) > 1 {
+            let res = watchdog
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .check();
+            if let Err(e) = res {
+                log::error!(target: "watchdog::monitor", "reaction failed: {}", e);
+            }
+            std::thread::sleep(period);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{spawn_monitor, Watchdog, WatchdogReaction, WatchdogState};
+    use crate::test_utils::enabled_motors;
+    use crate::{CompositeController, CompositePart, MotorsController};
+
+    #[test]
+    fn fed_by_commands() {
+        let mut watchdog = Watchdog::new(
+            enabled_motors([1.0]),
+            Duration::from_secs(10),
+            WatchdogReaction::TorqueOff,
+        );
+
+        watchdog.set_target_position([0.5]).unwrap();
+        assert!(watchdog.elapsed() < Duration::from_secs(1));
+        assert_eq!(watchdog.check().unwrap(), WatchdogState::Armed);
+        assert_eq!(watchdog.get_current_position().unwrap(), [0.5]);
+    }
+
+    #[test]
+    fn hold() {
+        let mut watchdog = Watchdog::new(
+            enabled_motors([1.0]),
+            Duration::from_millis(10),
+            WatchdogReaction::Hold,
+        );
+        watchdog.inner_mut().fake_io().set_current_position([0.5]);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(watchdog.check().unwrap(), WatchdogState::Triggered);
+        assert_eq!(watchdog.get_target_position().unwrap(), [0.5]);
+        assert_eq!(watchdog.is_torque_on().unwrap(), [true]);
+
+        assert!(watchdog.set_target_position([0.0]).is_err());
+        assert!(watchdog.set_target_velocity([0.0]).is_err());
+        assert_eq!(watchdog.get_current_position().unwrap(), [0.5]);
+
+        watchdog.rearm();
+        assert_eq!(watchdog.state(), WatchdogState::Armed);
+        watchdog.set_target_position([0.0]).unwrap();
+        assert_eq!(watchdog.get_current_position().unwrap(), [0.0]);
+    }
+
+    #[test]
+    fn stale_command() {
+        let mut watchdog = Watchdog::new(
+            enabled_motors([1.0]),
+            Duration::from_millis(10),
+            WatchdogReaction::TorqueOff,
+        );
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(watchdog.set_target_position([0.0]).is_err());
+        assert_eq!(watchdog.state(), WatchdogState::Triggered);
+        assert_eq!(watchdog.is_torque_on().unwrap(), [false]);
+    }
+
+    #[test]
+    fn torque_enable_refused() {
+        let mut watchdog = Watchdog::new(
+            enabled_motors([1.0]),
+            Duration::from_millis(10),
+            WatchdogReaction::TorqueOff,
+        );
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(watchdog.check().unwrap(), WatchdogState::Triggered);
+        assert!(watchdog.set_torque([true]).is_err());
+        assert!(watchdog.safe_enable_torque(None).is_err());
+        assert!(watchdog.set_control_mode([1]).is_err());
+        watchdog.set_torque([false]).unwrap();
+        assert_eq!(watchdog.is_torque_on().unwrap(), [false]);
+
+        watchdog.rearm();
+        watchdog.safe_enable_torque(None).unwrap();
+        assert_eq!(watchdog.is_torque_on().unwrap(), [true]);
+    }
+
+    #[test]
+    fn forward_overrides() {
+        let composite = CompositeController::<1>::new(vec![CompositePart::from_controller(
+            "arm",
+            enabled_motors([1.0]),
+        )])
+        .unwrap();
+        let mut watchdog =
+            Watchdog::new(composite, Duration::from_secs(10), WatchdogReaction::Hold);
+
+        // The composite latches its parts, and releases them on reset
+        watchdog.emergency_stop().unwrap();
+        assert!(watchdog.set_target_position([0.0]).is_err());
+        watchdog.reset_emergency_stop().unwrap();
+        watchdog.set_torque([true]).unwrap();
+        watchdog.set_target_position([0.5]).unwrap();
+        assert_eq!(watchdog.get_current_position().unwrap(), [0.5]);
+    }
+
+    #[test]
+    fn safe_pose() {
+        let mut watchdog = Watchdog::new(
+            enabled_motors([1.0]),
+            Duration::from_millis(10),
+            WatchdogReaction::SafePose {
+                pose: [-1.0],
+                duration: Duration::from_millis(10),
+                period: Duration::from_millis(1),
+            },
+        );
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(watchdog.check().unwrap(), WatchdogState::Triggered);
+        assert_eq!(watchdog.get_current_position().unwrap(), [-1.0]);
+    }
+
+    #[test]
+    fn monitor() {
+        let watchdog = Arc::new(Mutex::new(Watchdog::new(
+            enabled_motors([1.0]),
+            Duration::from_millis(10),
+            WatchdogReaction::TorqueOff,
+        )));
+        let monitor = spawn_monitor(watchdog.clone(), Duration::from_millis(1));
+
+        std::thread::sleep(Duration::from_millis(50));
+        {
+            let mut watchdog = watchdog.lock().unwrap();
+            assert_eq!(watchdog.state(), WatchdogState::Triggered);
+            assert_eq!(watchdog.is_torque_on().unwrap(), [false]);
+        }
+
+        drop(watchdog);
+        monitor.join().unwrap();
+    }
+}